@@ -0,0 +1,138 @@
+//! AVI 1.0(RIFF) writer for a single video stream.
+
+///Chunk id of the frames of stream 0
+const CHUNK_ID: &[u8; 4] = b"00dc";
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+///Parameters of the video stream
+#[derive(Debug, Clone)]
+pub struct AviVideo {
+    pub fourcc: [u8; 4],
+    pub width: u32,
+    pub height: u32,
+    ///Frame rate is rate/scale
+    pub rate: u32,
+    pub scale: u32,
+    ///Codec specific bytes after the BITMAPINFOHEADER
+    pub extradata: Vec<u8>,
+}
+
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8 + data.len() + 1);
+    bytes.extend_from_slice(id);
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    bytes
+}
+
+fn list(list_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut bytes = b"LIST".to_vec();
+    bytes.extend_from_slice(&(4 + data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(list_type);
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+fn u32_fields(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+///A frame chunk of the movi list,padded to an even size
+pub fn frame_chunk(data: &[u8]) -> Vec<u8> {
+    chunk(CHUNK_ID, data)
+}
+
+///Size of the movi list data holding frames of the given sizes
+fn movi_size(frames: &[(u32, bool)]) -> u64 {
+    4 + frames
+        .iter()
+        .map(|(size, _)| 8 + *size as u64 + (*size as u64 % 2))
+        .sum::<u64>()
+}
+
+///Size of the whole file,AVI 1.0 can't exceed 4GB
+pub fn file_size(video: &AviVideo, frames: &[(u32, bool)]) -> u64 {
+    headers(video, frames).len() as u64 + movi_size(frames) - 4 + 8 + 16 * frames.len() as u64
+}
+
+///File header up to the movi list header,frames are (size,keyframe)
+pub fn headers(video: &AviVideo, frames: &[(u32, bool)]) -> Vec<u8> {
+    let count = frames.len() as u32;
+    let max_size = frames.iter().map(|f| f.0).max().unwrap_or(0);
+    let micro_sec_per_frame =
+        (1_000_000_u64 * video.scale as u64 / video.rate.max(1) as u64) as u32;
+    let avih = u32_fields(&[
+        micro_sec_per_frame,
+        0,
+        0,
+        AVIF_HASINDEX,
+        count,
+        0,
+        1,
+        max_size,
+        video.width,
+        video.height,
+        0,
+        0,
+        0,
+        0,
+    ]);
+    let mut strh = b"vids".to_vec();
+    strh.extend_from_slice(&video.fourcc);
+    strh.extend_from_slice(&u32_fields(&[
+        0,
+        0,
+        0,
+        video.scale,
+        video.rate,
+        0,
+        count,
+        max_size,
+        u32::MAX,
+        0,
+    ]));
+    for value in [0, 0, video.width as u16, video.height as u16] {
+        strh.extend_from_slice(&value.to_le_bytes());
+    }
+    let mut strf = u32_fields(&[40 + video.extradata.len() as u32, video.width, video.height]);
+    strf.extend_from_slice(&1_u16.to_le_bytes());
+    strf.extend_from_slice(&24_u16.to_le_bytes());
+    strf.extend_from_slice(&video.fourcc);
+    strf.extend_from_slice(&u32_fields(&[video.width * video.height * 3, 0, 0, 0, 0]));
+    strf.extend_from_slice(&video.extradata);
+
+    let mut strl = chunk(b"strh", &strh);
+    strl.extend_from_slice(&chunk(b"strf", &strf));
+    let mut hdrl = chunk(b"avih", &avih);
+    hdrl.extend_from_slice(&list(b"strl", &strl));
+    let hdrl = list(b"hdrl", &hdrl);
+
+    let movi_size = movi_size(frames);
+    let riff_size = 4 + hdrl.len() as u64 + 8 + movi_size + 8 + 16 * frames.len() as u64;
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&(riff_size as u32).to_le_bytes());
+    bytes.extend_from_slice(b"AVI ");
+    bytes.extend_from_slice(&hdrl);
+    bytes.extend_from_slice(b"LIST");
+    bytes.extend_from_slice(&(movi_size as u32).to_le_bytes());
+    bytes.extend_from_slice(b"movi");
+    bytes
+}
+
+///The idx1 index following the movi list
+pub fn index(frames: &[(u32, bool)]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(16 * frames.len());
+    //offsets are relative to the movi list type
+    let mut offset = 4_u32;
+    for (size, keyframe) in frames {
+        data.extend_from_slice(CHUNK_ID);
+        let flags = if *keyframe { AVIIF_KEYFRAME } else { 0 };
+        data.extend_from_slice(&u32_fields(&[flags, offset, *size]));
+        offset += 8 + size + size % 2;
+    }
+    chunk(b"idx1", &data)
+}
@@ -0,0 +1,213 @@
+//! `concat` command: join flv files,each continuing the timestamps of the previous one.
+
+use crate::media::{invalid_data, CodecConfig, Demuxer, MediaEvent, Metadata, Span, TrackKind};
+use crate::open_output;
+use flv_parser::flv::{Header, ParseResult, Reader, Tag, Writer};
+use std::{
+    fs::File,
+    io::{self, BufReader},
+};
+
+///Call f with every tag of an flv file,returns the size of a truncated tail
+fn for_each_tag(path: &str, mut f: impl FnMut(Tag) -> io::Result<()>) -> io::Result<usize> {
+    let mut reader = Reader::new(BufReader::new(File::open(path)?));
+    while let Some(result) = reader.read_next()? {
+        if let ParseResult::Tag(tag) = result {
+            f(tag)?;
+        }
+    }
+    Ok(reader.pending())
+}
+
+///Decide which tags are copied:script tags and end of sequence tags are dropped,
+///so are sequence headers repeating the current one of their stream
+#[derive(Debug, Default)]
+struct TagFilter {
+    sequence_headers: [Option<Vec<u8>>; 2],
+}
+
+impl TagFilter {
+    fn keep(&mut self, tag: &Tag) -> bool {
+        let kind = match TrackKind::of(tag) {
+            Some(kind) => kind,
+            None => return false,
+        };
+        if kind == TrackKind::Video && tag.packet_type() == Some(Tag::PACKET_TYPE_END_OF_SEQUENCE) {
+            return false;
+        }
+        if !tag.is_sequence_header() {
+            return true;
+        }
+        let current = &mut self.sequence_headers[kind.index()];
+        if current.as_deref() == Some(tag.body()) {
+            return false;
+        }
+        *current = Some(tag.body().to_vec());
+        true
+    }
+}
+
+///What the first pass learned about all inputs
+#[derive(Default)]
+struct Plan {
+    ///Spans of the video and audio streams of every input
+    spans: Vec<[Span; 2]>,
+    ///Codec id of video tags and sound format of audio tags
+    codecs: [Option<u8>; 2],
+    configs: [Option<CodecConfig>; 2],
+    ///Sample rate and channels of the first audio tag
+    sound: Option<(u32, u8)>,
+    ///Size of the copied tags with their previous tag sizes
+    tags_size: u64,
+}
+
+fn scan(inputs: &[&str]) -> io::Result<Plan> {
+    let mut plan = Plan::default();
+    let mut filter = TagFilter::default();
+    let mut demuxer = Demuxer::new();
+    let mut codec_sources = [""; 2];
+    for path in inputs {
+        let mut spans = [Span::default(); 2];
+        let truncated = for_each_tag(path, |tag| {
+            let kind = match TrackKind::of(&tag) {
+                Some(kind) => kind,
+                None => return Ok(()),
+            };
+            let index = kind.index();
+            let codec = match kind {
+                TrackKind::Video => tag.video_codec_id(),
+                TrackKind::Audio => tag.sound_format(),
+            };
+            match (plan.codecs[index], codec) {
+                (None, Some(codec)) => {
+                    plan.codecs[index] = Some(codec);
+                    codec_sources[index] = path;
+                }
+                (Some(first), Some(codec)) if first != codec => {
+                    return Err(invalid_data(format!(
+                        "{}: {:?} codec {} differs from {} of {}",
+                        path, kind, codec, first, codec_sources[index]
+                    )));
+                }
+                _ => {}
+            }
+            if kind == TrackKind::Audio && plan.sound.is_none() {
+                plan.sound = tag.sound_rate().zip(tag.sound_channels());
+            }
+            for event in demuxer.push(&tag)? {
+                if let MediaEvent::Config(kind, config) = event {
+                    let current = &mut plan.configs[kind.index()];
+                    match current {
+                        Some(current) => eprintln!(
+                            "Warning: {}: {:?} configuration changes at {}ms,{} -> {}",
+                            path,
+                            kind,
+                            tag.timestamp(),
+                            current.description(),
+                            config.description()
+                        ),
+                        None => *current = Some(config),
+                    }
+                }
+            }
+            if filter.keep(&tag) {
+                plan.tags_size += tag.tag_size() as u64 + 4;
+                if !tag.is_sequence_header() {
+                    spans[index].push(tag.timestamp(), tag.data_size());
+                }
+            }
+            Ok(())
+        })?;
+        if truncated > 0 {
+            eprintln!(
+                "Warning: {}: {} bytes of a truncated tag ignored",
+                path, truncated
+            );
+        }
+        plan.spans.push(spans);
+    }
+    if plan.codecs.iter().all(Option::is_none) {
+        return Err(invalid_data("No audio or video tags found"));
+    }
+    Ok(plan)
+}
+
+///Offset added to the timestamps of every input,so that each stream continues
+///gap milliseconds after its last frame,or its average frame interval if gap is None.
+///One offset per input keeps its streams in sync.
+fn offsets(spans: &[[Span; 2]], gap: Option<u32>) -> Vec<i64> {
+    let mut ends: [Option<i64>; 2] = [None, None];
+    let mut offsets = vec![];
+    for input in spans {
+        //a stream that is new to the output continues after the others
+        let latest = ends.iter().flatten().max().copied();
+        let offset = input
+            .iter()
+            .zip(&ends)
+            .filter_map(|(span, end)| Some(end.or(latest)? - span.first? as i64))
+            .max()
+            .unwrap_or(0);
+        let start = input.iter().filter_map(|s| s.first).min().unwrap_or(0);
+        let offset = offset.max(-(start as i64));
+        for (span, end) in input.iter().zip(ends.iter_mut()) {
+            if span.first.is_some() {
+                *end =
+                    Some(span.last as i64 + offset + gap.unwrap_or_else(|| span.interval()) as i64);
+            }
+        }
+        offsets.push(offset);
+    }
+    offsets
+}
+
+fn metadata(plan: &Plan, offsets: &[i64]) -> Metadata {
+    //rates are those of the inputs back to back
+    let mut spans = [Span::default(); 2];
+    for input in &plan.spans {
+        for (span, other) in spans.iter_mut().zip(input) {
+            span.append(other);
+        }
+    }
+    let mut metadata = Metadata::from_spans(&spans, plan.codecs, &plan.configs, plan.sound);
+    let end = plan
+        .spans
+        .iter()
+        .zip(offsets)
+        .flat_map(|(spans, offset)| {
+            spans
+                .iter()
+                .filter_map(move |span| Some(span.end()? as i64 + offset))
+        })
+        .max()
+        .unwrap_or(0);
+    metadata.duration = end as f64 / 1000.0;
+    metadata
+}
+
+///Concatenate inputs into one flv,gap is the distance in milliseconds between the last frame
+///of a stream and its first frame from the next input
+pub fn concat(inputs: &[&str], path: &str, gap: Option<u32>) -> io::Result<()> {
+    let plan = scan(inputs)?;
+    let offsets = offsets(&plan.spans, gap);
+    let mut metadata = metadata(&plan, &offsets);
+    let metadata_size = metadata.to_tag().tag_size() as u64 + 4;
+    metadata.file_size = Some(Header::HEADER_SIZE as u64 + 4 + metadata_size + plan.tags_size);
+
+    let mut writer = Writer::new(open_output(path)?);
+    writer.write_header(&Header::new(
+        metadata.video.is_some(),
+        metadata.audio.is_some(),
+    ))?;
+    writer.write_tag(&metadata.to_tag())?;
+    let mut filter = TagFilter::default();
+    for (input, offset) in inputs.iter().zip(offsets) {
+        for_each_tag(input, |mut tag| {
+            if filter.keep(&tag) {
+                tag.set_timestamp((tag.timestamp() as i64 + offset).max(0) as u32);
+                writer.write_tag(&tag)?;
+            }
+            Ok(())
+        })?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,328 @@
+//! `cut` command: copy a time range starting at a video keyframe.
+
+use crate::media::{is_metadata, stream_index};
+use crate::{open_input, open_output};
+use flv_parser::amf::Amf0Value;
+use flv_parser::flv::{
+    Header, ParseResult, Reader, ScriptTagDataTrait, Segment, Tag, TagData, Writer,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+};
+
+///Metadata keys that don't describe the cut range
+const STALE_METADATA_KEYS: [&str; 4] = [
+    "keyframes",
+    "filesize",
+    "lasttimestamp",
+    "lastkeyframetimestamp",
+];
+
+///Parse a time like 90,1:30,00:01:30 or 00:01:30.500 to milliseconds
+pub fn parse_time(text: &str) -> Option<u32> {
+    let mut seconds = 0.0;
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    for (index, part) in parts.iter().enumerate() {
+        let value: f64 = part.parse().ok()?;
+        if value < 0.0 || (index + 1 < parts.len() && value.fract() != 0.0) {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some((seconds * 1000.0).round() as u32)
+}
+
+///Tags of a stream,or of a file which can seek to a tag
+enum Input {
+    Stream(Reader<Box<dyn Read>>),
+    File(BufReader<File>),
+}
+
+impl Input {
+    fn open(src: &str) -> io::Result<(Self, Header)> {
+        if src == "-" {
+            let mut reader = Reader::new(open_input(src)?);
+            match reader.read_next()? {
+                Some(ParseResult::Header(header)) => Ok((Input::Stream(reader), header)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "No flv header found",
+                )),
+            }
+        } else {
+            let mut file = BufReader::new(File::open(src)?);
+            let header = Header::from_reader(&mut file)?;
+            Ok((Input::File(file), header))
+        }
+    }
+
+    fn next_tag(&mut self) -> io::Result<Option<Tag>> {
+        match self {
+            Input::Stream(reader) => {
+                while let Some(result) = reader.read_next()? {
+                    if let ParseResult::Tag(tag) = result {
+                        return Ok(Some(tag));
+                    }
+                }
+                Ok(None)
+            }
+            Input::File(file) => match Segment::from_reader(file) {
+                Ok(segment) => Ok(segment.tag().clone()),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    ///Position of the next previous tag size,None for streams
+    fn position(&mut self) -> Option<u64> {
+        match self {
+            Input::Stream(_) => None,
+            Input::File(file) => file.stream_position().ok(),
+        }
+    }
+
+    ///Sequence headers from the current position until end,the position of a previous tag size.
+    ///Only the first bytes of other tags are read,their data is skipped.
+    fn sequence_headers(&mut self, end: u64) -> io::Result<Vec<Tag>> {
+        let file = match self {
+            Input::File(file) => file,
+            Input::Stream(_) => return Ok(vec![]),
+        };
+        let mut headers = vec![];
+        let mut position = file.stream_position()?;
+        while position < end {
+            let mut head = [0; 4 + Tag::TAG_HEADER_SIZE as usize];
+            file.read_exact(&mut head)?;
+            let data_size = u32::from_be_bytes([0, head[5], head[6], head[7]]) as i64;
+            let mut probe = head[4..].to_vec();
+            let mut first = vec![0; data_size.min(2) as usize];
+            file.read_exact(&mut first)?;
+            //a tag of the first bytes tells whether it is a sequence header
+            probe[1..4].copy_from_slice(&(first.len() as u32).to_be_bytes()[1..]);
+            probe.extend_from_slice(&first);
+            let next = position + head.len() as u64 + data_size as u64;
+            if Tag::from_reader(&mut probe.as_slice())?.is_sequence_header() {
+                file.seek(SeekFrom::Start(position))?;
+                headers.push(Segment::from_reader(file)?.tag().clone().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Invalid sequence header")
+                })?);
+            } else {
+                file.seek_relative(data_size - first.len() as i64)?;
+            }
+            position = next;
+        }
+        Ok(headers)
+    }
+
+    ///Continue reading at position,the position of a previous tag size
+    fn seek(&mut self, position: u64) -> io::Result<()> {
+        if let Input::File(file) = self {
+            file.seek(SeekFrom::Start(position))?;
+        }
+        Ok(())
+    }
+}
+
+///Entries of an object or ecma array
+fn entries(value: &Amf0Value) -> Option<&Vec<flv_parser::amf::Pair<String, Amf0Value>>> {
+    match value {
+        Amf0Value::Object { entries, .. } | Amf0Value::EcmaArray { entries } => Some(entries),
+        _ => None,
+    }
+}
+
+///(time in milliseconds,file position) of the keyframes object written by flv indexers
+fn keyframe_index(metadata: &[Amf0Value]) -> Vec<(u32, u64)> {
+    let numbers = |keyframes: &Vec<flv_parser::amf::Pair<String, Amf0Value>>, key: &str| {
+        keyframes
+            .iter()
+            .find(|pair| pair.key == key)
+            .and_then(|pair| match &pair.value {
+                Amf0Value::Array { entries } => entries
+                    .iter()
+                    .map(|value| match value {
+                        Amf0Value::Number(n) => Some(*n),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<f64>>>(),
+                _ => None,
+            })
+            .unwrap_or_default()
+    };
+    let keyframes = match metadata
+        .get(1)
+        .and_then(entries)
+        .and_then(|e| e.iter().find(|pair| pair.key == "keyframes"))
+        .and_then(|pair| entries(&pair.value))
+    {
+        Some(keyframes) => keyframes,
+        None => return vec![],
+    };
+    numbers(keyframes, "times")
+        .into_iter()
+        .zip(numbers(keyframes, "filepositions"))
+        .map(|(time, position)| ((time * 1000.0).round() as u32, position as u64))
+        .collect()
+}
+
+///onMetaData of the cut,without values which no longer apply
+fn cut_metadata(metadata: &[Amf0Value], duration: Option<f64>) -> Vec<Amf0Value> {
+    let mut metadata = metadata.to_vec();
+    for value in metadata.iter_mut() {
+        if let Amf0Value::EcmaArray { entries } | Amf0Value::Object { entries, .. } = value {
+            entries.retain(|pair| !STALE_METADATA_KEYS.contains(&pair.key.as_str()));
+        }
+    }
+    if let Some(duration) = duration {
+        metadata.set_duration(duration);
+    }
+    metadata
+}
+
+///Copy the tags from the last video keyframe at or before start until end,
+///with the current sequence headers and metadata in front and timestamps starting at 0.
+///Files with a keyframe index in onMetaData are seeked instead of read from the start.
+pub fn cut(src: &str, path: &str, start: u32, end: Option<u32>) -> io::Result<()> {
+    let (mut input, input_header) = Input::open(src)?;
+    let mut metadata: Option<Vec<Amf0Value>> = None;
+    let mut sequence_headers: [Option<Tag>; 2] = [None, None];
+    let mut has_video = input_header.has_video();
+    //tags since the last video keyframe before start
+    let mut gop: Vec<Tag> = vec![];
+    //audio after start but before the first keyframe
+    let mut early_audio: Vec<Tag> = vec![];
+    let mut index_checked = false;
+    let mut next = input.next_tag()?;
+
+    let (base, mut pending) = loop {
+        let tag = match next.take() {
+            Some(tag) => tag,
+            None => break (None, gop),
+        };
+        let kind = stream_index(&tag);
+        if let TagData::Script(values) = tag.data() {
+            if metadata.is_none() && is_metadata(values) {
+                metadata = Some(values.clone());
+            }
+        } else if tag.is_sequence_header() {
+            sequence_headers[kind.unwrap()] = Some(tag);
+        } else {
+            has_video |= kind == Some(0);
+            //the first frame follows the sequence headers,jump to the keyframe before start
+            if !index_checked {
+                index_checked = true;
+                let index = metadata.as_deref().map(keyframe_index).unwrap_or_default();
+                let target = index.iter().rev().find(|(time, _)| *time <= start);
+                if let (Some(&(time, position)), Some(resume)) = (target, input.position()) {
+                    //positions of the index are tag starts
+                    if position >= resume + 4 {
+                        //configurations may change in the tags skipped
+                        let skipped = input.sequence_headers(position - 4);
+                        input.seek(position - 4)?;
+                        match (skipped, input.next_tag()) {
+                            (Ok(skipped), Ok(Some(keyframe)))
+                                if keyframe.is_keyframe()
+                                    && keyframe.timestamp().abs_diff(time) <= 1 =>
+                            {
+                                for header in skipped {
+                                    let kind = stream_index(&header).unwrap();
+                                    sequence_headers[kind] = Some(header);
+                                }
+                                next = Some(keyframe);
+                                continue;
+                            }
+                            _ => {
+                                eprintln!("Warning: the keyframe index doesn't match the file,reading from the start");
+                                input.seek(resume)?;
+                            }
+                        }
+                    }
+                }
+            }
+            let keyframe = kind == Some(0) && tag.is_keyframe();
+            //a keyframe right at start begins the cut,the previous gop isn't needed
+            if keyframe && tag.timestamp() == start {
+                gop.clear();
+            }
+            if tag.timestamp() >= start {
+                if !gop.is_empty() || keyframe || !has_video {
+                    let base = gop.first().unwrap_or(&tag).timestamp();
+                    if gop.is_empty() {
+                        gop.append(&mut early_audio);
+                    }
+                    gop.push(tag);
+                    break (Some(base), gop);
+                }
+                if kind == Some(1) {
+                    early_audio.push(tag);
+                }
+            } else if keyframe {
+                gop.clear();
+                gop.push(tag);
+            } else if !gop.is_empty() {
+                gop.push(tag);
+            }
+        }
+        next = input.next_tag()?;
+    };
+    let base = base.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No keyframe found after {}ms", start),
+        )
+    })?;
+
+    let mut writer = Writer::new(open_output(path)?);
+    let has_audio = input_header.has_audio()
+        || sequence_headers[1].is_some()
+        || pending.iter().any(Tag::is_audio_tag);
+    writer.write_header(&Header::new(has_video, has_audio))?;
+    if let Some(metadata) = &metadata {
+        let total = metadata.duration();
+        let last = match end {
+            Some(end) if total.is_finite() => Some((end as f64 / 1000.0).min(total)),
+            Some(end) => Some(end as f64 / 1000.0),
+            None if total.is_finite() => Some(total),
+            None => None,
+        };
+        let duration = last.map(|last| (last - base as f64 / 1000.0).max(0.0));
+        writer.write_tag(&Tag::new(
+            0,
+            TagData::Script(cut_metadata(metadata, duration)),
+        ))?;
+    }
+    for tag in sequence_headers.iter_mut().flatten() {
+        tag.set_timestamp(0);
+        writer.write_tag(tag)?;
+    }
+    pending.reverse();
+    loop {
+        let mut tag = match pending.pop() {
+            Some(tag) => tag,
+            None => match input.next_tag()? {
+                Some(tag) => tag,
+                None => break,
+            },
+        };
+        if let TagData::Script(values) = tag.data() {
+            if is_metadata(values) {
+                continue;
+            }
+        }
+        if matches!(end, Some(end) if tag.timestamp() >= end) && !tag.is_sequence_header() {
+            break;
+        }
+        //audio a bit ahead of the keyframe in the file
+        if tag.timestamp() < base && stream_index(&tag) == Some(1) {
+            continue;
+        }
+        tag.set_timestamp(tag.timestamp().saturating_sub(base));
+        writer.write_tag(&tag)?;
+    }
+    writer.flush()
+}
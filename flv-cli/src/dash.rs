@@ -0,0 +1,409 @@
+//! `dash` command: CMAF segments per track and an MPD manifest.
+
+use crate::media::{Frame, TrackKind};
+use crate::mp4::{self, Track};
+use crate::open_input;
+use crate::remux::{write_fragment, FrameSource, DISCONTINUITY_THRESHOLD, PROBE_DURATION};
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MANIFEST_NAME: &str = "manifest.mpd";
+
+#[derive(Debug, Clone)]
+pub struct DashOptions {
+    ///Target segment duration in milliseconds
+    pub segment_duration: u32,
+    ///Dynamic manifest,rewritten after each segment
+    pub live: bool,
+    ///Count of segments in a dynamic manifest
+    pub window_size: usize,
+    ///Remove segments some time after they left a dynamic manifest
+    pub delete_segments: bool,
+}
+
+///Media segment of one track,times are in the timescale of the track
+struct Segment {
+    time: u64,
+    duration: u64,
+}
+
+///Segments written for one track
+struct Representation {
+    track: Track,
+    segments: VecDeque<Segment>,
+    expired: VecDeque<Segment>,
+    sequence: u32,
+    ///Highest bitrate of a segment in bits per second
+    bandwidth: u64,
+}
+
+impl Representation {
+    fn name(&self) -> &'static str {
+        match self.track.kind {
+            TrackKind::Video => "video",
+            TrackKind::Audio => "audio",
+        }
+    }
+
+    fn segment_name(&self, time: u64) -> String {
+        format!("{}-{}.m4s", self.name(), time)
+    }
+
+    ///SegmentTimeline entries,runs of equal contiguous segments share one S element
+    fn timeline(&self) -> String {
+        let mut entries: Vec<(u64, u64, u32)> = vec![];
+        let mut next = None;
+        for segment in &self.segments {
+            match entries.last_mut() {
+                Some((_, duration, repeat))
+                    if next == Some(segment.time) && *duration == segment.duration =>
+                {
+                    *repeat += 1
+                }
+                _ => entries.push((segment.time, segment.duration, 0)),
+            }
+            next = Some(segment.time + segment.duration);
+        }
+        let mut text = String::new();
+        let mut next = None;
+        for (time, duration, repeat) in entries {
+            text.push_str("            <S");
+            if next != Some(time) {
+                write!(text, " t=\"{}\"", time).unwrap();
+            }
+            write!(text, " d=\"{}\"", duration).unwrap();
+            if repeat > 0 {
+                write!(text, " r=\"{}\"", repeat).unwrap();
+            }
+            text.push_str("/>\n");
+            next = Some(time + duration * (repeat as u64 + 1));
+        }
+        text
+    }
+}
+
+///xs:duration with millisecond precision
+fn format_duration(millis: u64) -> String {
+    format!("PT{}.{:03}S", millis / 1000, millis % 1000)
+}
+
+///xs:dateTime in UTC
+fn format_date_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    //civil date from days since the epoch
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+struct Packager<R: Read> {
+    source: FrameSource<R>,
+    dir: PathBuf,
+    options: DashOptions,
+    representations: Vec<Representation>,
+    ///Timestamp of the first frame,the presentation starts there
+    base: u32,
+    cts_shift: i32,
+    availability_start: SystemTime,
+}
+
+impl<R: Read> Packager<R> {
+    fn main_kind(&self) -> TrackKind {
+        if self
+            .representations
+            .iter()
+            .any(|r| r.track.kind == TrackKind::Video)
+        {
+            TrackKind::Video
+        } else {
+            TrackKind::Audio
+        }
+    }
+
+    ///Write one media segment per track for the frames of a segment
+    fn write_segments(&mut self, frames: &[Frame]) -> io::Result<()> {
+        for representation in self.representations.iter_mut() {
+            let track_frames: Vec<Frame> = frames
+                .iter()
+                .filter(|f| f.kind == representation.track.kind)
+                .cloned()
+                .collect();
+            if track_frames.is_empty() {
+                continue;
+            }
+            let mut data = vec![];
+            representation.sequence += 1;
+            let timing = write_fragment(
+                &mut data,
+                std::slice::from_ref(&representation.track),
+                &track_frames,
+                representation.sequence,
+                self.base,
+                self.cts_shift,
+            )?;
+            let (_, time, duration) = timing[0];
+            fs::write(self.dir.join(representation.segment_name(time)), &data)?;
+            let bits = data.len() as u64 * 8 * representation.track.timescale as u64;
+            if let Some(bandwidth) = bits.checked_div(duration) {
+                representation.bandwidth = representation.bandwidth.max(bandwidth);
+            }
+            if let Some(previous) = representation.segments.back_mut() {
+                //close gaps and overlaps left by millisecond timestamps
+                let end = previous.time + previous.duration;
+                let tolerance = representation.track.frame_duration() as u64;
+                if time > previous.time && (time as i64 - end as i64).unsigned_abs() <= tolerance {
+                    previous.duration = time - previous.time;
+                }
+            }
+            representation
+                .segments
+                .push_back(Segment { time, duration });
+            if self.options.live {
+                while representation.segments.len() > self.options.window_size {
+                    let segment = representation.segments.pop_front().unwrap();
+                    representation.expired.push_back(segment);
+                }
+                while representation.expired.len() > self.options.window_size {
+                    let segment = representation.expired.pop_front().unwrap();
+                    if self.options.delete_segments {
+                        fs::remove_file(self.dir.join(representation.segment_name(segment.time)))?;
+                    }
+                }
+            }
+        }
+        if self.options.live {
+            self.write_manifest(false)?;
+        }
+        Ok(())
+    }
+
+    ///Replace the manifest,the new one is renamed over it so readers never see a partial file
+    fn write_manifest(&self, ended: bool) -> io::Result<()> {
+        let duration = self
+            .representations
+            .iter()
+            .filter_map(|r| {
+                let last = r.segments.back()?;
+                Some((last.time + last.duration) * 1000 / r.track.timescale as u64)
+            })
+            .max()
+            .unwrap_or(0);
+        let segment_duration = format_duration(self.options.segment_duration as u64);
+        let mut text = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        text.push_str("<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011,urn:mpeg:dash:profile:cmaf:2019\"");
+        if self.options.live {
+            text.push_str(" type=\"dynamic\"");
+            write!(
+                text,
+                " availabilityStartTime=\"{}\" publishTime=\"{}\"",
+                format_date_time(self.availability_start),
+                format_date_time(SystemTime::now())
+            )
+            .unwrap();
+            if ended {
+                write!(
+                    text,
+                    " mediaPresentationDuration=\"{}\"",
+                    format_duration(duration)
+                )
+                .unwrap();
+            } else {
+                write!(text, " minimumUpdatePeriod=\"{}\"", segment_duration).unwrap();
+            }
+            write!(
+                text,
+                " timeShiftBufferDepth=\"{}\"",
+                format_duration(
+                    self.options.segment_duration as u64 * self.options.window_size as u64
+                )
+            )
+            .unwrap();
+        } else {
+            write!(
+                text,
+                " type=\"static\" mediaPresentationDuration=\"{}\"",
+                format_duration(duration)
+            )
+            .unwrap();
+        }
+        writeln!(text, " minBufferTime=\"{}\">", segment_duration).unwrap();
+        text.push_str("  <Period id=\"0\" start=\"PT0S\">\n");
+        for representation in &self.representations {
+            let track = &representation.track;
+            let (content_type, mime_type) = match track.kind {
+                TrackKind::Video => ("video", "video/mp4"),
+                TrackKind::Audio => ("audio", "audio/mp4"),
+            };
+            writeln!(
+                text,
+                "    <AdaptationSet contentType=\"{}\" mimeType=\"{}\" segmentAlignment=\"true\" startWithSAP=\"1\">",
+                content_type, mime_type
+            )
+            .unwrap();
+            write!(
+                text,
+                "      <Representation id=\"{}\" codecs=\"{}\" bandwidth=\"{}\"",
+                representation.name(),
+                track.config.codec_string(),
+                representation.bandwidth.max(1)
+            )
+            .unwrap();
+            if let Some((width, height)) = track.config.picture_size() {
+                write!(text, " width=\"{}\" height=\"{}\"", width, height).unwrap();
+            }
+            let audio_format = track.config.audio_format();
+            if let Some((sample_rate, _)) = audio_format {
+                write!(text, " audioSamplingRate=\"{}\"", sample_rate).unwrap();
+            }
+            text.push_str(">\n");
+            if let Some((_, channels)) = audio_format {
+                writeln!(
+                    text,
+                    "        <AudioChannelConfiguration schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" value=\"{}\"/>",
+                    channels
+                )
+                .unwrap();
+            }
+            writeln!(
+                text,
+                "        <SegmentTemplate timescale=\"{}\" initialization=\"init-{}.mp4\" media=\"{}-$Time$.m4s\">",
+                track.timescale,
+                representation.name(),
+                representation.name()
+            )
+            .unwrap();
+            text.push_str("          <SegmentTimeline>\n");
+            text.push_str(&representation.timeline());
+            text.push_str("          </SegmentTimeline>\n");
+            text.push_str("        </SegmentTemplate>\n");
+            text.push_str("      </Representation>\n");
+            text.push_str("    </AdaptationSet>\n");
+        }
+        text.push_str("  </Period>\n</MPD>\n");
+        let path = self.dir.join(MANIFEST_NAME);
+        let temp = self.dir.join(format!("{}.tmp", MANIFEST_NAME));
+        fs::write(&temp, text)?;
+        fs::rename(&temp, &path)
+    }
+
+    fn run(&mut self, pending: Vec<Frame>) -> io::Result<()> {
+        let segment_duration = self.options.segment_duration;
+        let main_kind = self.main_kind();
+        let mut pending = pending.into_iter();
+        let mut frames: Vec<Frame> = vec![];
+        //a single period needs one timeline,timestamp jumps are folded away
+        let mut offset = 0_i64;
+        //(timestamp,gap to the previous one) of the last frame of each stream after offset
+        let mut last_video: Option<(i64, i64)> = None;
+        let mut last_audio: Option<(i64, i64)> = None;
+        loop {
+            let mut frame = match pending.next() {
+                Some(frame) => frame,
+                None => match self.source.next_frame()? {
+                    Some(frame) => frame,
+                    None => break,
+                },
+            };
+            let last = match frame.kind {
+                TrackKind::Video => last_video,
+                TrackKind::Audio => last_audio,
+            };
+            let mut timestamp = frame.timestamp as i64 + offset;
+            if let Some((last, gap)) = last {
+                if (timestamp - last).abs() > DISCONTINUITY_THRESHOLD as i64 {
+                    offset += last + gap - timestamp;
+                    timestamp = last + gap;
+                }
+            }
+            let gap = last.map_or(0, |(last, _)| timestamp - last);
+            match frame.kind {
+                TrackKind::Video => last_video = Some((timestamp, gap)),
+                TrackKind::Audio => last_audio = Some((timestamp, gap)),
+            }
+            frame.timestamp = timestamp.max(0) as u32;
+            if let Some(first) = frames.first() {
+                if frame.keyframe
+                    && frame.kind == main_kind
+                    && frame.timestamp.saturating_sub(first.timestamp) >= segment_duration
+                {
+                    self.write_segments(&frames)?;
+                    frames.clear();
+                }
+            }
+            frames.push(frame);
+        }
+        if !frames.is_empty() {
+            self.write_segments(&frames)?;
+        }
+        self.write_manifest(true)
+    }
+}
+
+///Package src as DASH into dir
+pub fn package(src: &str, dir: &str, options: DashOptions) -> io::Result<()> {
+    let dir = Path::new(dir).to_path_buf();
+    fs::create_dir_all(&dir)?;
+    let mut source = FrameSource::new(open_input(src)?);
+    let availability_start = SystemTime::now();
+    let pending = source.probe(PROBE_DURATION)?;
+    let mut representations = vec![];
+    for track in source.tracks() {
+        let representation = Representation {
+            track: track.clone(),
+            segments: VecDeque::new(),
+            expired: VecDeque::new(),
+            sequence: 0,
+            bandwidth: 0,
+        };
+        let mut init = mp4::ftyp(b"cmfc", 0, &[b"iso6", b"cmfc", b"dash"]);
+        init.extend_from_slice(&mp4::fragmented_moov(std::slice::from_ref(track)));
+        fs::write(
+            dir.join(format!("init-{}.mp4", representation.name())),
+            init,
+        )?;
+        representations.push(representation);
+    }
+    let mut packager = Packager {
+        base: pending.iter().map(|f| f.timestamp).min().unwrap_or(0),
+        cts_shift: pending
+            .iter()
+            .find(|f| f.kind == TrackKind::Video)
+            .map(|f| f.composition_time)
+            .unwrap_or(0),
+        source,
+        dir,
+        options,
+        representations,
+        availability_start,
+    };
+    packager.run(pending)
+}
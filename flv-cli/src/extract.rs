@@ -0,0 +1,514 @@
+//! Elementary stream formats of the `extract` command.
+
+use crate::avi::{self, AviVideo};
+use crate::media::{self, CodecConfig, Demuxer, MediaEvent, TrackKind};
+use crate::ogg::OggWriter;
+use crate::remux::Spool;
+use crate::scan::{h263_picture_size, vp6_picture_size};
+use crate::{audio_codec_name, open_input, open_output, video_codec_name};
+use flv_parser::flv::{mp3::Mp3FrameHeader, ParseResult, Reader, Tag};
+use std::io::{self, Write};
+
+///Feed every decoded configuration and frame of src to handle
+fn demux(src: &str, mut handle: impl FnMut(MediaEvent) -> io::Result<()>) -> io::Result<()> {
+    let mut reader = Reader::new(open_input(src)?);
+    let mut demuxer = Demuxer::new();
+    while let Some(result) = reader.read_next()? {
+        if let ParseResult::Tag(tag) = result {
+            for event in demuxer.push(&tag)? {
+                handle(event)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+///Write the video of src as an Annex B byte stream,format is h264 or h265
+pub fn extract_video(src: &str, path: &str, format: &str) -> io::Result<()> {
+    let mut output = open_output(path)?;
+    let mut config: Option<CodecConfig> = None;
+    let mut frames = 0;
+    demux(src, |event| {
+        match event {
+            MediaEvent::Config(TrackKind::Video, new_config) => {
+                let expected = match &new_config {
+                    CodecConfig::Avc(_) => "h264",
+                    _ => "h265",
+                };
+                if expected != format {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Video is not {},use --format {}", format, expected),
+                    ));
+                }
+                config = Some(new_config);
+            }
+            MediaEvent::Frame(frame) if frame.kind == TrackKind::Video => {
+                //frames before the first sequence header can't be decoded
+                if let Some(config) = &config {
+                    output.write_all(&media::to_annex_b(config, &frame.data, false)?)?;
+                    frames += 1;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+    if frames == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No AVC/HEVC video found",
+        ));
+    }
+    output.flush()
+}
+
+///Write the aac audio of src with an adts header on every frame,
+///headers follow the latest sequence header
+pub fn extract_aac(src: &str, path: &str) -> io::Result<()> {
+    let mut output = open_output(path)?;
+    let mut config = None;
+    let mut frames = 0;
+    demux(src, |event| {
+        match event {
+            MediaEvent::Config(TrackKind::Audio, CodecConfig::Aac(new_config)) => {
+                config = Some(new_config);
+            }
+            MediaEvent::Config(TrackKind::Audio, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Audio is not AAC",
+                ));
+            }
+            MediaEvent::Frame(frame) if frame.kind == TrackKind::Audio => {
+                if let Some(config) = &config {
+                    output.write_all(&config.adts_header(frame.data.len())?)?;
+                    output.write_all(&frame.data)?;
+                    frames += 1;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+    if frames == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No AAC audio found",
+        ));
+    }
+    output.flush()
+}
+
+///Size of the side information of an mpeg audio layer 3 frame
+fn side_info_size(header: &Mp3FrameHeader) -> usize {
+    match (header.version() == 1, header.channels() == 1) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    }
+}
+
+///Build the frame carrying a Xing(vbr) or Info(cbr) tag,modelled on the first audio frame.
+///frame_offsets are the positions of all frames in the audio data of audio_size bytes.
+fn xing_frame(first: &[u8], frame_offsets: &[u64], audio_size: u64, vbr: bool) -> Option<Vec<u8>> {
+    let header = Mp3FrameHeader::from_bytes(first).ok()?;
+    if header.layer() != 3 {
+        return None;
+    }
+    let tag_offset = Mp3FrameHeader::HEADER_SIZE + side_info_size(&header);
+    let needed = tag_offset + 16 + 100;
+    //smallest bitrate whose frame holds the tag,without crc and padding
+    let mut raw = [first[0], first[1] | 0x01, 0, first[3]];
+    let size = (1..15_u8).find_map(|index| {
+        raw[2] = (index << 4) | (first[2] & 0x0D);
+        let size = Mp3FrameHeader::from_bytes(&raw).ok()?.frame_size();
+        if size >= needed {
+            Some(size)
+        } else {
+            None
+        }
+    })?;
+    let mut frame = vec![0_u8; size];
+    frame[..4].copy_from_slice(&raw);
+    let total_size = audio_size + size as u64;
+    let mut tag = Vec::with_capacity(116);
+    tag.extend_from_slice(if vbr { b"Xing" } else { b"Info" });
+    //frame count,byte count and seek table present
+    tag.extend_from_slice(&0x07_u32.to_be_bytes());
+    tag.extend_from_slice(&(frame_offsets.len() as u32).to_be_bytes());
+    tag.extend_from_slice(&(total_size.min(u32::MAX as u64) as u32).to_be_bytes());
+    for i in 0..100 {
+        let offset = frame_offsets
+            .get(i * frame_offsets.len() / 100)
+            .map_or(0, |o| o + size as u64);
+        tag.push((offset * 256 / total_size).min(255) as u8);
+    }
+    frame[tag_offset..tag_offset + tag.len()].copy_from_slice(&tag);
+    Some(frame)
+}
+
+///Write the mp3 audio of src as a plain mp3 file,
+///optionally starting with a Xing/Info frame holding the frame count
+pub fn extract_mp3(src: &str, path: &str, xing: bool) -> io::Result<()> {
+    let mut output = open_output(path)?;
+    let mut spool = if xing { Some(Spool::new()?) } else { None };
+    let mut first: Option<Vec<u8>> = None;
+    let mut frame_offsets: Vec<u64> = vec![];
+    let mut bitrates: Vec<u32> = vec![];
+    let mut audio_size = 0_u64;
+    demux(src, |event| {
+        match event {
+            MediaEvent::Config(TrackKind::Audio, CodecConfig::Mp3(_)) => {}
+            MediaEvent::Config(TrackKind::Audio, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Audio is not MP3",
+                ));
+            }
+            MediaEvent::Frame(frame) if frame.kind == TrackKind::Audio => {
+                //a tag may carry several frames
+                let mut offset = 0;
+                while let Ok(header) = Mp3FrameHeader::from_bytes(&frame.data[offset..]) {
+                    frame_offsets.push(audio_size + offset as u64);
+                    if !bitrates.contains(&header.bitrate()) {
+                        bitrates.push(header.bitrate());
+                    }
+                    offset += header.frame_size();
+                    if offset >= frame.data.len() {
+                        break;
+                    }
+                }
+                if first.is_none() {
+                    first = Some(frame.data.clone());
+                }
+                audio_size += frame.data.len() as u64;
+                match spool.as_mut() {
+                    Some(spool) => spool.write(&frame.data)?,
+                    None => output.write_all(&frame.data)?,
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+    let first =
+        first.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No MP3 audio found"))?;
+    if let Some(mut spool) = spool {
+        match xing_frame(&first, &frame_offsets, audio_size, bitrates.len() > 1) {
+            Some(frame) => output.write_all(&frame)?,
+            None => eprintln!("Warning: Xing header needs MPEG layer 3 audio,skipped"),
+        }
+        spool.copy_to(&mut output)?;
+    }
+    output.flush()
+}
+
+///RIFF/WAVE header,RF64 when the sizes don't fit 32 bits.
+///format_tag is 1 for pcm,6 for a-law and 7 for mu-law.
+fn wav_header(
+    format_tag: u16,
+    sample_rate: u32,
+    bits: u16,
+    channels: u16,
+    data_size: u64,
+) -> Vec<u8> {
+    let block_align = channels * bits / 8;
+    let sample_count = data_size / block_align.max(1) as u64;
+    let mut fmt = vec![];
+    fmt.extend_from_slice(b"fmt ");
+    fmt.extend_from_slice(&(if format_tag == 1 { 16_u32 } else { 18 }).to_le_bytes());
+    fmt.extend_from_slice(&format_tag.to_le_bytes());
+    fmt.extend_from_slice(&channels.to_le_bytes());
+    fmt.extend_from_slice(&sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    fmt.extend_from_slice(&block_align.to_le_bytes());
+    fmt.extend_from_slice(&bits.to_le_bytes());
+    if format_tag != 1 {
+        //non-pcm formats have an extension size and a fact chunk
+        fmt.extend_from_slice(&0_u16.to_le_bytes());
+        fmt.extend_from_slice(b"fact");
+        fmt.extend_from_slice(&4_u32.to_le_bytes());
+        fmt.extend_from_slice(&(sample_count.min(u32::MAX as u64) as u32).to_le_bytes());
+    }
+
+    let mut header = vec![];
+    let riff_size = 4 + fmt.len() as u64 + 8 + data_size;
+    if riff_size <= u32::MAX as u64 {
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(riff_size as u32).to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(&fmt);
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&(data_size as u32).to_le_bytes());
+    } else {
+        header.extend_from_slice(b"RF64");
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"ds64");
+        header.extend_from_slice(&28_u32.to_le_bytes());
+        header.extend_from_slice(&(riff_size + 36).to_le_bytes());
+        header.extend_from_slice(&data_size.to_le_bytes());
+        header.extend_from_slice(&sample_count.to_le_bytes());
+        header.extend_from_slice(&0_u32.to_le_bytes());
+        header.extend_from_slice(&fmt);
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+    }
+    header
+}
+
+///Error for an audio codec the requested format can't carry
+fn unsupported_audio(sound_format: Option<u8>, hint: &str) -> io::Error {
+    let name = audio_codec_name(&sound_format.map_or(-1.0, |f| f as f64));
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Audio is {},{}", name, hint),
+    )
+}
+
+///Write the linear pcm or G.711 audio of src as a wav file.
+///Platform endian samples are read as little endian unless big_endian is set.
+pub fn extract_wav(src: &str, path: &str, big_endian: bool) -> io::Result<()> {
+    let mut reader = Reader::new(open_input(src)?);
+    let mut spool = Spool::new()?;
+    //(sound format,sample rate,bits,channels)
+    let mut format: Option<(u8, u32, u8, u8)> = None;
+    while let Some(result) = reader.read_next()? {
+        let tag = match result {
+            ParseResult::Tag(tag) if tag.is_audio_tag() => tag,
+            _ => continue,
+        };
+        let sound_format = tag.sound_format();
+        let bits = match sound_format {
+            Some(Tag::SOUND_FORMAT_PCM_PLATFORM) | Some(Tag::SOUND_FORMAT_PCM_LE) => {
+                tag.sound_size().unwrap_or(16)
+            }
+            Some(Tag::SOUND_FORMAT_G711_ALAW) | Some(Tag::SOUND_FORMAT_G711_MULAW) => 8,
+            _ => return Err(unsupported_audio(sound_format, "use --format raw")),
+        };
+        let tag_format = (
+            sound_format.unwrap_or(0),
+            tag.sound_rate().unwrap_or(0),
+            bits,
+            tag.sound_channels().unwrap_or(1),
+        );
+        match format {
+            Some(format) if format != tag_format => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Audio format changes at {}ms", tag.timestamp()),
+                ));
+            }
+            Some(_) => {}
+            None => format = Some(tag_format),
+        }
+        if big_endian && bits == 16 && sound_format == Some(Tag::SOUND_FORMAT_PCM_PLATFORM) {
+            let mut samples = tag.body().to_vec();
+            for sample in samples.chunks_exact_mut(2) {
+                sample.swap(0, 1);
+            }
+            spool.write(&samples)?;
+        } else {
+            spool.write(tag.body())?;
+        }
+    }
+    let (sound_format, sample_rate, bits, channels) =
+        format.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No PCM audio found"))?;
+    let format_tag = match sound_format {
+        Tag::SOUND_FORMAT_G711_ALAW => 6,
+        Tag::SOUND_FORMAT_G711_MULAW => 7,
+        _ => 1,
+    };
+    let mut output = open_output(path)?;
+    output.write_all(&wav_header(
+        format_tag,
+        sample_rate,
+        bits as u16,
+        channels as u16,
+        spool.size,
+    ))?;
+    spool.copy_to(&mut output)?;
+    output.flush()
+}
+
+///Write the audio payloads of src unchanged,for codecs without a container like Nellymoser
+pub fn extract_raw_audio(src: &str, path: &str) -> io::Result<()> {
+    let mut reader = Reader::new(open_input(src)?);
+    let mut output = open_output(path)?;
+    let mut sound_format = None;
+    while let Some(result) = reader.read_next()? {
+        let tag = match result {
+            ParseResult::Tag(tag) if tag.is_audio_tag() => tag,
+            _ => continue,
+        };
+        if tag.sound_format() == Some(Tag::SOUND_FORMAT_AAC) && tag.is_sequence_header() {
+            continue;
+        }
+        match sound_format {
+            Some(format) if Some(format) != tag.sound_format() => {
+                eprintln!(
+                    "Warning: audio codec changes at {}ms,output mixes codecs",
+                    tag.timestamp()
+                );
+                sound_format = tag.sound_format();
+            }
+            Some(_) => {}
+            None => sound_format = tag.sound_format(),
+        }
+        output.write_all(tag.body())?;
+    }
+    if sound_format.is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "No audio found"));
+    }
+    output.flush()
+}
+
+///Speex identification header,flash writes 16kHz wideband mono with one frame per packet
+fn speex_header() -> Vec<u8> {
+    let mut header = b"Speex   ".to_vec();
+    let mut version = b"1.2".to_vec();
+    version.resize(20, 0);
+    header.extend_from_slice(&version);
+    //version id,header size,rate,mode,mode bitstream version,channels,bitrate,
+    //frame size,vbr,frames per packet,extra headers,reserved
+    for value in [1_i32, 80, 16000, 1, 4, 1, -1, 320, 0, 1, 0, 0, 0] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    header
+}
+
+///Write the speex audio of src into an ogg file
+pub fn extract_speex(src: &str, path: &str) -> io::Result<()> {
+    //samples of a 20ms wideband frame
+    const FRAME_SIZE: i64 = 320;
+    let mut reader = Reader::new(open_input(src)?);
+    let mut writer: Option<OggWriter<_>> = None;
+    let mut output = Some(open_output(path)?);
+    let mut granule = 0_i64;
+    while let Some(result) = reader.read_next()? {
+        let tag = match result {
+            ParseResult::Tag(tag) if tag.is_audio_tag() => tag,
+            _ => continue,
+        };
+        if tag.sound_format() != Some(Tag::SOUND_FORMAT_SPEEX) {
+            return Err(unsupported_audio(tag.sound_format(), "ogg needs Speex"));
+        }
+        if tag.body().is_empty() {
+            continue;
+        }
+        let writer = match writer.as_mut() {
+            Some(writer) => writer,
+            None => {
+                let mut ogg = OggWriter::new(output.take().unwrap(), 0x464C_5621);
+                ogg.write_packet(&speex_header(), 0, true)?;
+                let vendor = b"flv-cli";
+                let mut comment = (vendor.len() as u32).to_le_bytes().to_vec();
+                comment.extend_from_slice(vendor);
+                comment.extend_from_slice(&0_u32.to_le_bytes());
+                ogg.write_packet(&comment, 0, true)?;
+                writer.insert(ogg)
+            }
+        };
+        granule += FRAME_SIZE;
+        writer.write_packet(tag.body(), granule, false)?;
+    }
+    let writer =
+        writer.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No Speex audio found"))?;
+    writer.finish()?.flush()
+}
+
+///Write the Sorenson H.263 or VP6 video of src into an avi file
+pub fn extract_avi(src: &str, path: &str) -> io::Result<()> {
+    let mut reader = Reader::new(open_input(src)?);
+    let mut spool = Spool::new()?;
+    let mut video: Option<AviVideo> = None;
+    let mut codec = None;
+    //(size,keyframe) of every frame
+    let mut frames: Vec<(u32, bool)> = vec![];
+    let mut first_timestamp = 0;
+    let mut last_timestamp = 0;
+    while let Some(result) = reader.read_next()? {
+        let tag = match result {
+            ParseResult::Tag(tag) if tag.is_video_tag() => tag,
+            _ => continue,
+        };
+        let frame_type = tag.frame_type();
+        if frame_type == Some(Tag::FRAME_TYPE_COMMAND) || tag.body().is_empty() {
+            continue;
+        }
+        let (fourcc, data, extradata, size) = match tag.video_codec_id() {
+            Some(Tag::VIDEO_CODEC_H263) => {
+                (*b"FLV1", tag.body(), vec![], h263_picture_size(tag.body()))
+            }
+            Some(Tag::VIDEO_CODEC_VP6) => (
+                *b"VP6F",
+                &tag.body()[1..],
+                vec![tag.body()[0]],
+                vp6_picture_size(tag.body(), false),
+            ),
+            Some(Tag::VIDEO_CODEC_VP6_ALPHA) => (
+                *b"VP6A",
+                &tag.body()[1..],
+                vec![tag.body()[0]],
+                vp6_picture_size(tag.body(), true),
+            ),
+            id => {
+                let name = video_codec_name(&id.map_or(-1.0, |id| id as f64));
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Video is {},avi needs Sorenson H.263 or VP6", name),
+                ));
+            }
+        };
+        if codec.is_some() && codec != tag.video_codec_id() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Video codec changes at {}ms", tag.timestamp()),
+            ));
+        }
+        codec = tag.video_codec_id();
+        if video.is_none() {
+            let (width, height) = match size {
+                Some(size) => size,
+                //the size is only known from a keyframe
+                None => continue,
+            };
+            video = Some(AviVideo {
+                fourcc,
+                width,
+                height,
+                rate: 25,
+                scale: 1,
+                extradata,
+            });
+            first_timestamp = tag.timestamp();
+        }
+        last_timestamp = tag.timestamp();
+        spool.write(&avi::frame_chunk(data))?;
+        frames.push((data.len() as u32, tag.is_keyframe()));
+    }
+    let mut video = video.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No Sorenson H.263 or VP6 video found",
+        )
+    })?;
+    //avi has a constant frame rate,use the average one in millihertz
+    let duration = last_timestamp.saturating_sub(first_timestamp) as u64;
+    if frames.len() > 1 && duration > 0 {
+        video.rate = ((frames.len() as u64 - 1) * 1_000_000 / duration) as u32;
+        video.scale = 1000;
+    }
+    if avi::file_size(&video, &frames) > u32::MAX as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Video too large for an AVI 1.0 file",
+        ));
+    }
+    let mut output = open_output(path)?;
+    output.write_all(&avi::headers(&video, &frames))?;
+    spool.copy_to(&mut output)?;
+    output.write_all(&avi::index(&frames))?;
+    output.flush()
+}
@@ -0,0 +1,283 @@
+//! `hls` command: cut flv into HLS segments and keep the m3u8 playlist up to date.
+
+use crate::media::{Frame, TrackKind};
+use crate::mp4::{self, Track};
+use crate::open_input;
+use crate::remux::{write_fragment, FrameSource, DISCONTINUITY_THRESHOLD, PROBE_DURATION};
+use crate::ts::TsMuxer;
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+const PLAYLIST_NAME: &str = "index.m3u8";
+const INIT_SEGMENT_NAME: &str = "init.mp4";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentFormat {
+    Ts,
+    Fmp4,
+}
+
+#[derive(Debug, Clone)]
+pub struct HlsOptions {
+    pub format: SegmentFormat,
+    ///Target segment duration in milliseconds
+    pub target_duration: u32,
+    ///Sliding window playlist,rewritten after each segment
+    pub live: bool,
+    ///Count of segments in a live playlist
+    pub list_size: usize,
+    ///Remove segments some time after they left a live playlist
+    pub delete_segments: bool,
+}
+
+struct Segment {
+    name: String,
+    ///Duration in milliseconds
+    duration: u32,
+    discontinuity: bool,
+}
+
+struct Playlist {
+    dir: PathBuf,
+    options: HlsOptions,
+    segments: VecDeque<Segment>,
+    ///Segments that left the window,kept until players stop asking for them
+    expired: VecDeque<Segment>,
+    media_sequence: u64,
+    discontinuity_sequence: u64,
+    ///Longest segment in seconds so far,#EXT-X-TARGETDURATION must not shrink with the window
+    target_duration: u32,
+}
+
+impl Playlist {
+    fn push(&mut self, segment: Segment) -> io::Result<()> {
+        self.target_duration = self
+            .target_duration
+            .max(segment.duration.saturating_add(500) / 1000);
+        self.segments.push_back(segment);
+        if !self.options.live {
+            return Ok(());
+        }
+        while self.segments.len() > self.options.list_size {
+            let segment = self.segments.pop_front().unwrap();
+            self.media_sequence += 1;
+            if segment.discontinuity {
+                self.discontinuity_sequence += 1;
+            }
+            self.expired.push_back(segment);
+        }
+        while self.expired.len() > self.options.list_size {
+            let segment = self.expired.pop_front().unwrap();
+            if self.options.delete_segments {
+                fs::remove_file(self.dir.join(&segment.name))?;
+            }
+        }
+        self.write(false)
+    }
+
+    ///Replace the playlist file,the new one is renamed over it so readers never see a partial file
+    fn write(&self, ended: bool) -> io::Result<()> {
+        let mut text = String::from("#EXTM3U\n");
+        let version = match self.options.format {
+            SegmentFormat::Ts => 3,
+            SegmentFormat::Fmp4 => 7,
+        };
+        writeln!(text, "#EXT-X-VERSION:{}", version).unwrap();
+        writeln!(text, "#EXT-X-TARGETDURATION:{}", self.target_duration).unwrap();
+        writeln!(text, "#EXT-X-MEDIA-SEQUENCE:{}", self.media_sequence).unwrap();
+        if self.discontinuity_sequence > 0 {
+            writeln!(
+                text,
+                "#EXT-X-DISCONTINUITY-SEQUENCE:{}",
+                self.discontinuity_sequence
+            )
+            .unwrap();
+        }
+        if !self.options.live {
+            text.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n");
+        }
+        if self.options.format == SegmentFormat::Fmp4 {
+            writeln!(text, "#EXT-X-MAP:URI=\"{}\"", INIT_SEGMENT_NAME).unwrap();
+        }
+        for segment in &self.segments {
+            if segment.discontinuity {
+                text.push_str("#EXT-X-DISCONTINUITY\n");
+            }
+            writeln!(text, "#EXTINF:{:.3},", segment.duration as f64 / 1000.0).unwrap();
+            writeln!(text, "{}", segment.name).unwrap();
+        }
+        if ended {
+            text.push_str("#EXT-X-ENDLIST\n");
+        }
+        let path = self.dir.join(PLAYLIST_NAME);
+        let temp = self.dir.join(format!("{}.tmp", PLAYLIST_NAME));
+        fs::write(&temp, text)?;
+        fs::rename(&temp, &path)
+    }
+}
+
+struct Segmenter<R: Read> {
+    source: FrameSource<R>,
+    tracks: Vec<Track>,
+    playlist: Playlist,
+    muxer: TsMuxer<Vec<u8>>,
+    sequence: u32,
+    ///Shift of composition times so the first video frame has none,fmp4 only
+    cts_shift: Option<i32>,
+}
+
+impl<R: Read> Segmenter<R> {
+    ///Kind of the stream whose keyframes start segments
+    fn main_kind(&self) -> TrackKind {
+        if self.tracks.iter().any(|t| t.kind == TrackKind::Video) {
+            TrackKind::Video
+        } else {
+            TrackKind::Audio
+        }
+    }
+
+    ///Estimated end of frames that aren't followed by a contiguous one
+    fn estimated_end(&self, frames: &[Frame]) -> u32 {
+        let main: Vec<u32> = frames
+            .iter()
+            .filter(|f| f.kind == self.main_kind())
+            .map(|f| f.timestamp)
+            .collect();
+        let gap = match main.as_slice() {
+            [.., previous, last] => last.saturating_sub(*previous),
+            _ => 0,
+        };
+        frames
+            .iter()
+            .map(|f| f.timestamp)
+            .max()
+            .unwrap_or(0)
+            .saturating_add(gap)
+    }
+
+    fn write_segment(&mut self, frames: &[Frame], end: u32, discontinuity: bool) -> io::Result<()> {
+        let mut data = vec![];
+        match self.playlist.options.format {
+            SegmentFormat::Ts => {
+                self.muxer.write_tables();
+                for frame in frames {
+                    self.muxer.write_frame(frame)?;
+                }
+                std::mem::swap(&mut data, self.muxer.get_mut());
+            }
+            SegmentFormat::Fmp4 => {
+                let cts_shift = *self.cts_shift.get_or_insert_with(|| {
+                    frames
+                        .iter()
+                        .find(|f| f.kind == TrackKind::Video)
+                        .map(|f| f.composition_time)
+                        .unwrap_or(0)
+                });
+                self.sequence += 1;
+                write_fragment(&mut data, &self.tracks, frames, self.sequence, 0, cts_shift)?;
+            }
+        }
+        let extension = match self.playlist.options.format {
+            SegmentFormat::Ts => "ts",
+            SegmentFormat::Fmp4 => "m4s",
+        };
+        let index = self.playlist.media_sequence + self.playlist.segments.len() as u64;
+        let name = format!("segment{}.{}", index, extension);
+        fs::write(self.playlist.dir.join(&name), data)?;
+        self.playlist.push(Segment {
+            name,
+            duration: end.saturating_sub(frames[0].timestamp),
+            discontinuity,
+        })
+    }
+
+    fn run(&mut self, pending: Vec<Frame>) -> io::Result<()> {
+        let target_duration = self.playlist.options.target_duration;
+        let main_kind = self.main_kind();
+        let mut pending = pending.into_iter();
+        let mut frames: Vec<Frame> = vec![];
+        let mut discontinuity = false;
+        let mut last_video: Option<u32> = None;
+        let mut last_audio: Option<u32> = None;
+        loop {
+            let frame = match pending.next() {
+                Some(frame) => frame,
+                None => match self.source.next_frame()? {
+                    Some(frame) => frame,
+                    None => break,
+                },
+            };
+            let last = match frame.kind {
+                TrackKind::Video => &mut last_video,
+                TrackKind::Audio => &mut last_audio,
+            };
+            let jump = matches!(*last, Some(last)
+                if frame.timestamp.saturating_add(DISCONTINUITY_THRESHOLD) < last
+                    || frame.timestamp > last.saturating_add(DISCONTINUITY_THRESHOLD));
+            *last = Some(frame.timestamp);
+            if jump && !frames.is_empty() {
+                let end = self.estimated_end(&frames);
+                self.write_segment(&frames, end, discontinuity)?;
+                frames.clear();
+                discontinuity = true;
+                last_video = None;
+                last_audio = None;
+                match frame.kind {
+                    TrackKind::Video => last_video = Some(frame.timestamp),
+                    TrackKind::Audio => last_audio = Some(frame.timestamp),
+                }
+            } else if let Some(first) = frames.first() {
+                if frame.keyframe
+                    && frame.kind == main_kind
+                    && frame.timestamp.saturating_sub(first.timestamp) >= target_duration
+                {
+                    self.write_segment(&frames, frame.timestamp, discontinuity)?;
+                    frames.clear();
+                    discontinuity = false;
+                }
+            }
+            frames.push(frame);
+        }
+        if !frames.is_empty() {
+            let end = self.estimated_end(&frames);
+            self.write_segment(&frames, end, discontinuity)?;
+        }
+        self.playlist.write(true)
+    }
+}
+
+///Cut src into segments in dir and write the playlist
+pub fn segment(src: &str, dir: &str, options: HlsOptions) -> io::Result<()> {
+    let dir = Path::new(dir).to_path_buf();
+    fs::create_dir_all(&dir)?;
+    let mut source = FrameSource::new(open_input(src)?);
+    let pending = source.probe(PROBE_DURATION)?;
+    let tracks = source.tracks().to_vec();
+    if options.format == SegmentFormat::Fmp4 {
+        let mut init = mp4::ftyp(b"iso6", 0, &[b"iso6", b"mp41"]);
+        init.extend_from_slice(&mp4::fragmented_moov(&tracks));
+        fs::write(dir.join(INIT_SEGMENT_NAME), init)?;
+    }
+    let mut segmenter = Segmenter {
+        muxer: TsMuxer::new(vec![], &tracks),
+        source,
+        tracks,
+        playlist: Playlist {
+            dir,
+            target_duration: options.target_duration.div_ceil(1000).max(1),
+            options,
+            segments: VecDeque::new(),
+            expired: VecDeque::new(),
+            media_sequence: 0,
+            discontinuity_sequence: 0,
+        },
+        sequence: 0,
+        cts_shift: None,
+    };
+    segmenter.run(pending)
+}
@@ -0,0 +1,73 @@
+//! `interleave` command: put the tags of a file in timestamp order.
+
+use crate::{open_input, open_output};
+use flv_parser::flv::{Interleaver, LateTag, ParseResult, Reader, Tag, Writer};
+use std::io;
+
+fn stream_name(tag_type: u8) -> &'static str {
+    match tag_type {
+        Tag::TYPE_VIDEO => "Video",
+        Tag::TYPE_AUDIO => "Audio",
+        _ => "Script",
+    }
+}
+
+///Copy src to path with its tags sorted by timestamp within window milliseconds,
+///reporting tags that were further out of order
+pub fn interleave(src: &str, path: &str, window: u32) -> io::Result<()> {
+    let mut reader = Reader::new(open_input(src)?);
+    let mut writer = Writer::new(open_output(path)?);
+    let mut interleaver = Interleaver::new(window);
+    let mut first_late: Option<LateTag> = None;
+    let mut most_late = 0;
+    let mut steps_back = 0;
+    let mut previous: Option<u32> = None;
+    let mut count = 0;
+    while let Some(result) = reader.read_next()? {
+        match result {
+            ParseResult::Header(header) => writer.write_header(&header)?,
+            ParseResult::Tag(tag) => {
+                if matches!(previous, Some(previous) if tag.timestamp() < previous) {
+                    steps_back += 1;
+                }
+                previous = Some(tag.timestamp());
+                if let Some(late) = interleaver.push(tag) {
+                    first_late.get_or_insert(late);
+                    most_late = most_late.max(late.released - late.timestamp);
+                }
+                while let Some(tag) = interleaver.pop() {
+                    writer.write_tag(&tag)?;
+                    count += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    while let Some(tag) = interleaver.flush() {
+        writer.write_tag(&tag)?;
+        count += 1;
+    }
+    writer.flush()?;
+    if reader.pending() > 0 {
+        eprintln!(
+            "Warning: {} bytes of a truncated tag ignored",
+            reader.pending()
+        );
+    }
+    match first_late {
+        Some(late) => eprintln!(
+            "Warning: {} tags were out of order by more than {}ms,up to {}ms,the first is {} at {}ms",
+            interleaver.late(),
+            window,
+            most_late,
+            stream_name(late.tag_type),
+            late.timestamp
+        ),
+        None if steps_back > 0 => eprintln!(
+            "Sorted {} tags,timestamps went back {} times in the input",
+            count, steps_back
+        ),
+        None => eprintln!("Sorted {} tags,all were in order", count),
+    }
+    Ok(())
+}
@@ -0,0 +1,277 @@
+//! `extract --keyframes-only`: a small seekable flv of the video keyframes.
+
+use crate::media::{Demuxer, MediaEvent, Metadata, Span, VideoMetadata};
+use crate::scan::{h263_picture_size, vp6_picture_size};
+use crate::{open_input, open_output};
+use flv_parser::amf::{Amf0Value, Pair};
+use flv_parser::flv::{Header, ParseResult, Reader, ScriptTagDataTrait, Tag, TagData, Writer};
+use std::io;
+
+///Which keyframes are kept and how they are timed
+#[derive(Debug, Clone, Copy)]
+pub struct KeyframeOptions {
+    ///Keep every nth keyframe,starting with the first
+    pub every: u32,
+    ///Place the keyframes at this frame rate instead of their original times
+    pub frame_rate: Option<f64>,
+}
+
+///Keyframes object of onMetaData,the index flv players seek with
+fn keyframe_index(times: &[f64], positions: &[u64]) -> Amf0Value {
+    let numbers = |values: Vec<f64>| Amf0Value::Array {
+        entries: values.into_iter().map(Amf0Value::Number).collect(),
+    };
+    Amf0Value::Object {
+        class_name: None,
+        entries: vec![
+            Pair {
+                key: "times".to_string(),
+                value: numbers(times.to_vec()),
+            },
+            Pair {
+                key: "filepositions".to_string(),
+                value: numbers(positions.iter().map(|p| *p as f64).collect()),
+            },
+        ],
+    }
+}
+
+///onMetaData with the keyframe index,positions are those of the keyframe tags
+fn metadata_tag(metadata: &Metadata, times: &[f64], positions: &[u64]) -> Tag {
+    let mut values = match metadata.to_tag().data() {
+        TagData::Script(values) => values.clone(),
+        _ => unreachable!(),
+    };
+    if let Some(Amf0Value::EcmaArray { entries }) = values.get_mut(1) {
+        entries.push(Pair {
+            key: "keyframes".to_string(),
+            value: keyframe_index(times, positions),
+        });
+    }
+    Tag::new(0, TagData::Script(values))
+}
+
+///Picture size of a keyframe of a codec without sequence headers
+fn picture_size(tag: &Tag) -> Option<(u32, u32)> {
+    match tag.video_codec_id() {
+        Some(Tag::VIDEO_CODEC_H263) => h263_picture_size(tag.body()),
+        Some(Tag::VIDEO_CODEC_VP6) => vp6_picture_size(tag.body(), false),
+        Some(Tag::VIDEO_CODEC_VP6_ALPHA) => vp6_picture_size(tag.body(), true),
+        _ => None,
+    }
+}
+
+///Keeps the changed sequence headers and every nth keyframe of the video tags in file order
+struct Selection {
+    every: u32,
+    sequence_header: Option<Vec<u8>>,
+    ///Keyframes seen
+    count: u32,
+    ///Keyframes to skip before the next kept one
+    skip: u32,
+}
+
+impl Selection {
+    fn new(every: u32) -> Self {
+        Selection {
+            every,
+            sequence_header: None,
+            count: 0,
+            skip: 0,
+        }
+    }
+
+    fn keep(&mut self, tag: &Tag) -> bool {
+        if !tag.is_video_tag() {
+            false
+        } else if tag.is_sequence_header() {
+            //repeated sequence headers aren't needed without the frames between them
+            if self.sequence_header.as_deref() == Some(tag.body()) {
+                return false;
+            }
+            self.sequence_header = Some(tag.body().to_vec());
+            true
+        } else if tag.is_keyframe() && tag.packet_type() != Some(Tag::PACKET_TYPE_END_OF_SEQUENCE) {
+            self.count += 1;
+            if self.skip > 0 {
+                self.skip -= 1;
+                return false;
+            }
+            self.skip = self.every - 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+///A tag to write,as the first pass found it
+struct Kept {
+    ///Timestamp in the output
+    timestamp: u32,
+    sequence_header: bool,
+    data_size: u32,
+    ///Size of the tag with its previous tag size
+    size: u64,
+}
+
+///Write the video sequence headers and every nth keyframe of src as an flv without audio,
+///with a keyframe index in onMetaData.
+///A file is read twice,from stdin the kept tags are held in memory.
+pub fn extract_keyframes(src: &str, path: &str, options: KeyframeOptions) -> io::Result<()> {
+    //first pass:times and sizes of the kept tags for the index
+    let mut reader = Reader::new(open_input(src)?);
+    let mut selection = Selection::new(options.every);
+    let mut demuxer = Demuxer::new();
+    let mut codec_id = None;
+    let mut size = None;
+    let mut source_size = None;
+    let mut kept: Vec<Kept> = vec![];
+    let mut buffered: Vec<Tag> = vec![];
+    while let Some(result) = reader.read_next()? {
+        let tag = match result {
+            ParseResult::Tag(tag) => tag,
+            _ => continue,
+        };
+        if let TagData::Script(values) = tag.data() {
+            let (width, height) = (values.width(), values.height());
+            if source_size.is_none() && width.is_finite() && height.is_finite() {
+                source_size = Some((width as u32, height as u32));
+            }
+        }
+        if !tag.is_video_tag() {
+            continue;
+        }
+        for event in demuxer.push(&tag)? {
+            if let MediaEvent::Config(_, config) = event {
+                size = size.or_else(|| config.picture_size());
+            }
+        }
+        if !selection.keep(&tag) {
+            continue;
+        }
+        codec_id = codec_id.or_else(|| tag.video_codec_id());
+        if !tag.is_sequence_header() {
+            size = size.or_else(|| picture_size(&tag));
+        }
+        kept.push(Kept {
+            timestamp: tag.timestamp(),
+            sequence_header: tag.is_sequence_header(),
+            data_size: tag.data_size(),
+            size: tag.tag_size() as u64 + 4,
+        });
+        if src == "-" {
+            buffered.push(tag);
+        }
+    }
+    if reader.pending() > 0 {
+        eprintln!(
+            "Warning: {} bytes of a truncated tag ignored",
+            reader.pending()
+        );
+    }
+    let codec_id = codec_id
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No video keyframes found"))?;
+
+    let mut span = Span::default();
+    for tag in kept.iter_mut().filter(|tag| !tag.sequence_header) {
+        if let Some(frame_rate) = options.frame_rate {
+            let time = span.frames as f64 * 1000.0 / frame_rate;
+            tag.timestamp = time.round() as u32;
+        }
+        span.push(tag.timestamp, tag.data_size);
+    }
+    //sequence headers take the time of the keyframe that follows them
+    let mut next = span.last;
+    for tag in kept.iter_mut().rev() {
+        if tag.sequence_header {
+            tag.timestamp = next;
+        } else {
+            next = tag.timestamp;
+        }
+    }
+
+    let interval = match options.frame_rate {
+        Some(frame_rate) => (1000.0 / frame_rate).round() as u32,
+        None => span.interval(),
+    };
+    let length = match span.first {
+        Some(first) => (span.last - first + interval) as f64,
+        None => 0.0,
+    };
+    let (width, height) = size.or(source_size).unwrap_or((0, 0));
+    let mut metadata = Metadata {
+        duration: (span.last + interval) as f64 / 1000.0,
+        file_size: Some(0),
+        video: Some(VideoMetadata {
+            codec_id,
+            width,
+            height,
+            frame_rate: if length > 0.0 {
+                span.frames as f64 * 1000.0 / length
+            } else {
+                0.0
+            },
+            data_rate: if length > 0.0 {
+                span.bytes as f64 * 8.0 / length
+            } else {
+                0.0
+            },
+        }),
+        audio: None,
+    };
+    //the size of onMetaData doesn't depend on the values,only on how many keyframes it lists
+    let key_times: Vec<f64> = kept
+        .iter()
+        .filter(|tag| !tag.sequence_header)
+        .map(|tag| tag.timestamp as f64 / 1000.0)
+        .collect();
+    let placeholder = metadata_tag(&metadata, &key_times, &vec![0; key_times.len()]);
+    let mut position = Header::HEADER_SIZE as u64 + 4 + placeholder.tag_size() as u64 + 4;
+    let mut positions = vec![];
+    for tag in &kept {
+        if !tag.sequence_header {
+            positions.push(position);
+        }
+        position += tag.size;
+    }
+    metadata.file_size = Some(position);
+
+    //second pass:the kept tags with their new times
+    let mut writer = Writer::new(open_output(path)?);
+    writer.write_header(&Header::new(true, false))?;
+    writer.write_tag(&metadata_tag(&metadata, &key_times, &positions))?;
+    let mut kept = kept.iter();
+    let mut write = |mut tag: Tag| -> io::Result<()> {
+        let kept = kept.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Input changed while reading")
+        })?;
+        tag.set_timestamp(kept.timestamp);
+        if options.frame_rate.is_some() && !kept.sequence_header {
+            //only keyframes are shown,each at its own time
+            tag.set_composition_time(0);
+        }
+        writer.write_tag(&tag)
+    };
+    if src == "-" {
+        for tag in buffered {
+            write(tag)?;
+        }
+    } else {
+        let mut reader = Reader::new(open_input(src)?);
+        let mut selection = Selection::new(options.every);
+        while let Some(result) = reader.read_next()? {
+            if let ParseResult::Tag(tag) = result {
+                if selection.keep(&tag) {
+                    write(tag)?;
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    eprintln!(
+        "Kept {} of {} keyframes,duration {:.3}s",
+        span.frames, selection.count, metadata.duration
+    );
+    Ok(())
+}
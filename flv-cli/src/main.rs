@@ -1,5 +1,30 @@
-use clap::{App, Arg, SubCommand};
-use flv_parser::flv::{Header, ParseResult, Parser, ScriptTagDataTrait, Segment, TagData};
+mod avi;
+mod concat;
+mod cut;
+mod dash;
+mod extract;
+mod hls;
+mod interleave;
+mod keyframes;
+mod media;
+mod merge;
+mod mkv;
+mod mp4;
+mod mp4_reader;
+mod mux;
+mod ogg;
+mod remux;
+mod repair;
+mod report;
+mod retime;
+mod scan;
+mod split;
+mod ts;
+mod ts_reader;
+
+use clap::{App, Arg, ArgGroup, SubCommand};
+use flv_parser::flv::{ParseResult, Parser, ScriptTagDataTrait, TimestampFilterOptions};
+use scan::Scan;
 use std::{
     fs::File,
     io::{self, BufWriter, Read, Write},
@@ -12,13 +37,29 @@ fn main() {
         .author("Shell asypost@gmail.com")
         .arg(
             Arg::with_name("FILE")
-                .help("flv file,- for pipe")
-                .required(true),
+                .help("flv file,- for pipe"),
         )
         .subcommand(
             SubCommand::with_name("info")
                 .version("0.1")
-                .about("Show flv file metadata"),
+                .about("Show flv file metadata")
+                .arg(
+                    Arg::with_name("scan")
+                        .short("-s")
+                        .long("--scan")
+                        .help("walk the whole file and compare metadata with measured values"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("--json")
+                        .conflicts_with("yaml")
+                        .help("print a machine-readable json report,walks the whole file like --scan"),
+                )
+                .arg(
+                    Arg::with_name("yaml")
+                        .long("--yaml")
+                        .help("print a machine-readable yaml report,walks the whole file like --scan"),
+                ),
         )
         .subcommand(
             SubCommand::with_name("extract")
@@ -29,9 +70,209 @@ fn main() {
                         .short("-t")
                         .long("--type")
                         .takes_value(true)
-                        .required(true)
+                        .required_unless_one(&["format", "keyframes-only"])
                         .help("audio,video or all"),
                 )
+                .arg(
+                    Arg::with_name("format")
+                        .long("--format")
+                        .takes_value(true)
+                        .possible_values(&[
+                            "h264", "h265", "aac", "mp3", "wav", "ogg", "raw", "avi",
+                        ])
+                        .conflicts_with("type")
+                        .help("write an elementary stream instead of flv"),
+                )
+                .arg(
+                    Arg::with_name("keyframes-only")
+                        .long("--keyframes-only")
+                        .conflicts_with_all(&["type", "format"])
+                        .help("write only the video keyframes and sequence headers as a seekable flv"),
+                )
+                .arg(
+                    Arg::with_name("every")
+                        .long("--every")
+                        .takes_value(true)
+                        .requires("keyframes-only")
+                        .help("keep every nth keyframe [default: 1]"),
+                )
+                .arg(
+                    Arg::with_name("frame-rate")
+                        .long("--frame-rate")
+                        .takes_value(true)
+                        .requires("keyframes-only")
+                        .help("show the keyframes at this rate instead of their original times"),
+                )
+                .arg(
+                    Arg::with_name("xing")
+                        .long("--xing")
+                        .requires("format")
+                        .help("start mp3 with a Xing/Info header holding the frame count"),
+                )
+                .arg(
+                    Arg::with_name("pcm-big-endian")
+                        .long("--pcm-big-endian")
+                        .requires("format")
+                        .help("read platform endian pcm as big endian for wav"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("output path,- for stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("remux")
+                .version("0.1")
+                .about("Convert between flv and other containers without re-encoding")
+                .arg(
+                    Arg::with_name("to")
+                        .long("--to")
+                        .takes_value(true)
+                        .required_unless("from")
+                        .possible_values(&["mp4", "ts", "mkv"])
+                        .help("output container"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("--from")
+                        .takes_value(true)
+                        .conflicts_with("to")
+                        .possible_values(&["mp4", "ts"])
+                        .help("input container,converted to flv"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("output path,- for stdout"),
+                )
+                .arg(
+                    Arg::with_name("fragmented")
+                        .short("-f")
+                        .long("--fragmented")
+                        .help("write fragmented mp4,streamed as the input is read"),
+                )
+                .arg(
+                    Arg::with_name("fragment-duration")
+                        .long("--fragment-duration")
+                        .takes_value(true)
+                        .default_value("2000")
+                        .help("minimum fragment duration in milliseconds,fragments start at keyframes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("hls")
+                .version("0.1")
+                .about("Cut flv into HLS segments and write an m3u8 playlist")
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("directory of the playlist and segments"),
+                )
+                .arg(
+                    Arg::with_name("segment-type")
+                        .long("--segment-type")
+                        .takes_value(true)
+                        .possible_values(&["ts", "fmp4"])
+                        .default_value("ts")
+                        .help("segment container"),
+                )
+                .arg(
+                    Arg::with_name("target-duration")
+                        .long("--target-duration")
+                        .takes_value(true)
+                        .default_value("6")
+                        .help("target segment duration in seconds,segments start at keyframes"),
+                )
+                .arg(
+                    Arg::with_name("live")
+                        .long("--live")
+                        .help("sliding window playlist updated after each segment"),
+                )
+                .arg(
+                    Arg::with_name("list-size")
+                        .long("--list-size")
+                        .takes_value(true)
+                        .default_value("6")
+                        .help("count of segments in a live playlist"),
+                )
+                .arg(
+                    Arg::with_name("delete-segments")
+                        .long("--delete-segments")
+                        .requires("live")
+                        .help("delete segments some time after they left a live playlist"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dash")
+                .version("0.1")
+                .about("Package flv as MPEG-DASH with CMAF segments and an mpd manifest")
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("directory of the manifest and segments"),
+                )
+                .arg(
+                    Arg::with_name("segment-duration")
+                        .long("--segment-duration")
+                        .takes_value(true)
+                        .default_value("4")
+                        .help("target segment duration in seconds,segments start at keyframes"),
+                )
+                .arg(
+                    Arg::with_name("live")
+                        .long("--live")
+                        .help("dynamic manifest updated after each segment"),
+                )
+                .arg(
+                    Arg::with_name("window-size")
+                        .long("--window-size")
+                        .takes_value(true)
+                        .default_value("6")
+                        .help("count of segments in a live manifest"),
+                )
+                .arg(
+                    Arg::with_name("delete-segments")
+                        .long("--delete-segments")
+                        .requires("live")
+                        .help("delete segments some time after they left a live manifest"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mux")
+                .version("0.1")
+                .about("Build flv from H.264 Annex B and AAC ADTS elementary streams")
+                .arg(
+                    Arg::with_name("video")
+                        .long("--video")
+                        .takes_value(true)
+                        .required_unless("audio")
+                        .help("H.264 Annex B file"),
+                )
+                .arg(
+                    Arg::with_name("audio")
+                        .long("--audio")
+                        .takes_value(true)
+                        .help("AAC ADTS file"),
+                )
+                .arg(
+                    Arg::with_name("fps")
+                        .long("--fps")
+                        .takes_value(true)
+                        .help("video frame rate,read from the sps timing info if absent"),
+                )
                 .arg(
                     Arg::with_name("output")
                         .short("-o")
@@ -41,42 +282,554 @@ fn main() {
                         .help("output path,- for stdout"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .version("0.1")
+                .about("Combine the video of one flv with the audio of another")
+                .arg(
+                    Arg::with_name("video")
+                        .long("--video")
+                        .takes_value(true)
+                        .required(true)
+                        .help("flv file the video is taken from"),
+                )
+                .arg(
+                    Arg::with_name("audio")
+                        .long("--audio")
+                        .takes_value(true)
+                        .required(true)
+                        .help("flv file the audio is taken from"),
+                )
+                .arg(
+                    Arg::with_name("audio-offset")
+                        .long("--audio-offset")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .help("milliseconds added to audio timestamps,positive values delay the audio"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("output path,- for stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("concat")
+                .version("0.1")
+                .about("Join flv files,each continuing the timestamps of the previous one")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .multiple(true)
+                        .required(true)
+                        .help("flv files in playing order"),
+                )
+                .arg(
+                    Arg::with_name("gap")
+                        .long("--gap")
+                        .takes_value(true)
+                        .help("milliseconds after the last frame of a stream,its average frame interval if absent"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("output path,- for stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cut")
+                .version("0.1")
+                .about("Copy a time range,starting at the video keyframe before it")
+                .arg(
+                    Arg::with_name("start")
+                        .long("--start")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("start time like 90,01:30 or 00:01:30.5"),
+                )
+                .arg(
+                    Arg::with_name("end")
+                        .long("--end")
+                        .takes_value(true)
+                        .help("end time,the end of input if absent"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("output path,- for stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fix-timestamps")
+                .version("0.1")
+                .about("Make timestamps continuous across encoder restarts and jumps")
+                .arg(
+                    Arg::with_name("max-backward")
+                        .long("--max-backward")
+                        .takes_value(true)
+                        .default_value("1000")
+                        .help("milliseconds a stream may go back before it is a discontinuity,smaller steps are clamped"),
+                )
+                .arg(
+                    Arg::with_name("max-gap")
+                        .long("--max-gap")
+                        .takes_value(true)
+                        .default_value("10000")
+                        .help("milliseconds a stream may jump forward before it is a discontinuity"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("output path,- for stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("retime")
+                .version("0.1")
+                .about("Rebase,shift or scale timestamps,or move audio against video")
+                .arg(
+                    Arg::with_name("rebase")
+                        .long("--rebase")
+                        .help("move the first audio or video tag to 0"),
+                )
+                .arg(
+                    Arg::with_name("shift")
+                        .long("--shift")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .help("milliseconds added to every timestamp,may be negative"),
+                )
+                .arg(
+                    Arg::with_name("audio-offset")
+                        .long("--audio-offset")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .help("milliseconds added to audio timestamps,positive values delay the audio"),
+                )
+                .arg(
+                    Arg::with_name("speed")
+                        .long("--speed")
+                        .takes_value(true)
+                        .help("playback speed like 2.0,video timestamps are divided by it"),
+                )
+                .arg(
+                    Arg::with_name("audio")
+                        .long("--audio")
+                        .takes_value(true)
+                        .possible_values(&["drop", "keep"])
+                        .requires("speed")
+                        .help("drop audio or keep it unscaled when changing the speed [default: drop]"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("output path,- for stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("interleave")
+                .version("0.1")
+                .about("Sort tags by timestamp,so audio and video alternate")
+                .arg(
+                    Arg::with_name("window")
+                        .long("--window")
+                        .takes_value(true)
+                        .default_value("10000")
+                        .help("milliseconds a tag may be out of order"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("output path,- for stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("repair")
+                .version("0.1")
+                .about("Rewrite a damaged flv and report every fix")
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("output path,- for stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("split")
+                .version("0.1")
+                .about("Split into numbered self-contained files at keyframes")
+                .arg(
+                    Arg::with_name("duration")
+                        .long("--duration")
+                        .takes_value(true)
+                        .help("length of a file like 10m,90s or 00:10:00"),
+                )
+                .arg(
+                    Arg::with_name("size")
+                        .long("--size")
+                        .takes_value(true)
+                        .help("largest size of a file like 500M or 2G"),
+                )
+                .arg(
+                    Arg::with_name("gops")
+                        .long("--gops")
+                        .takes_value(true)
+                        .help("groups of pictures in a file"),
+                )
+                .arg(
+                    Arg::with_name("on-change")
+                        .long("--on-change")
+                        .help("also start a file when the codec or decoder configuration changes"),
+                )
+                .group(ArgGroup::with_name("rule").args(&["duration", "size", "gops"]))
+                .arg(
+                    Arg::with_name("output")
+                        .short("-o")
+                        .long("--out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("file name template,%d or %03d is replaced by the number from 1"),
+                ),
+        )
         .get_matches();
 
+    if let Some(args) = matches.subcommand_matches("merge") {
+        let audio_offset = match args.value_of("audio-offset").map(str::parse::<i64>) {
+            Some(Ok(offset)) => offset,
+            None => 0,
+            _ => {
+                println!("{}", args.usage());
+                return;
+            }
+        };
+        report_error(merge::merge(
+            args.value_of("video").unwrap(),
+            args.value_of("audio").unwrap(),
+            args.value_of("output").unwrap(),
+            audio_offset,
+        ));
+        return;
+    }
+
+    if let Some(args) = matches.subcommand_matches("concat") {
+        let gap = match args.value_of("gap").map(|gap| gap.parse::<u32>()) {
+            Some(Ok(gap)) => Some(gap),
+            None => None,
+            _ => {
+                println!("{}", args.usage());
+                return;
+            }
+        };
+        let inputs: Vec<&str> = args.values_of("INPUT").unwrap().collect();
+        report_error(concat::concat(
+            &inputs,
+            args.value_of("output").unwrap(),
+            gap,
+        ));
+        return;
+    }
+
+    if let Some(args) = matches.subcommand_matches("mux") {
+        let frame_rate = match args.value_of("fps").map(|fps| fps.parse::<f64>()) {
+            Some(Ok(fps)) if fps > 0.0 => Some(fps),
+            None => None,
+            _ => {
+                println!("{}", args.usage());
+                return;
+            }
+        };
+        report_error(mux::mux(
+            args.value_of("video"),
+            args.value_of("audio"),
+            args.value_of("output").unwrap(),
+            frame_rate,
+        ));
+        return;
+    }
+
     if let Some(file) = matches.value_of("FILE") {
-        if let Some(_) = matches.subcommand_matches("info") {
-            show_flv_info(file).expect("Read flv file error");
+        if let Some(args) = matches.subcommand_matches("info") {
+            let format = if args.is_present("json") {
+                Some("json")
+            } else if args.is_present("yaml") {
+                Some("yaml")
+            } else {
+                None
+            };
+            //reports always carry the scan statistics
+            let full_scan = args.is_present("scan") || format.is_some();
+            show_flv_info(file, full_scan, format).expect("Read flv file error");
         } else if let Some(args) = matches.subcommand_matches("extract") {
-            let tp = args.value_of("type").unwrap();
             let out = args.value_of("output").unwrap();
+            if args.is_present("keyframes-only") {
+                let every = args.value_of("every").map(str::parse::<u32>);
+                let frame_rate = args.value_of("frame-rate").map(str::parse::<f64>);
+                let positive = |rate: Option<f64>| rate.is_none_or(|r| r.is_finite() && r > 0.0);
+                match (every.transpose(), frame_rate.transpose()) {
+                    (Ok(every), Ok(frame_rate)) if every != Some(0) && positive(frame_rate) => {
+                        report_error(keyframes::extract_keyframes(
+                            file,
+                            out,
+                            keyframes::KeyframeOptions {
+                                every: every.unwrap_or(1),
+                                frame_rate,
+                            },
+                        ))
+                    }
+                    _ => println!("{}", args.usage()),
+                }
+                return;
+            }
+            if let Some(format) = args.value_of("format") {
+                let result = match format {
+                    "aac" => extract::extract_aac(file, out),
+                    "mp3" => extract::extract_mp3(file, out, args.is_present("xing")),
+                    "wav" => extract::extract_wav(file, out, args.is_present("pcm-big-endian")),
+                    "ogg" => extract::extract_speex(file, out),
+                    "raw" => extract::extract_raw_audio(file, out),
+                    "avi" => extract::extract_avi(file, out),
+                    _ => extract::extract_video(file, out, format),
+                };
+                report_error(result);
+                return;
+            }
+            let tp = args.value_of("type").unwrap();
             if tp != "audio" && tp != "video" && tp != "all" {
                 println!("{}", args.usage());
             } else {
-                if let Err(e) = extract(file, tp, out) {
-                    if e.kind() != io::ErrorKind::BrokenPipe {
-                        eprintln!("Error: {}", e);
-                    }
+                report_error(extract(file, tp, out));
+            }
+        } else if let Some(args) = matches.subcommand_matches("remux") {
+            let out = args.value_of("output").unwrap();
+            let fragment_duration = match args.value_of("fragment-duration").unwrap().parse() {
+                Ok(duration) => duration,
+                Err(_) => {
+                    println!("{}", args.usage());
+                    return;
+                }
+            };
+            if let Some(from) = args.value_of("from") {
+                report_error(match from {
+                    "ts" => remux::remux_from_ts(file, out),
+                    _ => remux::remux_from_mp4(file, out),
+                });
+                return;
+            }
+            let result = match args.value_of("to").unwrap() {
+                "ts" => remux::remux_ts(file, out),
+                "mkv" => remux::remux_mkv(file, out),
+                _ => remux::remux_mp4(file, out, args.is_present("fragmented"), fragment_duration),
+            };
+            report_error(result);
+        } else if let Some(args) = matches.subcommand_matches("cut") {
+            let start = cut::parse_time(args.value_of("start").unwrap());
+            let end = args.value_of("end").map(cut::parse_time);
+            match (start, end) {
+                (Some(start), None) => report_error(cut::cut(
+                    file,
+                    args.value_of("output").unwrap(),
+                    start,
+                    None,
+                )),
+                (Some(start), Some(Some(end))) if end > start => report_error(cut::cut(
+                    file,
+                    args.value_of("output").unwrap(),
+                    start,
+                    Some(end),
+                )),
+                _ => println!("{}", args.usage()),
+            }
+        } else if let Some(args) = matches.subcommand_matches("fix-timestamps") {
+            let max_backward = args.value_of("max-backward").unwrap().parse::<u32>();
+            let max_gap = args.value_of("max-gap").unwrap().parse::<u32>();
+            match (max_backward, max_gap) {
+                (Ok(max_backward), Ok(max_gap)) => report_error(retime::fix_timestamps(
+                    file,
+                    args.value_of("output").unwrap(),
+                    TimestampFilterOptions {
+                        max_backward,
+                        max_gap,
+                    },
+                )),
+                _ => println!("{}", args.usage()),
+            }
+        } else if let Some(args) = matches.subcommand_matches("retime") {
+            let shift = args.value_of("shift").map(str::parse::<i64>);
+            let audio_offset = args.value_of("audio-offset").map(str::parse::<i64>);
+            let speed = args.value_of("speed").map(|speed| {
+                speed
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|s| s.is_finite() && *s > 0.0)
+            });
+            match (shift.transpose(), audio_offset.transpose(), speed) {
+                (Ok(shift), Ok(audio_offset), None | Some(Some(_))) => {
+                    report_error(retime::retime(
+                        file,
+                        args.value_of("output").unwrap(),
+                        retime::RetimeOptions {
+                            rebase: args.is_present("rebase"),
+                            shift: shift.unwrap_or(0),
+                            audio_offset: audio_offset.unwrap_or(0),
+                            speed: speed.flatten().unwrap_or(1.0),
+                            keep_audio: speed.is_none() || args.value_of("audio") == Some("keep"),
+                        },
+                    ))
                 }
+                _ => println!("{}", args.usage()),
+            }
+        } else if let Some(args) = matches.subcommand_matches("interleave") {
+            match args.value_of("window").unwrap().parse::<u32>() {
+                Ok(window) => report_error(interleave::interleave(
+                    file,
+                    args.value_of("output").unwrap(),
+                    window,
+                )),
+                Err(_) => println!("{}", args.usage()),
+            }
+        } else if let Some(args) = matches.subcommand_matches("repair") {
+            report_error(repair::repair(file, args.value_of("output").unwrap()));
+        } else if let Some(args) = matches.subcommand_matches("split") {
+            let rule = if let Some(duration) = args.value_of("duration") {
+                Some(
+                    split::parse_duration(duration)
+                        .filter(|duration| *duration > 0)
+                        .map(split::SplitRule::Duration),
+                )
+            } else if let Some(size) = args.value_of("size") {
+                Some(split::parse_size(size).map(split::SplitRule::Size))
+            } else {
+                args.value_of("gops").map(|gops| {
+                    gops.parse()
+                        .ok()
+                        .filter(|gops| *gops > 0)
+                        .map(split::SplitRule::Gops)
+                })
+            };
+            let on_change = args.is_present("on-change");
+            match rule {
+                Some(Some(rule)) => report_error(split::split(
+                    file,
+                    args.value_of("output").unwrap(),
+                    Some(rule),
+                    on_change,
+                )),
+                None if on_change => report_error(split::split(
+                    file,
+                    args.value_of("output").unwrap(),
+                    None,
+                    on_change,
+                )),
+                _ => println!("{}", args.usage()),
             }
+        } else if let Some(args) = matches.subcommand_matches("hls") {
+            let target_duration = args.value_of("target-duration").unwrap().parse::<f64>();
+            let list_size = args.value_of("list-size").unwrap().parse::<usize>();
+            let (target_duration, list_size) = match (target_duration, list_size) {
+                (Ok(duration), Ok(size)) if duration > 0.0 && size > 0 => (duration, size),
+                _ => {
+                    println!("{}", args.usage());
+                    return;
+                }
+            };
+            let options = hls::HlsOptions {
+                format: if args.value_of("segment-type") == Some("fmp4") {
+                    hls::SegmentFormat::Fmp4
+                } else {
+                    hls::SegmentFormat::Ts
+                },
+                target_duration: (target_duration * 1000.0) as u32,
+                live: args.is_present("live"),
+                list_size,
+                delete_segments: args.is_present("delete-segments"),
+            };
+            report_error(hls::segment(
+                file,
+                args.value_of("output").unwrap(),
+                options,
+            ));
+        } else if let Some(args) = matches.subcommand_matches("dash") {
+            let segment_duration = args.value_of("segment-duration").unwrap().parse::<f64>();
+            let window_size = args.value_of("window-size").unwrap().parse::<usize>();
+            let (segment_duration, window_size) = match (segment_duration, window_size) {
+                (Ok(duration), Ok(size)) if duration > 0.0 && size > 0 => (duration, size),
+                _ => {
+                    println!("{}", args.usage());
+                    return;
+                }
+            };
+            let options = dash::DashOptions {
+                segment_duration: (segment_duration * 1000.0) as u32,
+                live: args.is_present("live"),
+                window_size,
+                delete_segments: args.is_present("delete-segments"),
+            };
+            report_error(dash::package(
+                file,
+                args.value_of("output").unwrap(),
+                options,
+            ));
         }
     } else {
         println!("{}", matches.usage());
     }
 }
 
-fn extract(src: &str, tp: &str, path: &str) -> io::Result<()> {
-    let stdin = io::stdin();
-    let mut fp: Box<dyn Read> = if src == "-" {
-        Box::new(stdin.lock())
+///Print the error of a command,a closed stdout pipe is not an error
+fn report_error(result: io::Result<()>) {
+    if let Err(e) = result {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("Error: {}", e);
+        }
+    }
+}
+
+///Open a flv file,- for stdin
+fn open_input(src: &str) -> io::Result<Box<dyn Read>> {
+    if src == "-" {
+        Ok(Box::new(io::stdin().lock()))
     } else {
-        Box::new(File::open(src)?)
-    };
-    let stdout = io::stdout();
-    let mut ofp: BufWriter<Box<dyn io::Write>> = if path == "-" {
-        BufWriter::with_capacity(4 * 1024, Box::new(stdout.lock()))
+        Ok(Box::new(File::open(src)?))
+    }
+}
+
+///Create an output file,- for stdout
+fn open_output(path: &str) -> io::Result<BufWriter<Box<dyn Write>>> {
+    if path == "-" {
+        Ok(BufWriter::with_capacity(
+            4 * 1024,
+            Box::new(io::stdout().lock()),
+        ))
     } else {
-        BufWriter::with_capacity(4 * 1024, Box::new(File::create(path)?))
-    };
+        Ok(BufWriter::with_capacity(
+            4 * 1024,
+            Box::new(File::create(path)?),
+        ))
+    }
+}
+
+fn extract(src: &str, tp: &str, path: &str) -> io::Result<()> {
+    let mut fp = open_input(src)?;
+    let mut ofp = open_output(path)?;
     let mut parser = Parser::new();
     let mut buffer: Vec<u8> = vec![0x00; 100 * 1024];
     loop {
@@ -98,13 +851,10 @@ fn extract(src: &str, tp: &str, path: &str) -> io::Result<()> {
                 }
                 ParseResult::PreTagSize(_) => {}
                 ParseResult::Tag(tag) => {
-                    if (tp == "video" || tp == "all") && tag.is_video_tag() {
-                        ofp.write_all(&tag.into_bytes())?;
-                        ofp.write_all(&tag.tag_size().to_be_bytes())?;
-                    } else if (tp == "audio" || tp == "all") && tag.is_audio_tag() {
-                        ofp.write_all(&tag.into_bytes())?;
-                        ofp.write_all(&tag.tag_size().to_be_bytes())?;
-                    } else if tag.is_script_tag() {
+                    if ((tp == "video" || tp == "all") && tag.is_video_tag())
+                        || ((tp == "audio" || tp == "all") && tag.is_audio_tag())
+                        || tag.is_script_tag()
+                    {
                         ofp.write_all(&tag.into_bytes())?;
                         ofp.write_all(&tag.tag_size().to_be_bytes())?;
                     }
@@ -113,12 +863,12 @@ fn extract(src: &str, tp: &str, path: &str) -> io::Result<()> {
         }
     }
     ofp.flush()?;
-    return Ok(());
+    Ok(())
 }
 
 fn video_codec_name(id: &f64) -> String {
     let _id = *id as i32;
-    return match _id {
+    match _id {
         1 => "JPEG",
         2 => "H.263",
         3 => "Screen video",
@@ -126,14 +876,15 @@ fn video_codec_name(id: &f64) -> String {
         5 => "On2 VP6 with alpha channel",
         6 => "Screen video version 2",
         7 => "AVC",
+        12 => "HEVC",
         _ => "Unknown",
     }
-    .to_string();
+    .to_string()
 }
 
 fn audio_codec_name(id: &f64) -> String {
     let _id = *id as i32;
-    return match _id {
+    match _id {
         0 => "Linear PCM, platform endian",
         1 => "ADPCM",
         2 => "MP3",
@@ -150,52 +901,260 @@ fn audio_codec_name(id: &f64) -> String {
         15 => "Device-specific sound",
         _ => "Unknown",
     }
-    .to_string();
+    .to_string()
 }
 
-fn show_flv_info(file: &str) -> io::Result<()> {
-    let stdin = io::stdin();
-    let mut fp: Box<dyn Read> = if file == "-" {
-        Box::new(stdin.lock())
-    } else {
-        Box::new(File::open(file)?)
+fn video_profile_name(codec_id: u8, profile: u8, level: u8) -> String {
+    if codec_id == 12 {
+        let name = match profile {
+            1 => "Main",
+            2 => "Main 10",
+            3 => "Main Still Picture",
+            4 => "Range Extensions",
+            _ => "Unknown",
+        };
+        return format!("{}, level {:0.1}", name, level as f64 / 30.0);
+    }
+    let name = match profile {
+        66 => "Baseline",
+        77 => "Main",
+        88 => "Extended",
+        100 => "High",
+        110 => "High 10",
+        122 => "High 4:2:2",
+        244 => "High 4:4:4",
+        _ => "Unknown",
     };
-    let header = Header::from_reader(&mut fp)?;
-    let mut script: Option<Segment> = Option::None;
-    loop {
-        match Segment::from_reader(&mut fp) {
-            Ok(segment) => {
-                if segment.has_script_tag() {
-                    script.replace(segment);
-                    break;
-                }
-            }
-            Err(e) => {
-                println!("Decode tag failed:{}", e);
-                break;
-            }
+    format!("{}, level {:0.1}", name, level as f64 / 10.0)
+}
+
+///Format a metadata number,metadata fields absent are shown as unknown
+fn format_number(value: f64, precision: usize) -> String {
+    if value.is_finite() {
+        format!("{:0.*}", precision, value)
+    } else {
+        "unknown".to_string()
+    }
+}
+
+fn show_flv_info(file: &str, full_scan: bool, format: Option<&str>) -> io::Result<()> {
+    let scan = Scan::from_reader(open_input(file)?, full_scan);
+    let header = scan
+        .header
+        .as_ref()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Missing flv header"))?;
+    match format {
+        Some("json") => {
+            let report = report::info(file, &scan);
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
         }
+        Some("yaml") => {
+            print!("{}", report::to_yaml(&report::info(file, &scan)));
+            return Ok(());
+        }
+        _ => {}
     }
     println!("version: {}", header.version());
     println!("video: {}", if header.has_video() { "yes" } else { "no" });
     println!("audio: {}", if header.has_audio() { "yes" } else { "no" });
-    if script.is_some() {
-        let tag_seg = script.unwrap();
-        let tag_data = tag_seg.tag().as_ref().unwrap().data();
-        if let TagData::Script(values) = tag_data {
-            println!("duration: {:0.3}s", values.duration());
-            println!("width : {:0.0}", values.width());
-            println!("height: {:0.0}", values.height());
-            println!("fps: {:0.0}", values.framerate());
+    if let Some(e) = &scan.error {
+        println!("Decode tag failed:{}", e);
+    }
+    if full_scan {
+        print_scan_info(&scan);
+    } else if let Some(values) = &scan.metadata {
+        println!("duration: {}s", format_number(values.duration(), 3));
+        println!("width : {}", format_number(values.width(), 0));
+        println!("height: {}", format_number(values.height(), 0));
+        println!("fps: {}", format_number(values.framerate(), 0));
+        println!(
+            "video codec: {}",
+            video_codec_name(&values.video_codec_id())
+        );
+        println!(
+            "audio codec: {}",
+            audio_codec_name(&values.audio_codec_id())
+        );
+    }
+    Ok(())
+}
+
+///A row of declared and measured values,marked if they differ
+struct CompareRow {
+    name: &'static str,
+    declared: String,
+    measured: String,
+    differs: bool,
+}
+
+impl CompareRow {
+    fn number(
+        name: &'static str,
+        declared: f64,
+        measured: Option<f64>,
+        precision: usize,
+        tolerance: f64,
+    ) -> Self {
+        let differs = match measured {
+            Some(measured) if declared.is_finite() => {
+                (declared - measured).abs() > tolerance * measured.abs().max(1.0)
+            }
+            _ => false,
+        };
+        Self {
+            name,
+            declared: format_number(declared, precision),
+            measured: format_number(measured.unwrap_or(f64::INFINITY), precision),
+            differs,
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "{} {:<20}{:<20}{}",
+            if self.differs { "*" } else { " " },
+            self.name,
+            self.declared,
+            self.measured
+        );
+    }
+}
+
+fn print_scan_info(scan: &Scan) {
+    let metadata = scan.metadata.clone().unwrap_or_default();
+    let video = &scan.video_params;
+    let audio = &scan.audio_params;
+    let has_video = scan.video.tags > 0;
+    let has_audio = scan.audio.tags > 0;
+    let mut rows = vec![CompareRow::number(
+        "duration(s)",
+        metadata.duration(),
+        Some(scan.duration()),
+        3,
+        0.01,
+    )];
+    if has_video {
+        let codec = video.codec_id.map(|id| id as f64);
+        rows.push(CompareRow::number(
+            "width",
+            metadata.width(),
+            video.width.map(|w| w as f64),
+            0,
+            0.0,
+        ));
+        rows.push(CompareRow::number(
+            "height",
+            metadata.height(),
+            video.height.map(|h| h as f64),
+            0,
+            0.0,
+        ));
+        rows.push(CompareRow::number(
+            "fps",
+            metadata.framerate(),
+            Some(scan.video.frame_rate()),
+            2,
+            0.05,
+        ));
+        rows.push(CompareRow::number(
+            "video bitrate(kbps)",
+            metadata.video_data_rate(),
+            Some(scan.video.average_bitrate()),
+            0,
+            0.2,
+        ));
+        rows.push(CompareRow {
+            name: "video codec",
+            declared: video_codec_name(&metadata.video_codec_id()),
+            measured: video_codec_name(&codec.unwrap_or(f64::INFINITY)),
+            differs: CompareRow::number("", metadata.video_codec_id(), codec, 0, 0.0).differs,
+        });
+    }
+    if has_audio {
+        let codec = audio.sound_format.map(|id| id as f64);
+        rows.push(CompareRow {
+            name: "audio codec",
+            declared: audio_codec_name(&metadata.audio_codec_id()),
+            measured: audio_codec_name(&codec.unwrap_or(f64::INFINITY)),
+            differs: CompareRow::number("", metadata.audio_codec_id(), codec, 0, 0.0).differs,
+        });
+        rows.push(CompareRow::number(
+            "sample rate(Hz)",
+            metadata.audio_sample_rate(),
+            audio.sample_rate.map(|r| r as f64),
+            0,
+            0.0,
+        ));
+        rows.push(CompareRow::number(
+            "audio bitrate(kbps)",
+            metadata.audio_data_rate(),
+            Some(scan.audio.average_bitrate()),
+            0,
+            0.2,
+        ));
+        let declared_stereo = metadata.stereo();
+        let measured_stereo = audio.channels.map(|c| c > 1);
+        rows.push(CompareRow {
+            name: "stereo",
+            declared: declared_stereo
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            measured: audio
+                .channels
+                .map(|c| format!("{} ({} channels)", c > 1, c))
+                .unwrap_or_else(|| "unknown".to_string()),
+            differs: declared_stereo.is_some()
+                && measured_stereo.is_some()
+                && declared_stereo != measured_stereo,
+        });
+    }
+
+    println!();
+    println!("  {:<20}{:<20}measured", "", "declared");
+    for row in &rows {
+        row.print();
+    }
+    if rows.iter().any(|row| row.differs) {
+        println!("(* declared value differs from measured)");
+    }
+
+    println!();
+    println!(
+        "video tags: {} (keyframes: {})",
+        scan.video.tags, scan.keyframes
+    );
+    println!("audio tags: {}", scan.audio.tags);
+    println!("script tags: {}", scan.script_tags);
+    if has_video {
+        if let (Some(profile), Some(level)) = (video.profile, video.level) {
             println!(
-                "video codec: {}",
-                video_codec_name(&values.video_codec_id())
+                "video profile: {}",
+                video_profile_name(video.codec_id.unwrap_or(0), profile, level)
             );
+        }
+        println!(
+            "video bitrate: avg {:0.1}kbps, peak {:0.1}kbps",
+            scan.video.average_bitrate(),
+            scan.video.peak_bitrate()
+        );
+        if let (Some(interval), Some((min, max))) =
+            (scan.keyframe_interval(), scan.keyframe_interval_range())
+        {
             println!(
-                "audio codec: {}",
-                audio_codec_name(&values.audio_codec_id())
+                "keyframe interval: avg {:0.3}s, min {:0.3}s, max {:0.3}s",
+                interval, min, max
             );
         }
     }
-    return Ok(());
+    if has_audio {
+        if let Some(size) = audio.sample_size {
+            println!("audio sample size: {}bits", size);
+        }
+        println!(
+            "audio bitrate: avg {:0.1}kbps, peak {:0.1}kbps",
+            scan.audio.average_bitrate(),
+            scan.audio.peak_bitrate()
+        );
+    }
 }
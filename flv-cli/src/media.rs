@@ -0,0 +1,639 @@
+//! Codec level view of flv tags,shared by the remux and extract commands.
+
+use flv_parser::amf::{Amf0Value, Pair};
+use flv_parser::flv::{
+    aac::AudioSpecificConfig,
+    avc::{self, AvcDecoderConfig},
+    hevc::{self, HevcDecoderConfig},
+    mp3::Mp3FrameHeader,
+    Tag, TagData,
+};
+use std::io;
+
+///Error of input that can't be decoded or converted
+pub fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackKind {
+    Video,
+    Audio,
+}
+
+impl TrackKind {
+    ///Kind of a video or audio tag,None for script tags
+    pub fn of(tag: &Tag) -> Option<TrackKind> {
+        if tag.is_video_tag() {
+            Some(TrackKind::Video)
+        } else if tag.is_audio_tag() {
+            Some(TrackKind::Audio)
+        } else {
+            None
+        }
+    }
+
+    ///Position in arrays holding one value per stream,video first
+    pub fn index(self) -> usize {
+        match self {
+            TrackKind::Video => 0,
+            TrackKind::Audio => 1,
+        }
+    }
+}
+
+///Position of the stream of a video or audio tag,see TrackKind::index
+pub fn stream_index(tag: &Tag) -> Option<usize> {
+    TrackKind::of(tag).map(TrackKind::index)
+}
+
+///Whether the values of a script tag are onMetaData
+pub fn is_metadata(values: &[Amf0Value]) -> bool {
+    matches!(values.first(), Some(Amf0Value::String(name)) if name == "onMetaData")
+}
+
+///Decoder configuration of a stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodecConfig {
+    Avc(AvcDecoderConfig),
+    Hevc(HevcDecoderConfig),
+    Aac(AudioSpecificConfig),
+    ///Mp3 has no sequence header,the header of the first frame is used
+    Mp3(Mp3FrameHeader),
+}
+
+impl CodecConfig {
+    ///Short description for reports
+    pub fn description(&self) -> String {
+        match (self.picture_size(), self.audio_format()) {
+            (Some((width, height)), _) => format!("{} {}x{}", self.codec_string(), width, height),
+            (_, Some((sample_rate, channels))) => format!(
+                "{} {}Hz {} channels",
+                self.codec_string(),
+                sample_rate,
+                channels
+            ),
+            _ => self.codec_string(),
+        }
+    }
+
+    ///Picture size from the sps
+    pub fn picture_size(&self) -> Option<(u32, u32)> {
+        match self {
+            CodecConfig::Avc(config) => config.parse_sps().ok().map(|s| (s.width(), s.height())),
+            CodecConfig::Hevc(config) => config.parse_sps().ok().map(|s| (s.width(), s.height())),
+            _ => None,
+        }
+    }
+
+    ///Sample rate and count of channels
+    pub fn audio_format(&self) -> Option<(u32, u8)> {
+        match self {
+            CodecConfig::Aac(config) => Some((config.sampling_frequency(), config.channels())),
+            CodecConfig::Mp3(header) => Some((header.sample_rate(), header.channels())),
+            _ => None,
+        }
+    }
+
+    ///Codec parameter of mime types as in RFC 6381,like avc1.64001f or mp4a.40.2
+    pub fn codec_string(&self) -> String {
+        match self {
+            CodecConfig::Avc(config) => format!(
+                "avc1.{:02x}{:02x}{:02x}",
+                config.profile(),
+                config.compatibility(),
+                config.level()
+            ),
+            CodecConfig::Hevc(config) => {
+                let bytes = config.into_bytes();
+                let space = ["", "A", "B", "C"][(bytes[1] >> 6) as usize];
+                let tier = if bytes[1] & 0x20 != 0 { 'H' } else { 'L' };
+                let compatibility =
+                    u32::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]).reverse_bits();
+                let mut codec = format!(
+                    "hvc1.{}{}.{:X}.{}{}",
+                    space,
+                    config.profile(),
+                    compatibility,
+                    tier,
+                    config.level()
+                );
+                //constraint bytes,trailing zero bytes are omitted
+                let constraints = &bytes[6..12];
+                let count = constraints
+                    .iter()
+                    .rposition(|b| *b != 0)
+                    .map_or(0, |i| i + 1);
+                for byte in &constraints[..count] {
+                    codec.push_str(&format!(".{:X}", byte));
+                }
+                codec
+            }
+            CodecConfig::Aac(config) => format!("mp4a.40.{}", config.object_type()),
+            CodecConfig::Mp3(header) if header.version() == 1 => "mp4a.6B".to_string(),
+            CodecConfig::Mp3(_) => "mp4a.69".to_string(),
+        }
+    }
+
+    ///Size in bytes of the nal unit length prefix of video frames
+    pub fn nalu_length_size(&self) -> Option<usize> {
+        match self {
+            CodecConfig::Avc(config) => Some(config.nalu_length_size()),
+            CodecConfig::Hevc(config) => Some(config.nalu_length_size()),
+            _ => None,
+        }
+    }
+
+    ///CodecID of video tags or SoundFormat of audio tags
+    pub fn flv_codec_id(&self) -> u8 {
+        match self {
+            CodecConfig::Avc(_) => Tag::VIDEO_CODEC_AVC,
+            CodecConfig::Hevc(_) => Tag::VIDEO_CODEC_HEVC,
+            CodecConfig::Aac(_) => Tag::SOUND_FORMAT_AAC,
+            CodecConfig::Mp3(header) if header.sample_rate() == 8000 => Tag::SOUND_FORMAT_MP3_8K,
+            CodecConfig::Mp3(_) => Tag::SOUND_FORMAT_MP3,
+        }
+    }
+
+    ///Flags byte of audio tags,AAC always declares 44kHz 16 bits stereo
+    fn sound_flags(&self) -> u8 {
+        let (rate, channels) = match self {
+            CodecConfig::Mp3(header) => (header.sample_rate(), header.channels()),
+            _ => (44100, 2),
+        };
+        let rate_index = match rate {
+            r if r >= 44100 => 3,
+            r if r >= 22050 => 2,
+            r if r >= 11025 => 1,
+            _ => 0,
+        };
+        (self.flv_codec_id() << 4) | (rate_index << 2) | 0x02 | (channels > 1) as u8
+    }
+
+    ///The flv tag of the sequence header,None for codecs without one
+    pub fn sequence_header_tag(&self, timestamp: u32) -> Option<Tag> {
+        let video = |record: Vec<u8>| {
+            Tag::video(
+                timestamp,
+                Tag::FRAME_TYPE_KEY,
+                self.flv_codec_id(),
+                Tag::PACKET_TYPE_SEQUENCE_HEADER,
+                0,
+                &record,
+            )
+        };
+        match self {
+            CodecConfig::Avc(config) => Some(video(config.into_bytes())),
+            CodecConfig::Hevc(config) => Some(video(config.into_bytes())),
+            CodecConfig::Aac(config) => Some(Tag::audio(
+                timestamp,
+                self.sound_flags(),
+                Tag::PACKET_TYPE_SEQUENCE_HEADER,
+                config.as_bytes(),
+            )),
+            CodecConfig::Mp3(_) => None,
+        }
+    }
+
+    ///The flv tag of a frame coded with this config
+    pub fn frame_tag(&self, frame: &Frame) -> Tag {
+        match frame.kind {
+            TrackKind::Video => Tag::video(
+                frame.timestamp,
+                if frame.keyframe {
+                    Tag::FRAME_TYPE_KEY
+                } else {
+                    Tag::FRAME_TYPE_INTER
+                },
+                self.flv_codec_id(),
+                Tag::PACKET_TYPE_NALU,
+                frame.composition_time,
+                &frame.data,
+            ),
+            TrackKind::Audio => Tag::audio(
+                frame.timestamp,
+                self.sound_flags(),
+                Tag::PACKET_TYPE_NALU,
+                &frame.data,
+            ),
+        }
+    }
+
+    ///Size of the flv tag holding a frame of data_size bytes,with its previous tag size
+    pub fn frame_tag_size(&self, kind: TrackKind, data_size: u32) -> u64 {
+        let header_size = match (kind, self) {
+            (TrackKind::Video, _) => 5,
+            (TrackKind::Audio, CodecConfig::Aac(_)) => 2,
+            (TrackKind::Audio, _) => 1,
+        };
+        (Tag::TAG_HEADER_SIZE + header_size + data_size) as u64 + 4
+    }
+
+    ///Parameter set nal units of the sequence header
+    fn parameter_sets(&self) -> Vec<&[u8]> {
+        match self {
+            CodecConfig::Avc(config) => config
+                .sps()
+                .iter()
+                .chain(config.pps().iter())
+                .map(|p| p.as_slice())
+                .collect(),
+            CodecConfig::Hevc(config) => config.parameter_sets(),
+            _ => vec![],
+        }
+    }
+}
+
+///Split a frame made of length prefixed nal units
+pub fn split_nal_units(data: &[u8], length_size: usize) -> io::Result<Vec<&[u8]>> {
+    let mut units = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        if offset + length_size > data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Truncated nal unit length",
+            ));
+        }
+        let size = data[offset..offset + length_size]
+            .iter()
+            .fold(0_usize, |size, b| (size << 8) | *b as usize);
+        offset += length_size;
+        if offset + size > data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Nal unit exceeds the frame",
+            ));
+        }
+        units.push(&data[offset..offset + size]);
+        offset += size;
+    }
+    Ok(units)
+}
+
+///Convert a video frame to Annex B byte stream,optionally starting with an access unit delimiter.
+///Parameter sets of the sequence header are inserted before random access pictures
+///which don't carry their own.
+pub fn to_annex_b(config: &CodecConfig, data: &[u8], aud: bool) -> io::Result<Vec<u8>> {
+    const START_CODE: [u8; 4] = [0, 0, 0, 1];
+    let length_size = config
+        .nalu_length_size()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a video configuration"))?;
+    let units = split_nal_units(data, length_size)?;
+    let is_hevc = matches!(config, CodecConfig::Hevc(_));
+    let nal_type = |nal: &[u8]| -> u8 {
+        if is_hevc {
+            hevc::nalu_type(nal)
+        } else {
+            nal.first().map(|b| b & 0x1F).unwrap_or(0)
+        }
+    };
+    let is_parameter_set = |t: u8| {
+        if is_hevc {
+            t == hevc::NALU_TYPE_VPS || t == hevc::NALU_TYPE_SPS || t == hevc::NALU_TYPE_PPS
+        } else {
+            t == avc::NALU_TYPE_SPS || t == avc::NALU_TYPE_PPS
+        }
+    };
+    let is_aud = |t: u8| {
+        if is_hevc {
+            t == hevc::NALU_TYPE_AUD
+        } else {
+            t == avc::NALU_TYPE_AUD
+        }
+    };
+    let is_random_access = |t: u8| {
+        if is_hevc {
+            hevc::is_irap(t)
+        } else {
+            t == avc::NALU_TYPE_IDR
+        }
+    };
+
+    let mut out = Vec::with_capacity(data.len() + 64);
+    if aud {
+        out.extend_from_slice(&START_CODE);
+        if is_hevc {
+            out.extend_from_slice(&[hevc::NALU_TYPE_AUD << 1, 0x01, 0x50]);
+        } else {
+            out.extend_from_slice(&[avc::NALU_TYPE_AUD, 0xF0]);
+        }
+    }
+    let has_parameter_sets = units.iter().any(|nal| is_parameter_set(nal_type(nal)));
+    let mut inserted = has_parameter_sets;
+    for nal in units {
+        let t = nal_type(nal);
+        if aud && is_aud(t) {
+            continue;
+        }
+        if !inserted && is_random_access(t) {
+            for parameter_set in config.parameter_sets() {
+                out.extend_from_slice(&START_CODE);
+                out.extend_from_slice(parameter_set);
+            }
+            inserted = true;
+        }
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(nal);
+    }
+    Ok(out)
+}
+
+///A coded video or audio frame
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub kind: TrackKind,
+    ///Decode timestamp in milliseconds
+    pub timestamp: u32,
+    ///Presentation timestamp minus decode timestamp in milliseconds
+    pub composition_time: i32,
+    pub keyframe: bool,
+    ///Frame data without the flv audio/video tag header
+    pub data: Vec<u8>,
+}
+
+///Timestamps and size of the frames of one stream
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Span {
+    pub first: Option<u32>,
+    pub last: u32,
+    pub frames: u64,
+    pub bytes: u64,
+}
+
+impl Span {
+    pub fn push(&mut self, timestamp: u32, size: u32) {
+        self.first.get_or_insert(timestamp);
+        self.last = self.last.max(timestamp);
+        self.frames += 1;
+        self.bytes += size as u64;
+    }
+
+    ///Average gap between frames,at least 1ms
+    pub fn interval(&self) -> u32 {
+        match self.first {
+            Some(first) if self.frames > 1 => {
+                ((self.last - first) as u64 / (self.frames - 1)).max(1) as u32
+            }
+            _ => 1,
+        }
+    }
+
+    ///Time after the last frame,which lasts one average interval
+    pub fn end(&self) -> Option<u32> {
+        self.first.map(|_| self.last + self.interval())
+    }
+
+    ///Milliseconds from the first frame to the end of the last
+    pub fn length(&self) -> u32 {
+        match self.first {
+            Some(first) => self.last - first + self.interval(),
+            None => 0,
+        }
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        match self.length() {
+            0 => 0.0,
+            length => self.frames as f64 * 1000.0 / length as f64,
+        }
+    }
+
+    ///Kilobits per second
+    pub fn data_rate(&self) -> f64 {
+        match self.length() {
+            0 => 0.0,
+            length => self.bytes as f64 * 8.0 / length as f64,
+        }
+    }
+
+    ///Continue with the frames of other one average interval after the last frame
+    pub fn append(&mut self, other: &Span) {
+        let Some(other_first) = other.first else {
+            return;
+        };
+        let start = self.end().unwrap_or(other_first);
+        self.first.get_or_insert(start);
+        self.last = start + (other.last - other_first);
+        self.frames += other.frames;
+        self.bytes += other.bytes;
+    }
+}
+
+///Video fields of onMetaData
+#[derive(Debug, Clone, Default)]
+pub struct VideoMetadata {
+    pub codec_id: u8,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f64,
+    ///Kilobits per second
+    pub data_rate: f64,
+}
+
+///Audio fields of onMetaData
+#[derive(Debug, Clone, Default)]
+pub struct AudioMetadata {
+    pub codec_id: u8,
+    pub sample_rate: u32,
+    pub stereo: bool,
+    ///Kilobits per second
+    pub data_rate: f64,
+}
+
+///Values of the onMetaData script tag written by flv producing commands
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    ///Duration in seconds
+    pub duration: f64,
+    ///Omitted if unknown
+    pub file_size: Option<u64>,
+    pub video: Option<VideoMetadata>,
+    pub audio: Option<AudioMetadata>,
+}
+
+impl Metadata {
+    ///Duration and rates of spans for the streams with a codec id,sound is the sample rate
+    ///and channels used if the audio configuration doesn't have them
+    pub fn from_spans(
+        spans: &[Span; 2],
+        codecs: [Option<u8>; 2],
+        configs: &[Option<CodecConfig>; 2],
+        sound: Option<(u32, u8)>,
+    ) -> Metadata {
+        let end = spans.iter().filter_map(Span::end).max().unwrap_or(0);
+        Metadata {
+            duration: end as f64 / 1000.0,
+            file_size: Some(0),
+            video: codecs[0].map(|codec_id| {
+                let (width, height) = configs[0]
+                    .as_ref()
+                    .and_then(|c| c.picture_size())
+                    .unwrap_or((0, 0));
+                VideoMetadata {
+                    codec_id,
+                    width,
+                    height,
+                    frame_rate: spans[0].frame_rate(),
+                    data_rate: spans[0].data_rate(),
+                }
+            }),
+            audio: codecs[1].map(|codec_id| {
+                let (sample_rate, channels) = configs[1]
+                    .as_ref()
+                    .and_then(|c| c.audio_format())
+                    .or(sound)
+                    .unwrap_or((0, 0));
+                AudioMetadata {
+                    codec_id,
+                    sample_rate,
+                    stereo: channels > 1,
+                    data_rate: spans[1].data_rate(),
+                }
+            }),
+        }
+    }
+
+    ///Build the tag,its size only depends on which fields are present
+    pub fn to_tag(&self) -> Tag {
+        let mut entries = vec![("duration", Amf0Value::Number(self.duration))];
+        if let Some(video) = &self.video {
+            entries.extend(vec![
+                ("width", Amf0Value::Number(video.width as f64)),
+                ("height", Amf0Value::Number(video.height as f64)),
+                ("videodatarate", Amf0Value::Number(video.data_rate)),
+                ("framerate", Amf0Value::Number(video.frame_rate)),
+                ("videocodecid", Amf0Value::Number(video.codec_id as f64)),
+            ]);
+        }
+        if let Some(audio) = &self.audio {
+            entries.extend(vec![
+                ("audiodatarate", Amf0Value::Number(audio.data_rate)),
+                (
+                    "audiosamplerate",
+                    Amf0Value::Number(audio.sample_rate as f64),
+                ),
+                ("audiosamplesize", Amf0Value::Number(16.0)),
+                ("stereo", Amf0Value::Boolean(audio.stereo)),
+                ("audiocodecid", Amf0Value::Number(audio.codec_id as f64)),
+            ]);
+        }
+        if let Some(file_size) = self.file_size {
+            entries.push(("filesize", Amf0Value::Number(file_size as f64)));
+        }
+        entries.push(("encoder", Amf0Value::String("flv-cli".to_string())));
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| Pair {
+                key: key.to_string(),
+                value,
+            })
+            .collect();
+        Tag::new(
+            0,
+            TagData::Script(vec![
+                Amf0Value::String("onMetaData".to_string()),
+                Amf0Value::EcmaArray { entries },
+            ]),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MediaEvent {
+    ///A new or changed decoder configuration
+    Config(TrackKind, CodecConfig),
+    Frame(Frame),
+}
+
+///Turn flv tags into decoder configurations and frames
+#[derive(Debug, Default)]
+pub struct Demuxer {
+    video: Option<CodecConfig>,
+    audio: Option<CodecConfig>,
+}
+
+impl Demuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn set_config(&mut self, kind: TrackKind, config: CodecConfig) -> Option<MediaEvent> {
+        let current = match kind {
+            TrackKind::Video => &mut self.video,
+            TrackKind::Audio => &mut self.audio,
+        };
+        if current.as_ref() == Some(&config) {
+            return None;
+        }
+        *current = Some(config.clone());
+        Some(MediaEvent::Config(kind, config))
+    }
+
+    ///Decode a tag,script tags and unsupported codecs yield nothing
+    pub fn push(&mut self, tag: &Tag) -> io::Result<Vec<MediaEvent>> {
+        let mut events = vec![];
+        if tag.is_video_tag() {
+            let codec = tag.video_codec_id();
+            if codec != Some(Tag::VIDEO_CODEC_AVC) && codec != Some(Tag::VIDEO_CODEC_HEVC) {
+                return Ok(events);
+            }
+            if tag.frame_type() == Some(Tag::FRAME_TYPE_COMMAND) {
+                return Ok(events);
+            }
+            match tag.packet_type() {
+                Some(Tag::PACKET_TYPE_SEQUENCE_HEADER) => {
+                    let config = if codec == Some(Tag::VIDEO_CODEC_AVC) {
+                        CodecConfig::Avc(AvcDecoderConfig::from_bytes(tag.body())?)
+                    } else {
+                        CodecConfig::Hevc(HevcDecoderConfig::from_bytes(tag.body())?)
+                    };
+                    events.extend(self.set_config(TrackKind::Video, config));
+                }
+                Some(Tag::PACKET_TYPE_NALU) if !tag.body().is_empty() => {
+                    events.push(MediaEvent::Frame(Frame {
+                        kind: TrackKind::Video,
+                        timestamp: tag.timestamp(),
+                        composition_time: tag.composition_time(),
+                        keyframe: tag.is_keyframe(),
+                        data: tag.body().to_vec(),
+                    }));
+                }
+                _ => {}
+            }
+        } else if tag.is_audio_tag() {
+            match tag.sound_format() {
+                Some(Tag::SOUND_FORMAT_AAC) => {
+                    if tag.is_sequence_header() {
+                        let config = AudioSpecificConfig::from_bytes(tag.body())?;
+                        events.extend(self.set_config(TrackKind::Audio, CodecConfig::Aac(config)));
+                        return Ok(events);
+                    }
+                }
+                Some(Tag::SOUND_FORMAT_MP3) | Some(Tag::SOUND_FORMAT_MP3_8K) => {
+                    if let Ok(header) = Mp3FrameHeader::from_bytes(tag.body()) {
+                        if !matches!(&self.audio, Some(CodecConfig::Mp3(current))
+                            if current.sample_rate() == header.sample_rate()
+                                && current.channels() == header.channels())
+                        {
+                            events.extend(
+                                self.set_config(TrackKind::Audio, CodecConfig::Mp3(header)),
+                            );
+                        }
+                    }
+                }
+                _ => return Ok(events),
+            }
+            if !tag.body().is_empty() {
+                events.push(MediaEvent::Frame(Frame {
+                    kind: TrackKind::Audio,
+                    timestamp: tag.timestamp(),
+                    composition_time: 0,
+                    keyframe: true,
+                    data: tag.body().to_vec(),
+                }));
+            }
+        }
+        Ok(events)
+    }
+}
@@ -0,0 +1,208 @@
+//! `merge` command: the video of one flv with the audio of another.
+
+use crate::media::{
+    invalid_data, is_metadata, CodecConfig, Demuxer, MediaEvent, Metadata, Span, TrackKind,
+};
+use crate::open_output;
+use flv_parser::amf::Amf0Value;
+use flv_parser::flv::{Header, ParseResult, Reader, ScriptTagDataTrait, Tag, TagData, Writer};
+use std::{
+    fs::File,
+    io::{self, BufReader},
+};
+
+///Tags of one stream of an flv file,with an offset added to their timestamps
+struct Track<'a> {
+    path: &'a str,
+    kind: TrackKind,
+    offset: i64,
+    reader: Reader<BufReader<File>>,
+    header: Option<Header>,
+    ///onMetaData of the file
+    metadata: Option<Vec<Amf0Value>>,
+    ///Frames dropped because the offset moved them before 0
+    dropped: u64,
+}
+
+impl<'a> Track<'a> {
+    fn open(path: &'a str, kind: TrackKind, offset: i64) -> io::Result<Self> {
+        Ok(Track {
+            path,
+            kind,
+            offset,
+            reader: Reader::new(BufReader::new(File::open(path)?)),
+            header: None,
+            metadata: None,
+            dropped: 0,
+        })
+    }
+
+    fn next_tag(&mut self) -> io::Result<Option<Tag>> {
+        while let Some(result) = self.reader.read_next()? {
+            let mut tag = match result {
+                ParseResult::Header(header) => {
+                    self.header = Some(header);
+                    continue;
+                }
+                ParseResult::Tag(tag) => tag,
+                _ => continue,
+            };
+            let selected = match self.kind {
+                TrackKind::Video => tag.is_video_tag(),
+                TrackKind::Audio => tag.is_audio_tag(),
+            };
+            if !selected {
+                if let TagData::Script(values) = tag.data() {
+                    if self.metadata.is_none() && is_metadata(values) {
+                        self.metadata = Some(values.clone());
+                    }
+                }
+                continue;
+            }
+            let timestamp = tag.timestamp() as i64 + self.offset;
+            if timestamp < 0 && !tag.is_sequence_header() {
+                self.dropped += 1;
+                continue;
+            }
+            tag.set_timestamp(timestamp.max(0) as u32);
+            return Ok(Some(tag));
+        }
+        if self.reader.pending() > 0 {
+            eprintln!(
+                "Warning: {}: {} bytes of a truncated tag ignored",
+                self.path,
+                self.reader.pending()
+            );
+        }
+        Ok(None)
+    }
+}
+
+///What the first pass learned about a track
+#[derive(Default)]
+struct TrackPlan {
+    span: Span,
+    ///Codec id of video tags or sound format of audio tags
+    codec: Option<u8>,
+    config: Option<CodecConfig>,
+    ///Sample rate and channels of the first audio tag
+    sound: Option<(u32, u8)>,
+    ///Size of the copied tags with their previous tag sizes
+    tags_size: u64,
+}
+
+fn scan(track: &mut Track) -> io::Result<TrackPlan> {
+    let mut plan = TrackPlan::default();
+    let mut demuxer = Demuxer::new();
+    while let Some(tag) = track.next_tag()? {
+        let codec = match track.kind {
+            TrackKind::Video => tag.video_codec_id(),
+            TrackKind::Audio => tag.sound_format(),
+        };
+        plan.codec = plan.codec.or(codec);
+        if track.kind == TrackKind::Audio && plan.sound.is_none() {
+            plan.sound = tag.sound_rate().zip(tag.sound_channels());
+        }
+        for event in demuxer.push(&tag)? {
+            if let MediaEvent::Config(_, config) = event {
+                plan.config.get_or_insert(config);
+            }
+        }
+        plan.tags_size += tag.tag_size() as u64 + 4;
+        let end_of_sequence = tag.packet_type() == Some(Tag::PACKET_TYPE_END_OF_SEQUENCE)
+            && track.kind == TrackKind::Video;
+        if !tag.is_sequence_header() && !end_of_sequence {
+            plan.span.push(tag.timestamp(), tag.data_size());
+        }
+    }
+    if plan.codec.is_none() {
+        return Err(invalid_data(format!(
+            "{}: no {:?} tags found",
+            track.path, track.kind
+        )));
+    }
+    if track.dropped > 0 {
+        eprintln!(
+            "{}: dropped {} {:?} frames moved before 0",
+            track.path, track.dropped, track.kind
+        );
+    }
+    Ok(plan)
+}
+
+fn metadata(video: &TrackPlan, audio: &TrackPlan, source: Option<&Vec<Amf0Value>>) -> Metadata {
+    let mut metadata = Metadata::from_spans(
+        &[video.span, audio.span],
+        [
+            Some(video.codec.unwrap_or(0)),
+            Some(audio.codec.unwrap_or(0)),
+        ],
+        &[video.config.clone(), audio.config.clone()],
+        audio.sound,
+    );
+    //fall back to the size in the onMetaData of the video input
+    let size = source.and_then(|source| {
+        let (width, height) = (source.width(), source.height());
+        Some((width as u32, height as u32)).filter(|_| width.is_finite() && height.is_finite())
+    });
+    if let (Some(video), None, Some((width, height))) = (
+        &mut metadata.video,
+        video.config.as_ref().and_then(|c| c.picture_size()),
+        size,
+    ) {
+        video.width = width;
+        video.height = height;
+    }
+    metadata
+}
+
+///Write the video tags of video_path and the audio tags of audio_path interleaved by timestamp,
+///audio_offset in milliseconds is added to the audio timestamps
+pub fn merge(video_path: &str, audio_path: &str, path: &str, audio_offset: i64) -> io::Result<()> {
+    let mut video = Track::open(video_path, TrackKind::Video, 0)?;
+    let mut audio = Track::open(audio_path, TrackKind::Audio, audio_offset)?;
+    let video_plan = scan(&mut video)?;
+    let audio_plan = scan(&mut audio)?;
+    let mut metadata = metadata(&video_plan, &audio_plan, video.metadata.as_ref());
+    let metadata_size = metadata.to_tag().tag_size() as u64 + 4;
+    metadata.file_size = Some(
+        Header::HEADER_SIZE as u64
+            + 4
+            + metadata_size
+            + video_plan.tags_size
+            + audio_plan.tags_size,
+    );
+
+    //the header of the video file,with the audio flag it didn't need to have
+    let mut header = video
+        .header
+        .clone()
+        .unwrap_or_else(|| Header::new(true, false));
+    header.set_has_video(true);
+    header.set_has_audio(true);
+    let mut video = Track::open(video_path, TrackKind::Video, 0)?;
+    let mut audio = Track::open(audio_path, TrackKind::Audio, audio_offset)?;
+    let mut writer = Writer::new(open_output(path)?);
+    writer.write_header(&header)?;
+    writer.write_tag(&metadata.to_tag())?;
+    let mut next = [video.next_tag()?, audio.next_tag()?];
+    loop {
+        //on equal timestamps sequence headers go first,then video
+        let index = match &next {
+            [Some(v), Some(a)] => {
+                let header_first = a.is_sequence_header() && !v.is_sequence_header();
+                (a.timestamp() < v.timestamp() || (a.timestamp() == v.timestamp() && header_first))
+                    as usize
+            }
+            [Some(_), None] => 0,
+            [None, Some(_)] => 1,
+            [None, None] => break,
+        };
+        writer.write_tag(next[index].as_ref().unwrap())?;
+        next[index] = match index {
+            0 => video.next_tag()?,
+            _ => audio.next_tag()?,
+        };
+    }
+    writer.flush()
+}
@@ -0,0 +1,242 @@
+//! Matroska(EBML) element writer.
+
+use crate::media::{CodecConfig, TrackKind};
+use flv_parser::amf::{Amf0Value, Pair};
+
+pub const EBML: u32 = 0x1A45_DFA3;
+pub const SEGMENT: u32 = 0x1853_8067;
+pub const SEEK_HEAD: u32 = 0x114D_9B74;
+pub const INFO: u32 = 0x1549_A966;
+pub const TRACKS: u32 = 0x1654_AE6B;
+pub const TAGS: u32 = 0x1254_C367;
+pub const CLUSTER: u32 = 0x1F43_B675;
+pub const CUES: u32 = 0x1C53_BB6B;
+///Timestamps are in milliseconds
+pub const TIMESTAMP_SCALE: u64 = 1_000_000;
+
+///Encode an element id,ids keep their length marker
+fn id_bytes(id: u32) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().position(|b| *b != 0).unwrap_or(3);
+    bytes[skip..].to_vec()
+}
+
+///Encode a size as the shortest variable length integer
+pub fn vint(value: u64) -> Vec<u8> {
+    let length = (1..=8)
+        .find(|length| value < (1 << (7 * length)) - 1)
+        .unwrap_or(8);
+    sized_vint(value, length)
+}
+
+///Encode a value as a variable length integer of length bytes
+pub fn sized_vint(value: u64, length: usize) -> Vec<u8> {
+    let marked = value | (1 << (7 * length));
+    marked.to_be_bytes()[8 - length..].to_vec()
+}
+
+pub fn element(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = id_bytes(id);
+    bytes.extend_from_slice(&vint(payload.len() as u64));
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+pub fn uint(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().position(|b| *b != 0).unwrap_or(7);
+    element(id, &bytes[skip..])
+}
+
+pub fn float(id: u32, value: f64) -> Vec<u8> {
+    element(id, &value.to_be_bytes())
+}
+
+pub fn string(id: u32, value: &str) -> Vec<u8> {
+    element(id, value.as_bytes())
+}
+
+pub fn ebml_header() -> Vec<u8> {
+    let payload = [
+        uint(0x4286, 1),
+        uint(0x42F7, 1),
+        uint(0x42F2, 4),
+        uint(0x42F3, 8),
+        string(0x4282, "matroska"),
+        uint(0x4287, 4),
+        uint(0x4285, 2),
+    ]
+    .concat();
+    element(EBML, &payload)
+}
+
+///Header of a segment holding size bytes,the size always takes 8 bytes
+pub fn segment_header(size: u64) -> Vec<u8> {
+    let mut bytes = id_bytes(SEGMENT);
+    bytes.extend_from_slice(&sized_vint(size, 8));
+    bytes
+}
+
+///Seek entries of the top level elements,positions are relative to the segment data.
+///Positions take 8 bytes so the size doesn't depend on them.
+pub fn seek_head(entries: &[(u32, u64)]) -> Vec<u8> {
+    let payload: Vec<u8> = entries
+        .iter()
+        .flat_map(|(id, position)| {
+            let seek = [
+                element(0x53AB, &id_bytes(*id)),
+                element(0x53AC, &position.to_be_bytes()),
+            ]
+            .concat();
+            element(0x4DBB, &seek)
+        })
+        .collect();
+    element(SEEK_HEAD, &payload)
+}
+
+///Segment information,duration in milliseconds
+pub fn info(duration: f64) -> Vec<u8> {
+    let payload = [
+        uint(0x2AD7B1, TIMESTAMP_SCALE),
+        float(0x4489, duration),
+        string(0x4D80, "flv-cli"),
+        string(0x5741, "flv-cli"),
+    ]
+    .concat();
+    element(INFO, &payload)
+}
+
+///Codec ID and CodecPrivate of a track
+fn codec(config: &CodecConfig) -> (&'static str, Option<Vec<u8>>) {
+    match config {
+        CodecConfig::Avc(config) => ("V_MPEG4/ISO/AVC", Some(config.into_bytes())),
+        CodecConfig::Hevc(config) => ("V_MPEGH/ISO/HEVC", Some(config.into_bytes())),
+        CodecConfig::Aac(config) => ("A_AAC", Some(config.as_bytes().to_vec())),
+        CodecConfig::Mp3(_) => ("A_MPEG/L3", None),
+    }
+}
+
+fn track_entry(number: u64, kind: TrackKind, config: &CodecConfig) -> Vec<u8> {
+    let (codec_id, private) = codec(config);
+    let mut payload = [
+        uint(0xD7, number),
+        uint(0x73C5, number),
+        uint(
+            0x83,
+            match kind {
+                TrackKind::Video => 1,
+                TrackKind::Audio => 2,
+            },
+        ),
+        uint(0x9C, 0),
+        string(0x86, codec_id),
+    ]
+    .concat();
+    if let Some(private) = private {
+        payload.extend_from_slice(&element(0x63A2, &private));
+    }
+    if let Some((width, height)) = config.picture_size() {
+        let video = [uint(0xB0, width as u64), uint(0xBA, height as u64)].concat();
+        payload.extend_from_slice(&element(0xE0, &video));
+    }
+    if let Some((sample_rate, channels)) = config.audio_format() {
+        let audio = [float(0xB5, sample_rate as f64), uint(0x9F, channels as u64)].concat();
+        payload.extend_from_slice(&element(0xE1, &audio));
+    }
+    element(0xAE, &payload)
+}
+
+///Tracks element,track numbers start at 1 in the given order
+pub fn tracks(tracks: &[(TrackKind, &CodecConfig)]) -> Vec<u8> {
+    let payload: Vec<u8> = tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (kind, config))| track_entry(index as u64 + 1, *kind, config))
+        .collect();
+    element(TRACKS, &payload)
+}
+
+///Text of an amf value,None for values without a plain text form
+fn amf_text(value: &Amf0Value) -> Option<String> {
+    match value {
+        Amf0Value::Number(n) => Some(n.to_string()),
+        Amf0Value::Boolean(b) => Some(b.to_string()),
+        Amf0Value::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+///Tags element with a simple tag of every plain value of onMetaData,names are upper cased
+pub fn tags(entries: &[Pair<String, Amf0Value>]) -> Vec<u8> {
+    let simple_tags: Vec<u8> = entries
+        .iter()
+        .filter_map(|pair| {
+            let text = amf_text(&pair.value)?;
+            let simple_tag = [
+                string(0x45A3, &pair.key.to_uppercase()),
+                string(0x4487, &text),
+            ]
+            .concat();
+            Some(element(0x67C8, &simple_tag))
+        })
+        .flatten()
+        .collect();
+    let tag = [element(0x63C0, &uint(0x68CA, 50)), simple_tags].concat();
+    element(TAGS, &element(0x7373, &tag))
+}
+
+///A SimpleBlock of a frame,timestamp is relative to the cluster
+pub fn simple_block(track: u64, timestamp: i16, keyframe: bool, data: &[u8]) -> Vec<u8> {
+    let mut payload = vint(track);
+    payload.extend_from_slice(&timestamp.to_be_bytes());
+    payload.push(if keyframe { 0x80 } else { 0x00 });
+    payload.extend_from_slice(data);
+    element(0xA3, &payload)
+}
+
+///A cluster of the given blocks,timestamp in milliseconds
+pub fn cluster(timestamp: u64, blocks: &[u8]) -> Vec<u8> {
+    let mut payload = uint(0xE7, timestamp);
+    payload.extend_from_slice(blocks);
+    element(CLUSTER, &payload)
+}
+
+///Cues of (time in milliseconds,track number,cluster position relative to the segment data)
+pub fn cues(points: &[(u64, u64, u64)]) -> Vec<u8> {
+    let payload: Vec<u8> = points
+        .iter()
+        .flat_map(|(time, track, position)| {
+            let positions = [uint(0xF7, *track), uint(0xF1, *position)].concat();
+            let point = [uint(0xB3, *time), element(0xB7, &positions)].concat();
+            element(0xBB, &point)
+        })
+        .collect();
+    element(CUES, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_shortest_vint_without_the_reserved_all_ones_value() {
+        assert_eq!(vint(0), [0x80]);
+        assert_eq!(vint(126), [0xFE]);
+        //all ones of a length means unknown size
+        assert_eq!(vint(127), [0x40, 0x7F]);
+        assert_eq!(vint(16382), [0x7F, 0xFE]);
+        assert_eq!(vint(16383), [0x20, 0x3F, 0xFF]);
+        assert_eq!(vint((1 << 21) - 1), [0x10, 0x1F, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn keeps_the_length_marker_of_ids() {
+        assert_eq!(element(0xEC, &[]), [0xEC, 0x80]);
+        assert_eq!(uint(0x4286, 1), [0x42, 0x86, 0x81, 0x01]);
+        assert_eq!(uint(0xD7, 0), [0xD7, 0x81, 0x00]);
+        assert_eq!(
+            segment_header(5),
+            [0x18, 0x53, 0x80, 0x67, 0x01, 0, 0, 0, 0, 0, 0, 0x05]
+        );
+    }
+}
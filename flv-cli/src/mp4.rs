@@ -0,0 +1,624 @@
+//! ISO BMFF(mp4) box writer,for progressive and fragmented files.
+
+use crate::media::{CodecConfig, TrackKind};
+
+///Timescale of the movie header
+pub const MOVIE_TIMESCALE: u32 = 1000;
+///Timescale of video tracks
+pub const VIDEO_TIMESCALE: u32 = 90000;
+
+const SAMPLE_FLAGS_SYNC: u32 = 0x0200_0000;
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x0101_0000;
+
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+///A track of the movie
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub id: u32,
+    pub kind: TrackKind,
+    pub config: CodecConfig,
+    pub timescale: u32,
+}
+
+impl Track {
+    pub fn new(id: u32, kind: TrackKind, config: CodecConfig) -> Self {
+        let timescale = match kind {
+            TrackKind::Video => VIDEO_TIMESCALE,
+            TrackKind::Audio => config.audio_format().map(|f| f.0).unwrap_or(48000),
+        };
+        Self {
+            id,
+            kind,
+            config,
+            timescale,
+        }
+    }
+
+    ///Convert milliseconds to the timescale of this track
+    pub fn millis_to_timescale(&self, millis: i64) -> i64 {
+        (millis * self.timescale as i64 + 500) / 1000
+    }
+
+    ///Nominal duration of one frame in the timescale of this track
+    pub fn frame_duration(&self) -> u32 {
+        match &self.config {
+            CodecConfig::Aac(_) => 1024,
+            CodecConfig::Mp3(header) => header.samples_per_frame(),
+            _ => self.timescale / 25,
+        }
+    }
+}
+
+///A sample of a track,times are in the timescale of the track
+#[derive(Debug, Clone, Default)]
+pub struct Sample {
+    pub dts: u64,
+    pub duration: u32,
+    pub cts_offset: i32,
+    pub size: u32,
+    pub keyframe: bool,
+}
+
+///Sample tables of a track in a progressive file
+#[derive(Debug, Clone, Default)]
+pub struct SampleTable {
+    pub samples: Vec<Sample>,
+    ///(offset in file,sample count) of every chunk
+    pub chunks: Vec<(u64, u32)>,
+    ///Write 64 bits chunk offsets
+    pub large_offsets: bool,
+}
+
+pub fn mp4_box(fourcc: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len() + 8);
+    bytes.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    bytes.extend_from_slice(fourcc);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+pub fn full_box(fourcc: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut content = Vec::with_capacity(payload.len() + 4);
+    content.push(version);
+    content.extend_from_slice(&flags.to_be_bytes()[1..]);
+    content.extend_from_slice(payload);
+    mp4_box(fourcc, &content)
+}
+
+///Header of a mdat box holding size bytes of data
+pub fn mdat_header(size: u64) -> Vec<u8> {
+    if size + 8 <= u32::MAX as u64 {
+        let mut bytes = (size as u32 + 8).to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"mdat");
+        bytes
+    } else {
+        let mut bytes = 1_u32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"mdat");
+        bytes.extend_from_slice(&(size + 16).to_be_bytes());
+        bytes
+    }
+}
+
+pub fn ftyp(major: &[u8; 4], minor: u32, compatible: &[&[u8; 4]]) -> Vec<u8> {
+    let mut payload = major.to_vec();
+    payload.extend_from_slice(&minor.to_be_bytes());
+    for brand in compatible {
+        payload.extend_from_slice(*brand);
+    }
+    mp4_box(b"ftyp", &payload)
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn mvhd(duration: u64, next_track_id: u32) -> Vec<u8> {
+    let mut p = vec![];
+    p.extend_from_slice(&0_u64.to_be_bytes());
+    p.extend_from_slice(&0_u64.to_be_bytes());
+    push_u32(&mut p, MOVIE_TIMESCALE);
+    p.extend_from_slice(&duration.to_be_bytes());
+    push_u32(&mut p, 0x0001_0000);
+    push_u16(&mut p, 0x0100);
+    p.extend_from_slice(&[0; 10]);
+    for value in MATRIX.iter() {
+        push_u32(&mut p, *value);
+    }
+    p.extend_from_slice(&[0; 24]);
+    push_u32(&mut p, next_track_id);
+    full_box(b"mvhd", 1, 0, &p)
+}
+
+fn tkhd(track: &Track, duration: u64) -> Vec<u8> {
+    let mut p = vec![];
+    p.extend_from_slice(&0_u64.to_be_bytes());
+    p.extend_from_slice(&0_u64.to_be_bytes());
+    push_u32(&mut p, track.id);
+    push_u32(&mut p, 0);
+    p.extend_from_slice(&duration.to_be_bytes());
+    p.extend_from_slice(&[0; 8]);
+    push_u16(&mut p, 0);
+    push_u16(&mut p, 0);
+    push_u16(
+        &mut p,
+        if track.kind == TrackKind::Audio {
+            0x0100
+        } else {
+            0
+        },
+    );
+    push_u16(&mut p, 0);
+    for value in MATRIX.iter() {
+        push_u32(&mut p, *value);
+    }
+    let (width, height) = track.config.picture_size().unwrap_or((0, 0));
+    push_u32(&mut p, width << 16);
+    push_u32(&mut p, height << 16);
+    full_box(b"tkhd", 1, 0x03, &p)
+}
+
+///Edit list of (segment duration in the movie timescale,media time),a media time of -1 is an empty edit
+fn edts(edits: &[(u64, i64)]) -> Vec<u8> {
+    let mut p = vec![];
+    push_u32(&mut p, edits.len() as u32);
+    for (segment_duration, media_time) in edits {
+        p.extend_from_slice(&segment_duration.to_be_bytes());
+        p.extend_from_slice(&media_time.to_be_bytes());
+        push_u32(&mut p, 0x0001_0000);
+    }
+    mp4_box(b"edts", &full_box(b"elst", 1, 0, &p))
+}
+
+///Start of the first sample of a track in the movie timescale,tracks are timed from the earliest one
+fn start_delay(track: &Track, table: &SampleTable) -> u64 {
+    let start = table.samples.first().map(|s| s.dts).unwrap_or(0);
+    start * MOVIE_TIMESCALE as u64 / track.timescale as u64
+}
+
+fn mdhd(track: &Track, duration: u64) -> Vec<u8> {
+    let mut p = vec![];
+    p.extend_from_slice(&0_u64.to_be_bytes());
+    p.extend_from_slice(&0_u64.to_be_bytes());
+    push_u32(&mut p, track.timescale);
+    p.extend_from_slice(&duration.to_be_bytes());
+    //language und
+    push_u16(&mut p, 0x55C4);
+    push_u16(&mut p, 0);
+    full_box(b"mdhd", 1, 0, &p)
+}
+
+fn hdlr(kind: TrackKind) -> Vec<u8> {
+    let (handler, name): (&[u8; 4], &[u8]) = match kind {
+        TrackKind::Video => (b"vide", b"VideoHandler\0"),
+        TrackKind::Audio => (b"soun", b"SoundHandler\0"),
+    };
+    let mut p = vec![0; 4];
+    p.extend_from_slice(handler);
+    p.extend_from_slice(&[0; 12]);
+    p.extend_from_slice(name);
+    full_box(b"hdlr", 0, 0, &p)
+}
+
+fn descriptor(tag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![tag];
+    let len = payload.len() as u32;
+    bytes.extend_from_slice(&[
+        0x80 | ((len >> 21) & 0x7F) as u8,
+        0x80 | ((len >> 14) & 0x7F) as u8,
+        0x80 | ((len >> 7) & 0x7F) as u8,
+        (len & 0x7F) as u8,
+    ]);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn esds(track: &Track, object_type: u8, specific_info: Option<&[u8]>) -> Vec<u8> {
+    let mut decoder_config = vec![object_type, 0x15, 0, 0, 0];
+    push_u32(&mut decoder_config, 0);
+    push_u32(&mut decoder_config, 0);
+    if let Some(info) = specific_info {
+        decoder_config.extend_from_slice(&descriptor(0x05, info));
+    }
+    let mut es = vec![];
+    push_u16(&mut es, track.id as u16);
+    es.push(0);
+    es.extend_from_slice(&descriptor(0x04, &decoder_config));
+    es.extend_from_slice(&descriptor(0x06, &[0x02]));
+    full_box(b"esds", 0, 0, &descriptor(0x03, &es))
+}
+
+fn sample_entry(track: &Track) -> Vec<u8> {
+    let mut p = vec![0; 6];
+    push_u16(&mut p, 1);
+    match &track.config {
+        CodecConfig::Avc(_) | CodecConfig::Hevc(_) => {
+            let (width, height) = track.config.picture_size().unwrap_or((0, 0));
+            p.extend_from_slice(&[0; 16]);
+            push_u16(&mut p, width as u16);
+            push_u16(&mut p, height as u16);
+            push_u32(&mut p, 0x0048_0000);
+            push_u32(&mut p, 0x0048_0000);
+            push_u32(&mut p, 0);
+            push_u16(&mut p, 1);
+            p.extend_from_slice(&[0; 32]);
+            push_u16(&mut p, 0x0018);
+            push_u16(&mut p, 0xFFFF);
+            match &track.config {
+                CodecConfig::Avc(config) => {
+                    p.extend_from_slice(&mp4_box(b"avcC", &config.into_bytes()));
+                    mp4_box(b"avc1", &p)
+                }
+                CodecConfig::Hevc(config) => {
+                    p.extend_from_slice(&mp4_box(b"hvcC", &config.into_bytes()));
+                    mp4_box(b"hvc1", &p)
+                }
+                _ => unreachable!(),
+            }
+        }
+        CodecConfig::Aac(_) | CodecConfig::Mp3(_) => {
+            let (sample_rate, channels) = track.config.audio_format().unwrap_or((48000, 2));
+            p.extend_from_slice(&[0; 8]);
+            push_u16(&mut p, channels as u16);
+            push_u16(&mut p, 16);
+            push_u32(&mut p, 0);
+            push_u32(&mut p, (sample_rate.min(0xFFFF)) << 16);
+            match &track.config {
+                CodecConfig::Aac(config) => {
+                    p.extend_from_slice(&esds(track, 0x40, Some(config.as_bytes())))
+                }
+                CodecConfig::Mp3(header) => {
+                    let object_type = if header.version() == 1 { 0x6B } else { 0x69 };
+                    p.extend_from_slice(&esds(track, object_type, None))
+                }
+                _ => unreachable!(),
+            }
+            mp4_box(b"mp4a", &p)
+        }
+    }
+}
+
+fn stsd(track: &Track) -> Vec<u8> {
+    let mut p = 1_u32.to_be_bytes().to_vec();
+    p.extend_from_slice(&sample_entry(track));
+    full_box(b"stsd", 0, 0, &p)
+}
+
+///Run length encode (count,value) pairs
+fn run_lengths<T: PartialEq + Copy>(values: impl Iterator<Item = T>) -> Vec<(u32, T)> {
+    let mut runs: Vec<(u32, T)> = vec![];
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+fn stbl(track: &Track, table: &SampleTable) -> Vec<u8> {
+    let samples = &table.samples;
+    let mut p = stsd(track);
+
+    let runs = run_lengths(samples.iter().map(|s| s.duration));
+    let mut stts = vec![];
+    push_u32(&mut stts, runs.len() as u32);
+    for (count, duration) in runs {
+        push_u32(&mut stts, count);
+        push_u32(&mut stts, duration);
+    }
+    p.extend_from_slice(&full_box(b"stts", 0, 0, &stts));
+
+    if samples.iter().any(|s| s.cts_offset != 0) {
+        let runs = run_lengths(samples.iter().map(|s| s.cts_offset));
+        let mut ctts = vec![];
+        push_u32(&mut ctts, runs.len() as u32);
+        for (count, offset) in runs {
+            push_u32(&mut ctts, count);
+            ctts.extend_from_slice(&offset.to_be_bytes());
+        }
+        let version = if samples.iter().any(|s| s.cts_offset < 0) {
+            1
+        } else {
+            0
+        };
+        p.extend_from_slice(&full_box(b"ctts", version, 0, &ctts));
+    }
+
+    if track.kind == TrackKind::Video && samples.iter().any(|s| !s.keyframe) {
+        let mut stss = vec![];
+        let keyframes: Vec<u32> = (1..)
+            .zip(samples.iter())
+            .filter(|(_, s)| s.keyframe)
+            .map(|(i, _)| i)
+            .collect();
+        push_u32(&mut stss, keyframes.len() as u32);
+        for index in keyframes {
+            push_u32(&mut stss, index);
+        }
+        p.extend_from_slice(&full_box(b"stss", 0, 0, &stss));
+    }
+
+    let mut stsc = vec![];
+    let runs = run_lengths(table.chunks.iter().map(|c| c.1));
+    push_u32(&mut stsc, runs.len() as u32);
+    let mut first_chunk = 1;
+    for (count, samples_per_chunk) in runs {
+        push_u32(&mut stsc, first_chunk);
+        push_u32(&mut stsc, samples_per_chunk);
+        push_u32(&mut stsc, 1);
+        first_chunk += count;
+    }
+    p.extend_from_slice(&full_box(b"stsc", 0, 0, &stsc));
+
+    let mut stsz = vec![];
+    push_u32(&mut stsz, 0);
+    push_u32(&mut stsz, samples.len() as u32);
+    for sample in samples {
+        push_u32(&mut stsz, sample.size);
+    }
+    p.extend_from_slice(&full_box(b"stsz", 0, 0, &stsz));
+
+    let mut stco = vec![];
+    push_u32(&mut stco, table.chunks.len() as u32);
+    if table.large_offsets {
+        for (offset, _) in &table.chunks {
+            stco.extend_from_slice(&offset.to_be_bytes());
+        }
+        p.extend_from_slice(&full_box(b"co64", 0, 0, &stco));
+    } else {
+        for (offset, _) in &table.chunks {
+            push_u32(&mut stco, *offset as u32);
+        }
+        p.extend_from_slice(&full_box(b"stco", 0, 0, &stco));
+    }
+    mp4_box(b"stbl", &p)
+}
+
+fn trak(track: &Track, table: &SampleTable) -> Vec<u8> {
+    let media_duration: u64 = table.samples.iter().map(|s| s.duration as u64).sum();
+    let movie_duration = media_duration * MOVIE_TIMESCALE as u64 / track.timescale as u64;
+
+    let mut minf = match track.kind {
+        TrackKind::Video => full_box(b"vmhd", 0, 1, &[0; 8]),
+        TrackKind::Audio => full_box(b"smhd", 0, 0, &[0; 4]),
+    };
+    let mut dref = 1_u32.to_be_bytes().to_vec();
+    dref.extend_from_slice(&full_box(b"url ", 0, 1, &[]));
+    minf.extend_from_slice(&mp4_box(b"dinf", &full_box(b"dref", 0, 0, &dref)));
+    minf.extend_from_slice(&stbl(track, table));
+
+    let mut mdia = mdhd(track, media_duration);
+    mdia.extend_from_slice(&hdlr(track.kind));
+    mdia.extend_from_slice(&mp4_box(b"minf", &minf));
+
+    //stts only holds durations,a track starting later waits in an empty edit
+    let delay = start_delay(track, table);
+    let mut p = tkhd(track, delay + movie_duration);
+    //shift the presentation of b-frames streams back to zero
+    let first_cts = table.samples.first().map(|s| s.cts_offset).unwrap_or(0);
+    let mut edits = vec![];
+    if delay > 0 {
+        edits.push((delay, -1));
+    }
+    if first_cts > 0 || delay > 0 {
+        edits.push((movie_duration, first_cts.max(0) as i64));
+    }
+    if !edits.is_empty() {
+        p.extend_from_slice(&edts(&edits));
+    }
+    p.extend_from_slice(&mp4_box(b"mdia", &mdia));
+    mp4_box(b"trak", &p)
+}
+
+///Build the moov box of a progressive file
+pub fn progressive_moov(tracks: &[(Track, SampleTable)]) -> Vec<u8> {
+    let duration = tracks
+        .iter()
+        .map(|(track, table)| {
+            start_delay(track, table)
+                + table.samples.iter().map(|s| s.duration as u64).sum::<u64>()
+                    * MOVIE_TIMESCALE as u64
+                    / track.timescale as u64
+        })
+        .max()
+        .unwrap_or(0);
+    let next_track_id = tracks.iter().map(|(t, _)| t.id).max().unwrap_or(0) + 1;
+    let mut p = mvhd(duration, next_track_id);
+    for (track, table) in tracks {
+        p.extend_from_slice(&trak(track, table));
+    }
+    mp4_box(b"moov", &p)
+}
+
+///Build the moov box of a fragmented file,samples are described by the fragments
+pub fn fragmented_moov(tracks: &[Track]) -> Vec<u8> {
+    let next_track_id = tracks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+    let mut p = mvhd(0, next_track_id);
+    let mut mvex = vec![];
+    for track in tracks {
+        p.extend_from_slice(&trak(track, &SampleTable::default()));
+        let mut trex = vec![];
+        push_u32(&mut trex, track.id);
+        push_u32(&mut trex, 1);
+        push_u32(&mut trex, 0);
+        push_u32(&mut trex, 0);
+        push_u32(&mut trex, 0);
+        mvex.extend_from_slice(&full_box(b"trex", 0, 0, &trex));
+    }
+    p.extend_from_slice(&mp4_box(b"mvex", &mvex));
+    mp4_box(b"moov", &p)
+}
+
+fn traf(track: &Track, samples: &[Sample], data_offset: u32) -> Vec<u8> {
+    //default-base-is-moof
+    let mut p = full_box(b"tfhd", 0, 0x02_0000, &track.id.to_be_bytes());
+    let base = samples.first().map(|s| s.dts).unwrap_or(0);
+    p.extend_from_slice(&full_box(b"tfdt", 1, 0, &base.to_be_bytes()));
+    let mut trun = vec![];
+    push_u32(&mut trun, samples.len() as u32);
+    push_u32(&mut trun, data_offset);
+    for sample in samples {
+        push_u32(&mut trun, sample.duration);
+        push_u32(&mut trun, sample.size);
+        push_u32(
+            &mut trun,
+            if sample.keyframe {
+                SAMPLE_FLAGS_SYNC
+            } else {
+                SAMPLE_FLAGS_NON_SYNC
+            },
+        );
+        trun.extend_from_slice(&sample.cts_offset.to_be_bytes());
+    }
+    //data offset,duration,size,flags and composition time offset present
+    p.extend_from_slice(&full_box(b"trun", 1, 0x0F01, &trun));
+    mp4_box(b"traf", &p)
+}
+
+///Build a moof box,the data of every track is expected to follow in one mdat in the same order
+pub fn moof(sequence: u32, fragments: &[(&Track, &[Sample])]) -> Vec<u8> {
+    let build = |moof_size: u32| {
+        let mut p = full_box(b"mfhd", 0, 0, &sequence.to_be_bytes());
+        let mut data_offset = moof_size + 8;
+        for (track, samples) in fragments {
+            p.extend_from_slice(&traf(track, samples, data_offset));
+            data_offset += samples.iter().map(|s| s.size).sum::<u32>();
+        }
+        mp4_box(b"moof", &p)
+    };
+    let size = build(0).len() as u32;
+    build(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mp4_reader::{children, find};
+    use flv_parser::flv::avc::AvcDecoderConfig;
+    use std::convert::TryInto;
+
+    ///A 320x240 baseline avcC
+    const AVC_CONFIG: [u8; 23] = [
+        0x01, 0x42, 0x00, 0x1E, 0xFF, 0xE1, 0x00, 0x08, 0x67, 0x42, 0x00, 0x1E, 0xF4, 0x0A, 0x0F,
+        0xC8, 0x01, 0x00, 0x04, 0x68, 0xCE, 0x38, 0x80,
+    ];
+
+    fn video_track() -> Track {
+        let config = AvcDecoderConfig::from_bytes(&AVC_CONFIG).unwrap();
+        Track::new(1, TrackKind::Video, CodecConfig::Avc(config))
+    }
+
+    ///I P B in decode order,presented I B P
+    fn samples() -> Vec<Sample> {
+        [(3000, 10, true), (6000, 20, false), (0, 30, false)]
+            .iter()
+            .zip(0..)
+            .map(|(&(cts_offset, size, keyframe), i)| Sample {
+                dts: i * 3000,
+                duration: 3000,
+                cts_offset,
+                size,
+                keyframe,
+            })
+            .collect()
+    }
+
+    fn names(data: &[u8]) -> Vec<&[u8; 4]> {
+        children(data)
+            .unwrap()
+            .into_iter()
+            .map(|(f, _)| f)
+            .collect()
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn progressive_moov_describes_b_frames() {
+        let track = video_track();
+        assert_eq!(track.config.picture_size(), Some((320, 240)));
+        let table = SampleTable {
+            samples: samples(),
+            chunks: vec![(48, 3)],
+            large_offsets: false,
+        };
+        let file = progressive_moov(&[(track, table)]);
+        let moov = children(&file).unwrap()[0].1;
+        assert_eq!(names(moov), [b"mvhd", b"trak"]);
+        let trak = find(moov, &[b"trak"]).unwrap().unwrap();
+        assert_eq!(names(trak), [b"tkhd", b"edts", b"mdia"]);
+        let stbl = find(trak, &[b"mdia", b"minf", b"stbl"]).unwrap().unwrap();
+        assert_eq!(
+            names(stbl),
+            [b"stsd", b"stts", b"ctts", b"stss", b"stsc", b"stsz", b"stco"]
+        );
+
+        //one run of 3 samples lasting 3000
+        let stts = find(stbl, &[b"stts"]).unwrap().unwrap();
+        assert_eq!(&stts[4..], [0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0x0B, 0xB8]);
+        let ctts = find(stbl, &[b"ctts"]).unwrap().unwrap();
+        assert_eq!(ctts[0], 0);
+        assert_eq!(u32_at(ctts, 4), 3);
+        let offsets: Vec<u32> = (0..3).map(|i| u32_at(ctts, 12 + i * 8)).collect();
+        assert_eq!(offsets, [3000, 6000, 0]);
+        let stss = find(stbl, &[b"stss"]).unwrap().unwrap();
+        assert_eq!(&stss[4..], [0, 0, 0, 1, 0, 0, 0, 1]);
+
+        //the edit starts the presentation at the first frame shown,not at 0
+        let elst = find(trak, &[b"edts", b"elst"]).unwrap().unwrap();
+        assert_eq!(elst[0], 1);
+        assert_eq!(u32_at(elst, 4), 1);
+        assert_eq!(u64::from_be_bytes(elst[8..16].try_into().unwrap()), 100);
+        assert_eq!(i64::from_be_bytes(elst[16..24].try_into().unwrap()), 3000);
+    }
+
+    #[test]
+    fn fragmented_moov_leaves_samples_to_the_fragments() {
+        let track = video_track();
+        let file = fragmented_moov(std::slice::from_ref(&track));
+        let moov = children(&file).unwrap()[0].1;
+        assert_eq!(names(moov), [b"mvhd", b"trak", b"mvex"]);
+        let trak = find(moov, &[b"trak"]).unwrap().unwrap();
+        assert_eq!(names(trak), [b"tkhd", b"mdia"]);
+        let stbl = find(trak, &[b"mdia", b"minf", b"stbl"]).unwrap().unwrap();
+        assert_eq!(names(stbl), [b"stsd", b"stts", b"stsc", b"stsz", b"stco"]);
+        assert_eq!(u32_at(find(stbl, &[b"stts"]).unwrap().unwrap(), 4), 0);
+        assert!(find(moov, &[b"mvex", b"trex"]).unwrap().is_some());
+
+        let samples = samples();
+        let fragment = moof(7, &[(&track, &samples)]);
+        let moof_size = fragment.len() as u32;
+        let moof = children(&fragment).unwrap()[0].1;
+        assert_eq!(names(moof), [b"mfhd", b"traf"]);
+        assert_eq!(u32_at(find(moof, &[b"mfhd"]).unwrap().unwrap(), 4), 7);
+        let traf = find(moof, &[b"traf"]).unwrap().unwrap();
+        assert_eq!(names(traf), [b"tfhd", b"tfdt", b"trun"]);
+        let trun = find(traf, &[b"trun"]).unwrap().unwrap();
+        //version 1 for signed composition offsets
+        assert_eq!(trun[..4], [1, 0, 0x0F, 0x01]);
+        assert_eq!(u32_at(trun, 4), 3);
+        //data starts right after the mdat header
+        assert_eq!(u32_at(trun, 8), moof_size + 8);
+        let entries: Vec<[u32; 4]> = (0..3)
+            .map(|i| {
+                let offset = 12 + i * 16;
+                [0, 4, 8, 12].map(|field| u32_at(trun, offset + field))
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                [3000, 10, SAMPLE_FLAGS_SYNC, 3000],
+                [3000, 20, SAMPLE_FLAGS_NON_SYNC, 6000],
+                [3000, 30, SAMPLE_FLAGS_NON_SYNC, 0],
+            ]
+        );
+    }
+}
@@ -0,0 +1,591 @@
+//! ISO BMFF(mp4) reader,the tracks and sample tables of progressive files.
+
+use crate::media::{invalid_data, CodecConfig, TrackKind};
+use crate::mp4::Sample;
+use flv_parser::flv::{
+    aac::AudioSpecificConfig, avc::AvcDecoderConfig, hevc::HevcDecoderConfig, mp3::Mp3FrameHeader,
+};
+use std::{
+    convert::TryInto,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+///Object type indications of the esds decoder config
+const OBJECT_TYPE_AAC: u8 = 0x40;
+const OBJECT_TYPE_MPEG2_AAC: [u8; 3] = [0x66, 0x67, 0x68];
+const OBJECT_TYPE_MP3: [u8; 2] = [0x69, 0x6B];
+
+fn truncated(fourcc: &[u8; 4]) -> io::Error {
+    invalid_data(format!("Truncated {} box", String::from_utf8_lossy(fourcc)))
+}
+
+///A sample of an input track and where its data is
+#[derive(Debug, Clone)]
+pub struct StoredSample {
+    pub sample: Sample,
+    pub offset: u64,
+    ///Index of the sample description coding this sample
+    pub description: usize,
+}
+
+///A track of the input movie
+#[derive(Debug, Clone)]
+pub struct InputTrack {
+    pub id: u32,
+    pub kind: Option<TrackKind>,
+    pub timescale: u32,
+    ///Fourcc of every sample description
+    pub formats: Vec<[u8; 4]>,
+    ///Decoder config of every sample description,None for unsupported codecs
+    pub configs: Vec<Option<CodecConfig>>,
+    ///Milliseconds added to the media times,from the edit list
+    pub time_offset: f64,
+    pub samples: Vec<StoredSample>,
+}
+
+impl InputTrack {
+    ///Codec name of the first sample description,for messages
+    pub fn format_name(&self) -> String {
+        self.formats
+            .first()
+            .map(|f| String::from_utf8_lossy(f).into_owned())
+            .unwrap_or_else(|| "none".to_string())
+    }
+
+    ///Convert a time in the track timescale to milliseconds of the presentation
+    pub fn to_millis(&self, time: i64) -> f64 {
+        time as f64 * 1000.0 / self.timescale.max(1) as f64 + self.time_offset
+    }
+}
+
+///Big endian fields of a box payload
+struct Cursor<'a> {
+    fourcc: &'a [u8; 4],
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(fourcc: &'a [u8; 4], data: &'a [u8]) -> Self {
+        Self {
+            fourcc,
+            data,
+            position: 0,
+        }
+    }
+
+    fn bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or_else(|| truncated(self.fourcc))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, count: usize) -> io::Result<()> {
+        self.bytes(count).map(|_| ())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(((self.u32()? as u64) << 32) | self.u32()? as u64)
+    }
+
+    ///Version of a full box,the flags are skipped
+    fn version(&mut self) -> io::Result<u8> {
+        let version = self.u8()?;
+        self.skip(3)?;
+        Ok(version)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.position.min(self.data.len())..];
+        self.position = self.data.len();
+        rest
+    }
+}
+
+///Split a payload into its child boxes
+pub(crate) fn children(data: &[u8]) -> io::Result<Vec<(&[u8; 4], &[u8])>> {
+    let mut boxes = vec![];
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let size = u32::from_be_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ]) as u64;
+        let fourcc: &[u8; 4] = data[offset + 4..offset + 8].try_into().unwrap();
+        let (header_size, size) = match size {
+            0 => (8, (data.len() - offset) as u64),
+            1 => {
+                let large = data
+                    .get(offset + 8..offset + 16)
+                    .ok_or_else(|| truncated(fourcc))?;
+                (16, u64::from_be_bytes(large.try_into().unwrap()))
+            }
+            size => (8, size),
+        };
+        if size < header_size as u64 || offset as u64 + size > data.len() as u64 {
+            return Err(truncated(fourcc));
+        }
+        let end = offset + size as usize;
+        boxes.push((fourcc, &data[offset + header_size..end]));
+        offset = end;
+    }
+    Ok(boxes)
+}
+
+///Payload of the first box at path
+pub(crate) fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> io::Result<Option<&'a [u8]>> {
+    let mut data = data;
+    for fourcc in path {
+        match children(data)?.into_iter().find(|(f, _)| f == fourcc) {
+            Some((_, payload)) => data = payload,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(data))
+}
+
+///Read the payload of the top level moov box
+fn read_moov(input: &mut (impl Read + Seek)) -> io::Result<Vec<u8>> {
+    let file_size = input.seek(SeekFrom::End(0))?;
+    let mut offset = input.seek(SeekFrom::Start(0))?;
+    let mut has_moof = false;
+    while offset + 8 <= file_size {
+        let mut header = [0_u8; 8];
+        input.read_exact(&mut header)?;
+        let mut header_size = 8;
+        let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            0 => file_size - offset,
+            1 => {
+                let mut large = [0_u8; 8];
+                input.read_exact(&mut large)?;
+                header_size = 16;
+                u64::from_be_bytes(large)
+            }
+            size => size as u64,
+        };
+        if size < header_size || offset + size > file_size {
+            return Err(invalid_data(format!(
+                "Invalid box size:{} at {},not an mp4 file?",
+                size, offset
+            )));
+        }
+        match &header[4..] {
+            b"moov" => {
+                let mut moov = vec![0_u8; (size - header_size) as usize];
+                input.read_exact(&mut moov)?;
+                return Ok(moov);
+            }
+            b"moof" => has_moof = true,
+            _ => {}
+        }
+        offset = input.seek(SeekFrom::Start(offset + size))?;
+    }
+    Err(invalid_data(if has_moof {
+        "Fragmented mp4 without moov".to_string()
+    } else {
+        "No moov box found".to_string()
+    }))
+}
+
+///Read an expandable descriptor size
+fn descriptor_size(cursor: &mut Cursor) -> io::Result<usize> {
+    let mut size = 0;
+    for _ in 0..4 {
+        let byte = cursor.u8()?;
+        size = (size << 7) | (byte & 0x7F) as usize;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(size)
+}
+
+///Object type indication and decoder specific info of an esds box
+fn parse_esds(data: &[u8]) -> io::Result<(u8, Vec<u8>)> {
+    let mut cursor = Cursor::new(b"esds", data);
+    cursor.version()?;
+    if cursor.u8()? != 0x03 {
+        return Err(truncated(b"esds"));
+    }
+    descriptor_size(&mut cursor)?;
+    cursor.skip(2)?;
+    let flags = cursor.u8()?;
+    if flags & 0x80 != 0 {
+        cursor.skip(2)?;
+    }
+    if flags & 0x40 != 0 {
+        let len = cursor.u8()? as usize;
+        cursor.skip(len)?;
+    }
+    if flags & 0x20 != 0 {
+        cursor.skip(2)?;
+    }
+    if cursor.u8()? != 0x04 {
+        return Err(truncated(b"esds"));
+    }
+    descriptor_size(&mut cursor)?;
+    let object_type = cursor.u8()?;
+    cursor.skip(12)?;
+    let mut specific_info = vec![];
+    if cursor.u8().ok() == Some(0x05) {
+        let size = descriptor_size(&mut cursor)?;
+        specific_info = cursor.bytes(size)?.to_vec();
+    }
+    Ok((object_type, specific_info))
+}
+
+///Codec of a sample entry
+enum SampleEntry {
+    Config(CodecConfig),
+    ///Mp3 has no decoder config,the header of the first sample is used
+    Mp3,
+    Unsupported,
+}
+
+fn parse_sample_entry(
+    kind: Option<TrackKind>,
+    fourcc: &[u8; 4],
+    data: &[u8],
+) -> io::Result<SampleEntry> {
+    let mut cursor = Cursor::new(fourcc, data);
+    cursor.skip(8)?;
+    match kind {
+        Some(TrackKind::Video) => {
+            cursor.skip(70)?;
+            let boxes = children(cursor.rest())?;
+            let config = |name: &[u8; 4]| boxes.iter().find(|(f, _)| *f == name).map(|b| b.1);
+            Ok(match (fourcc, config(b"avcC"), config(b"hvcC")) {
+                (b"avc1", Some(record), _) | (b"avc3", Some(record), _) => {
+                    SampleEntry::Config(CodecConfig::Avc(AvcDecoderConfig::from_bytes(record)?))
+                }
+                (b"hvc1", _, Some(record)) | (b"hev1", _, Some(record)) => {
+                    SampleEntry::Config(CodecConfig::Hevc(HevcDecoderConfig::from_bytes(record)?))
+                }
+                _ => SampleEntry::Unsupported,
+            })
+        }
+        Some(TrackKind::Audio) => {
+            //quicktime sound descriptions have extra fields after version 1 and 2
+            let version = cursor.u16()?;
+            cursor.skip(18)?;
+            match version {
+                1 => cursor.skip(16)?,
+                2 => cursor.skip(36)?,
+                _ => {}
+            }
+            let boxes = children(cursor.rest())?;
+            let esds = boxes.iter().find(|(f, _)| *f == b"esds").map(|b| b.1);
+            Ok(match (fourcc, esds) {
+                (b"mp4a", Some(esds)) => {
+                    let (object_type, specific_info) = parse_esds(esds)?;
+                    if object_type == OBJECT_TYPE_AAC
+                        || OBJECT_TYPE_MPEG2_AAC.contains(&object_type)
+                    {
+                        SampleEntry::Config(CodecConfig::Aac(AudioSpecificConfig::from_bytes(
+                            &specific_info,
+                        )?))
+                    } else if OBJECT_TYPE_MP3.contains(&object_type) {
+                        SampleEntry::Mp3
+                    } else {
+                        SampleEntry::Unsupported
+                    }
+                }
+                (b".mp3", _) => SampleEntry::Mp3,
+                _ => SampleEntry::Unsupported,
+            })
+        }
+        None => Ok(SampleEntry::Unsupported),
+    }
+}
+
+///The samples described by the sample table
+fn parse_samples(stbl: &[u8]) -> io::Result<Vec<StoredSample>> {
+    let table = |fourcc: &'static [u8; 4]| -> io::Result<Option<Cursor>> {
+        Ok(find(stbl, &[fourcc])?.map(|data| Cursor::new(fourcc, data)))
+    };
+
+    //sample sizes
+    let mut sizes = vec![];
+    if let Some(mut stsz) = table(b"stsz")? {
+        stsz.version()?;
+        let size = stsz.u32()?;
+        let count = stsz.u32()? as usize;
+        if size != 0 {
+            sizes = vec![size; count];
+        } else {
+            for _ in 0..count {
+                sizes.push(stsz.u32()?);
+            }
+        }
+    } else if let Some(mut stz2) = table(b"stz2")? {
+        stz2.version()?;
+        stz2.skip(3)?;
+        let field_size = stz2.u8()?;
+        let count = stz2.u32()? as usize;
+        if field_size == 4 {
+            for byte in stz2.bytes(count.div_ceil(2))? {
+                sizes.push((byte >> 4) as u32);
+                sizes.push((byte & 0x0F) as u32);
+            }
+            sizes.truncate(count);
+        } else {
+            for _ in 0..count {
+                sizes.push(match field_size {
+                    8 => stz2.u8()? as u32,
+                    _ => stz2.u16()? as u32,
+                });
+            }
+        }
+    }
+
+    //chunk offsets
+    let mut chunks = vec![];
+    if let Some(mut stco) = table(b"stco")? {
+        stco.version()?;
+        for _ in 0..stco.u32()? {
+            chunks.push(stco.u32()? as u64);
+        }
+    } else if let Some(mut co64) = table(b"co64")? {
+        co64.version()?;
+        for _ in 0..co64.u32()? {
+            chunks.push(co64.u64()?);
+        }
+    }
+
+    //(first chunk,samples per chunk,sample description index)
+    let mut sample_to_chunk = vec![];
+    if let Some(mut stsc) = table(b"stsc")? {
+        stsc.version()?;
+        for _ in 0..stsc.u32()? {
+            sample_to_chunk.push((stsc.u32()?, stsc.u32()?, stsc.u32()?));
+        }
+    }
+
+    let mut samples = Vec::with_capacity(sizes.len());
+    let mut sizes_iter = sizes.iter();
+    'chunks: for (index, chunk_offset) in chunks.iter().enumerate() {
+        let chunk_number = index as u32 + 1;
+        let (_, per_chunk, description) = match sample_to_chunk
+            .iter()
+            .rev()
+            .find(|(first, _, _)| *first <= chunk_number)
+        {
+            Some(entry) => *entry,
+            None => break,
+        };
+        let mut offset = *chunk_offset;
+        for _ in 0..per_chunk {
+            let size = match sizes_iter.next() {
+                Some(size) => *size,
+                None => break 'chunks,
+            };
+            samples.push(StoredSample {
+                sample: Sample {
+                    size,
+                    keyframe: true,
+                    ..Default::default()
+                },
+                offset,
+                description: description.saturating_sub(1) as usize,
+            });
+            offset += size as u64;
+        }
+    }
+
+    //decode times,the last delta is repeated if the table is short
+    if let Some(mut stts) = table(b"stts")? {
+        stts.version()?;
+        let mut dts = 0_u64;
+        let mut index = 0;
+        let mut delta = 0;
+        for _ in 0..stts.u32()? {
+            let count = stts.u32()?;
+            delta = stts.u32()?;
+            for _ in 0..count {
+                if let Some(stored) = samples.get_mut(index) {
+                    stored.sample.dts = dts;
+                    stored.sample.duration = delta;
+                }
+                dts += delta as u64;
+                index += 1;
+            }
+        }
+        for stored in samples.iter_mut().skip(index) {
+            stored.sample.dts = dts;
+            stored.sample.duration = delta;
+            dts += delta as u64;
+        }
+    }
+
+    //composition offsets,version 0 offsets are read as signed like most readers do
+    if let Some(mut ctts) = table(b"ctts")? {
+        ctts.version()?;
+        let mut index = 0;
+        for _ in 0..ctts.u32()? {
+            let count = ctts.u32()?;
+            let offset = ctts.u32()? as i32;
+            for _ in 0..count {
+                if let Some(stored) = samples.get_mut(index) {
+                    stored.sample.cts_offset = offset;
+                }
+                index += 1;
+            }
+        }
+    }
+
+    //sync samples,all samples are sync samples without the table
+    if let Some(mut stss) = table(b"stss")? {
+        stss.version()?;
+        for stored in samples.iter_mut() {
+            stored.sample.keyframe = false;
+        }
+        for _ in 0..stss.u32()? {
+            let number = stss.u32()? as usize;
+            if let Some(stored) = number.checked_sub(1).and_then(|i| samples.get_mut(i)) {
+                stored.sample.keyframe = true;
+            }
+        }
+    }
+    Ok(samples)
+}
+
+///Milliseconds the edit list shifts the media times by
+fn parse_edit_list(edts: &[u8], movie_timescale: u32, timescale: u32) -> io::Result<f64> {
+    let mut elst = match find(edts, &[b"elst"])? {
+        Some(data) => Cursor::new(b"elst", data),
+        None => return Ok(0.0),
+    };
+    let version = elst.version()?;
+    let mut empty = 0_u64;
+    for _ in 0..elst.u32()? {
+        let (duration, media_time) = if version == 1 {
+            (elst.u64()?, elst.u64()? as i64)
+        } else {
+            (elst.u32()? as u64, elst.u32()? as i32 as i64)
+        };
+        elst.skip(4)?;
+        if media_time == -1 {
+            empty += duration;
+        } else {
+            return Ok(empty as f64 * 1000.0 / movie_timescale.max(1) as f64
+                - media_time as f64 * 1000.0 / timescale.max(1) as f64);
+        }
+    }
+    Ok(empty as f64 * 1000.0 / movie_timescale.max(1) as f64)
+}
+
+fn parse_track(
+    input: &mut (impl Read + Seek),
+    trak: &[u8],
+    movie_timescale: u32,
+) -> io::Result<InputTrack> {
+    let mut tkhd = Cursor::new(
+        b"tkhd",
+        find(trak, &[b"tkhd"])?.ok_or_else(|| truncated(b"trak"))?,
+    );
+    let skip = if tkhd.version()? == 1 { 16 } else { 8 };
+    tkhd.skip(skip)?;
+    let id = tkhd.u32()?;
+
+    let mut mdhd = Cursor::new(
+        b"mdhd",
+        find(trak, &[b"mdia", b"mdhd"])?.ok_or_else(|| truncated(b"mdia"))?,
+    );
+    let skip = if mdhd.version()? == 1 { 16 } else { 8 };
+    mdhd.skip(skip)?;
+    let timescale = mdhd.u32()?;
+
+    let kind = match find(trak, &[b"mdia", b"hdlr"])? {
+        Some(data) => match data.get(8..12) {
+            Some(b"vide") => Some(TrackKind::Video),
+            Some(b"soun") => Some(TrackKind::Audio),
+            _ => None,
+        },
+        None => None,
+    };
+
+    let stbl = find(trak, &[b"mdia", b"minf", b"stbl"])?.ok_or_else(|| truncated(b"minf"))?;
+    let samples = parse_samples(stbl)?;
+    let mut formats = vec![];
+    let mut configs = vec![];
+    if let Some(stsd) = find(stbl, &[b"stsd"])? {
+        for (fourcc, entry) in children(stsd.get(8..).ok_or_else(|| truncated(b"stsd"))?)? {
+            let config = match parse_sample_entry(kind, fourcc, entry)? {
+                SampleEntry::Config(config) => Some(config),
+                SampleEntry::Mp3 => match samples.iter().find(|s| s.description == formats.len()) {
+                    Some(stored) => {
+                        let mut header = [0_u8; 4];
+                        input.seek(SeekFrom::Start(stored.offset))?;
+                        input.read_exact(&mut header)?;
+                        Mp3FrameHeader::from_bytes(&header)
+                            .ok()
+                            .map(CodecConfig::Mp3)
+                    }
+                    None => None,
+                },
+                SampleEntry::Unsupported => None,
+            };
+            formats.push(*fourcc);
+            configs.push(config);
+        }
+    }
+    let time_offset = match find(trak, &[b"edts"])? {
+        Some(edts) => parse_edit_list(edts, movie_timescale, timescale)?,
+        None => 0.0,
+    };
+    Ok(InputTrack {
+        id,
+        kind,
+        timescale,
+        formats,
+        configs,
+        time_offset,
+        samples,
+    })
+}
+
+///Read the tracks of a progressive mp4 file
+pub fn read_tracks(input: &mut (impl Read + Seek)) -> io::Result<Vec<InputTrack>> {
+    let moov = read_moov(input)?;
+    let movie_timescale = match find(&moov, &[b"mvhd"])? {
+        Some(data) => {
+            let mut mvhd = Cursor::new(b"mvhd", data);
+            let skip = if mvhd.version()? == 1 { 16 } else { 8 };
+            mvhd.skip(skip)?;
+            mvhd.u32()?
+        }
+        None => 1000,
+    };
+    let mut tracks = vec![];
+    for (fourcc, trak) in children(&moov)? {
+        if fourcc == b"trak" {
+            tracks.push(parse_track(input, trak, movie_timescale)?);
+        }
+    }
+    if find(&moov, &[b"mvex"])?.is_some() && tracks.iter().all(|t| t.samples.is_empty()) {
+        return Err(invalid_data(
+            "Fragmented mp4 is not supported,samples are in moof boxes".to_string(),
+        ));
+    }
+
+    Ok(tracks)
+}
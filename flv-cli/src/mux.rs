@@ -0,0 +1,648 @@
+//! `mux` command: build flv from H.264 Annex B and AAC ADTS elementary streams.
+
+use crate::media::{invalid_data, AudioMetadata, Metadata, VideoMetadata};
+use crate::open_output;
+use flv_parser::flv::{
+    aac::{AudioSpecificConfig, SAMPLING_FREQUENCIES},
+    avc::{self, AvcDecoderConfig, SliceHeader, Sps},
+    Header, Tag, Writer,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, Read, Write},
+};
+
+const READ_SIZE: usize = 64 * 1024;
+///Samples of an AAC frame,HE-AAC counts them at the core rate
+const AAC_FRAME_SAMPLES: f64 = 1024.0;
+///Flags byte of AAC audio tags,the rate/size/type fields are fixed for AAC
+const AAC_SOUND_FLAGS: u8 = (Tag::SOUND_FORMAT_AAC << 4) | 0x0F;
+
+///Read until buf is full or the end of input,returns the count of bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut count = 0;
+    while count < buf.len() {
+        match reader.read(&mut buf[count..]) {
+            Ok(0) => break,
+            Ok(n) => count += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(count)
+}
+
+///Position of the first 00 00 01 at or after from
+fn find_start_code(data: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(3)
+        .position(|w| w == [0, 0, 1])
+        .map(|p| p + from)
+}
+
+///Split an Annex B byte stream into nal units
+pub(crate) struct NalReader<R: Read> {
+    inner: R,
+    buffer: Vec<u8>,
+    ///Start of the data not returned yet
+    position: usize,
+    eof: bool,
+}
+
+impl<R: Read> NalReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: vec![],
+            position: 0,
+            eof: false,
+        }
+    }
+
+    ///Drop the consumed data and read more,returns the count of bytes dropped
+    fn fill(&mut self) -> io::Result<usize> {
+        let consumed = self.position;
+        self.buffer.drain(..consumed);
+        self.position = 0;
+        let len = self.buffer.len();
+        self.buffer.resize(len + READ_SIZE, 0);
+        let count = read_full(&mut self.inner, &mut self.buffer[len..])?;
+        self.buffer.truncate(len + count);
+        self.eof = count < READ_SIZE;
+        Ok(consumed)
+    }
+
+    pub fn next_nal(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(start) = find_start_code(&self.buffer, self.position) {
+                self.position = start + 3;
+                break;
+            }
+            if self.eof {
+                return Ok(None);
+            }
+            //the last 2 bytes may begin a start code
+            self.position = self.buffer.len().saturating_sub(2).max(self.position);
+            self.fill()?;
+        }
+        let mut from = self.position;
+        let end = loop {
+            if let Some(end) = find_start_code(&self.buffer, from) {
+                break end;
+            }
+            if self.eof {
+                break self.buffer.len();
+            }
+            from = self.buffer.len().saturating_sub(2).max(self.position);
+            from -= self.fill()?;
+        };
+        //trailing zeros belong to the next start code
+        let nal_end = self.buffer[self.position..end]
+            .iter()
+            .rposition(|b| *b != 0)
+            .map_or(self.position, |p| self.position + p + 1);
+        let nal = self.buffer[self.position..nal_end].to_vec();
+        self.position = end;
+        Ok(Some(nal))
+    }
+}
+
+///A coded picture,with the nal units flv keeps
+struct AccessUnit {
+    nals: Vec<Vec<u8>>,
+    keyframe: bool,
+    ///Picture order count,None if it can't be derived
+    poc: Option<i32>,
+}
+
+///Group the nal units of an H.264 stream into access units,
+///collecting the parameter sets for the sequence header
+struct VideoInput<R: Read> {
+    nals: NalReader<R>,
+    ///First nal unit of the next access unit
+    next: Option<Vec<u8>>,
+    sps: BTreeMap<u32, Vec<u8>>,
+    pps: BTreeMap<u32, Vec<u8>>,
+    parsed_sps: HashMap<u32, Sps>,
+    ///sps id of every pps
+    pps_sps: HashMap<u32, u32>,
+    ///Msb and lsb of the previous reference picture,for picture order count type 0
+    previous_reference: (i32, i32),
+    ///Pictures since the last IDR,for picture order count type 2
+    pictures: i32,
+    started: bool,
+    ///Pictures before the first IDR,they can't be decoded
+    skipped: u64,
+}
+
+impl<R: Read> VideoInput<R> {
+    fn new(input: R) -> Self {
+        Self {
+            nals: NalReader::new(input),
+            next: None,
+            sps: BTreeMap::new(),
+            pps: BTreeMap::new(),
+            parsed_sps: HashMap::new(),
+            pps_sps: HashMap::new(),
+            previous_reference: (0, 0),
+            pictures: 0,
+            started: false,
+            skipped: 0,
+        }
+    }
+
+    fn sps_of_pps(&self, pps_id: u32) -> Option<&Sps> {
+        self.pps_sps
+            .get(&pps_id)
+            .and_then(|id| self.parsed_sps.get(id))
+    }
+
+    ///Sequence header built from the latest parameter sets
+    fn config(&self) -> io::Result<AvcDecoderConfig> {
+        if self.pps.is_empty() {
+            return Err(invalid_data("No sps/pps before the first IDR picture"));
+        }
+        AvcDecoderConfig::new(
+            self.sps.values().cloned().collect(),
+            self.pps.values().cloned().collect(),
+        )
+    }
+
+    fn picture_order(&mut self, nal: &[u8], header: &SliceHeader) -> Option<i32> {
+        if nal[0] & 0x1F == avc::NALU_TYPE_IDR {
+            self.previous_reference = (0, 0);
+            self.pictures = 0;
+        }
+        self.pictures += 1;
+        let sps = self.sps_of_pps(header.pps_id())?;
+        if header.field_pic() {
+            return None;
+        }
+        match sps.pic_order_cnt_type() {
+            0 => {
+                let lsb = header.pic_order_cnt_lsb()? as i32;
+                let max = sps.max_pic_order_cnt_lsb() as i32;
+                let (previous_msb, previous_lsb) = self.previous_reference;
+                let msb = if lsb < previous_lsb && previous_lsb - lsb >= max / 2 {
+                    previous_msb + max
+                } else if lsb > previous_lsb && lsb - previous_lsb > max / 2 {
+                    previous_msb - max
+                } else {
+                    previous_msb
+                };
+                //nal_ref_idc
+                if nal[0] & 0x60 != 0 {
+                    self.previous_reference = (msb, lsb);
+                }
+                Some(msb + lsb)
+            }
+            2 => Some(self.pictures),
+            _ => None,
+        }
+    }
+
+    fn read_access_unit(&mut self) -> io::Result<Option<AccessUnit>> {
+        let mut unit = AccessUnit {
+            nals: vec![],
+            keyframe: false,
+            poc: None,
+        };
+        let mut has_slice = false;
+        loop {
+            let nal = match self.next.take() {
+                Some(nal) => nal,
+                None => match self.nals.next_nal()? {
+                    Some(nal) => nal,
+                    None => break,
+                },
+            };
+            if nal.is_empty() {
+                continue;
+            }
+            let nal_type = nal[0] & 0x1F;
+            let slice = if nal_type == 1 || nal_type == avc::NALU_TYPE_IDR {
+                SliceHeader::from_nal(&nal, |pps_id| self.sps_of_pps(pps_id)).ok()
+            } else {
+                None
+            };
+            let starts_picture = match nal_type {
+                1 | avc::NALU_TYPE_IDR => matches!(&slice, Some(h) if h.first_mb_in_slice() == 0),
+                6..=9 | 14..=18 => true,
+                _ => false,
+            };
+            if has_slice && starts_picture {
+                self.next = Some(nal);
+                break;
+            }
+            match nal_type {
+                avc::NALU_TYPE_SPS => {
+                    if let Ok(sps) = Sps::from_nal(&nal) {
+                        self.sps.insert(sps.id(), nal);
+                        self.parsed_sps.insert(sps.id(), sps);
+                    }
+                }
+                avc::NALU_TYPE_PPS => {
+                    if let Ok((pps_id, sps_id)) = avc::pps_ids(&nal) {
+                        self.pps.insert(pps_id, nal);
+                        self.pps_sps.insert(pps_id, sps_id);
+                    }
+                }
+                //access unit delimiter,end of sequence/stream and filler data
+                avc::NALU_TYPE_AUD | 10..=12 => {}
+                _ => {
+                    if let Some(header) = &slice {
+                        if !has_slice {
+                            unit.keyframe = nal_type == avc::NALU_TYPE_IDR;
+                            unit.poc = self.picture_order(&nal, header);
+                        }
+                        has_slice = true;
+                    }
+                    unit.nals.push(nal);
+                }
+            }
+        }
+        Ok(if has_slice { Some(unit) } else { None })
+    }
+
+    ///Next access unit,pictures before the first IDR are skipped
+    fn next_access_unit(&mut self) -> io::Result<Option<AccessUnit>> {
+        while let Some(unit) = self.read_access_unit()? {
+            if unit.keyframe || self.started {
+                self.started = true;
+                return Ok(Some(unit));
+            }
+            self.skipped += 1;
+        }
+        Ok(None)
+    }
+}
+
+///Read AAC frames from an ADTS stream
+pub(crate) struct AdtsReader<R: Read> {
+    inner: R,
+    started: bool,
+    ///A truncated frame ended the stream
+    pub truncated: bool,
+}
+
+impl<R: Read> AdtsReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            started: false,
+            truncated: false,
+        }
+    }
+
+    fn read_or_truncate(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let count = read_full(&mut self.inner, buf)?;
+        if count > 0 && count < buf.len() {
+            self.truncated = true;
+        }
+        Ok(count == buf.len())
+    }
+
+    ///Skip an ID3v2 tag at the start of the stream,header holds its first 7 bytes
+    fn skip_id3(&mut self, header: &mut [u8; 7]) -> io::Result<bool> {
+        let mut rest = [0_u8; 3];
+        if !self.read_or_truncate(&mut rest)? {
+            return Ok(false);
+        }
+        let size = header[6..]
+            .iter()
+            .chain(rest.iter())
+            .fold(0_u64, |size, b| (size << 7) | (*b & 0x7F) as u64);
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        io::copy(&mut (&mut self.inner).take(size + footer), &mut io::sink())?;
+        self.read_or_truncate(header)
+    }
+
+    ///Next frame with the config its header describes
+    pub fn next_frame(&mut self) -> io::Result<Option<(AudioSpecificConfig, Vec<u8>)>> {
+        let mut header = [0_u8; 7];
+        if !self.read_or_truncate(&mut header)? {
+            return Ok(None);
+        }
+        if !self.started {
+            self.started = true;
+            if &header[..3] == b"ID3" && !self.skip_id3(&mut header)? {
+                return Ok(None);
+            }
+        }
+        if header[0] != 0xFF || header[1] & 0xF6 != 0xF0 {
+            return Err(invalid_data("Lost ADTS sync"));
+        }
+        let header_size = if header[1] & 0x01 == 0 { 9 } else { 7 };
+        let profile = header[2] >> 6;
+        let sampling_frequency_index = (header[2] >> 2) & 0x0F;
+        let channel_configuration = ((header[2] & 0x01) << 2) | (header[3] >> 6);
+        let frame_size = (((header[3] & 0x03) as usize) << 11)
+            | ((header[4] as usize) << 3)
+            | (header[5] >> 5) as usize;
+        if sampling_frequency_index as usize >= SAMPLING_FREQUENCIES.len() {
+            return Err(invalid_data("Unexcepted ADTS sampling frequency index"));
+        }
+        if header[6] & 0x03 != 0 {
+            return Err(invalid_data(
+                "ADTS frames with several raw data blocks are not supported",
+            ));
+        }
+        if frame_size < header_size {
+            return Err(invalid_data("Invalid ADTS frame length"));
+        }
+        let mut data = vec![0_u8; frame_size - 7];
+        if !self.read_or_truncate(&mut data)? {
+            return Ok(None);
+        }
+        data.drain(..header_size - 7);
+        let config =
+            AudioSpecificConfig::new(profile + 1, sampling_frequency_index, channel_configuration);
+        Ok(Some((config, data)))
+    }
+}
+
+///Count the bytes of the first pass instead of writing them
+#[derive(Default)]
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+///Composition offsets of reordered pictures,derived from picture order counts
+#[derive(Default)]
+struct Timing {
+    ///Presentation index minus decode index of every picture
+    offsets: Vec<i64>,
+    ///Pictures the presentation lags behind decoding
+    delay: i64,
+}
+
+impl Timing {
+    ///Pictures are sorted by picture order count between IDRs
+    fn new(pocs: &[Option<i32>], keyframes: &[bool]) -> Option<Self> {
+        let pocs = pocs.iter().copied().collect::<Option<Vec<i32>>>()?;
+        let mut offsets = vec![0_i64; pocs.len()];
+        let mut start = 0;
+        while start < pocs.len() {
+            let end = (start + 1..pocs.len())
+                .find(|i| keyframes[*i])
+                .unwrap_or(pocs.len());
+            let mut order: Vec<usize> = (start..end).collect();
+            order.sort_by_key(|i| pocs[*i]);
+            for (rank, i) in order.into_iter().enumerate() {
+                offsets[i] = (start + rank) as i64 - i as i64;
+            }
+            start = end;
+        }
+        let delay = offsets.iter().map(|o| -o).max().unwrap_or(0).max(0);
+        Some(Self { offsets, delay })
+    }
+}
+
+///What the first pass learned about the streams
+#[derive(Debug, Default, Clone)]
+struct Stats {
+    frame_rate: f64,
+    width: u32,
+    height: u32,
+    pocs: Vec<Option<i32>>,
+    keyframes: Vec<bool>,
+    video_bytes: u64,
+    sample_rate: u32,
+    stereo: bool,
+    ///Audio duration in milliseconds
+    audio_duration: f64,
+    audio_frames: u64,
+    audio_bytes: u64,
+}
+
+fn milliseconds(pictures: i64, frame_rate: f64) -> i64 {
+    (pictures as f64 * 1000.0 / frame_rate).round() as i64
+}
+
+///Write one flv of the streams,timing is None in the first pass
+fn write_flv<W: Write>(
+    writer: &mut Writer<W>,
+    video: Option<&str>,
+    audio: Option<&str>,
+    frame_rate: Option<f64>,
+    metadata: &Metadata,
+    timing: Option<&Timing>,
+) -> io::Result<Stats> {
+    let mut stats = Stats::default();
+    let mut video = video.map(File::open).transpose()?.map(VideoInput::new);
+    let mut audio = audio.map(File::open).transpose()?.map(AdtsReader::new);
+    let mut next_picture = match &mut video {
+        Some(video) => Some(
+            video
+                .next_access_unit()?
+                .ok_or_else(|| invalid_data("No IDR picture found in video"))?,
+        ),
+        None => None,
+    };
+    let mut next_audio = match &mut audio {
+        Some(audio) => Some(
+            audio
+                .next_frame()?
+                .ok_or_else(|| invalid_data("No ADTS frame found in audio"))?,
+        ),
+        None => None,
+    };
+    if let Some(video) = &video {
+        let sps = video.config()?.parse_sps()?;
+        stats.frame_rate = frame_rate
+            .or_else(|| sps.frame_rate())
+            .ok_or_else(|| invalid_data("No frame rate in the sps,use --fps"))?;
+        stats.width = sps.width();
+        stats.height = sps.height();
+    }
+    let delay = timing.map_or(0, |t| t.delay);
+    let audio_delay = milliseconds(delay, stats.frame_rate.max(1.0)) as f64;
+
+    writer.write_header(&Header::new(video.is_some(), audio.is_some()))?;
+    writer.write_tag(&metadata.to_tag())?;
+    let mut video_config: Option<AvcDecoderConfig> = None;
+    let mut audio_config: Option<AudioSpecificConfig> = None;
+    let mut last_timestamp = 0;
+    loop {
+        let index = stats.pocs.len() as i64;
+        let video_timestamp = next_picture
+            .as_ref()
+            .map(|_| milliseconds(index, stats.frame_rate) as f64);
+        let audio_timestamp = next_audio
+            .as_ref()
+            .map(|_| (audio_delay + stats.audio_duration).round());
+        let take_video = match (video_timestamp, audio_timestamp) {
+            (Some(video), Some(audio)) => video <= audio,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        if take_video {
+            let input = video.as_mut().unwrap();
+            let picture = next_picture.take().unwrap();
+            let timestamp = video_timestamp.unwrap() as u32;
+            if picture.keyframe {
+                let config = input.config()?;
+                if video_config.as_ref() != Some(&config) {
+                    writer.write_tag(&Tag::video(
+                        timestamp,
+                        Tag::FRAME_TYPE_KEY,
+                        Tag::VIDEO_CODEC_AVC,
+                        Tag::PACKET_TYPE_SEQUENCE_HEADER,
+                        0,
+                        &config.into_bytes(),
+                    ))?;
+                    video_config = Some(config);
+                }
+            }
+            let composition_time = match timing {
+                Some(timing) => {
+                    let offset = timing.offsets.get(index as usize).copied().unwrap_or(0);
+                    milliseconds(index + offset + delay, stats.frame_rate)
+                        - milliseconds(index, stats.frame_rate)
+                }
+                None => 0,
+            };
+            let mut body = vec![];
+            for nal in &picture.nals {
+                body.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                body.extend_from_slice(nal);
+            }
+            let frame_type = if picture.keyframe {
+                Tag::FRAME_TYPE_KEY
+            } else {
+                Tag::FRAME_TYPE_INTER
+            };
+            writer.write_tag(&Tag::video(
+                timestamp,
+                frame_type,
+                Tag::VIDEO_CODEC_AVC,
+                Tag::PACKET_TYPE_NALU,
+                composition_time as i32,
+                &body,
+            ))?;
+            stats.video_bytes += body.len() as u64;
+            stats.pocs.push(picture.poc);
+            stats.keyframes.push(picture.keyframe);
+            last_timestamp = last_timestamp.max(timestamp);
+            next_picture = input.next_access_unit()?;
+        } else {
+            let input = audio.as_mut().unwrap();
+            let (config, frame) = next_audio.take().unwrap();
+            let timestamp = audio_timestamp.unwrap() as u32;
+            if audio_config.as_ref() != Some(&config) {
+                writer.write_tag(&Tag::audio(
+                    timestamp,
+                    AAC_SOUND_FLAGS,
+                    Tag::PACKET_TYPE_SEQUENCE_HEADER,
+                    config.as_bytes(),
+                ))?;
+                stats.sample_rate = config.sampling_frequency();
+                stats.stereo = config.channels() > 1;
+                audio_config = Some(config);
+            }
+            writer.write_tag(&Tag::audio(
+                timestamp,
+                AAC_SOUND_FLAGS,
+                Tag::PACKET_TYPE_NALU,
+                &frame,
+            ))?;
+            stats.audio_duration += AAC_FRAME_SAMPLES * 1000.0 / stats.sample_rate as f64;
+            stats.audio_frames += 1;
+            stats.audio_bytes += frame.len() as u64;
+            last_timestamp = last_timestamp.max(timestamp);
+            next_audio = input.next_frame()?;
+        }
+    }
+    if video.is_some() {
+        writer.write_tag(&Tag::video(
+            last_timestamp,
+            Tag::FRAME_TYPE_KEY,
+            Tag::VIDEO_CODEC_AVC,
+            Tag::PACKET_TYPE_END_OF_SEQUENCE,
+            0,
+            &[],
+        ))?;
+    }
+    //warnings are printed by the second pass only
+    if timing.is_some() {
+        if let Some(video) = &video {
+            if video.skipped > 0 {
+                eprintln!(
+                    "Warning: {} pictures before the first IDR dropped",
+                    video.skipped
+                );
+            }
+        }
+        if matches!(&audio, Some(audio) if audio.truncated) {
+            eprintln!("Warning: truncated ADTS frame at the end dropped");
+        }
+    }
+    writer.flush()?;
+    Ok(stats)
+}
+
+///Mux an H.264 Annex B file and/or an AAC ADTS file into flv.
+///The streams are read twice,the first pass measures what onMetaData and
+///the composition times need.
+pub fn mux(
+    video: Option<&str>,
+    audio: Option<&str>,
+    path: &str,
+    frame_rate: Option<f64>,
+) -> io::Result<()> {
+    let mut counter = Writer::new(ByteCounter::default());
+    let mut metadata = Metadata {
+        file_size: Some(0),
+        video: video.map(|_| VideoMetadata::default()),
+        audio: audio.map(|_| AudioMetadata::default()),
+        ..Default::default()
+    };
+    let stats = write_flv(&mut counter, video, audio, frame_rate, &metadata, None)?;
+    let timing = Timing::new(&stats.pocs, &stats.keyframes).unwrap_or_else(|| {
+        eprintln!("Warning: can't derive the picture order,composition times are left at 0");
+        Timing::default()
+    });
+    let frame_rate = stats.frame_rate.max(1.0);
+    let video_duration = stats.pocs.len() as f64 / frame_rate;
+    let delay = milliseconds(timing.delay, frame_rate) as f64;
+    metadata.duration = (delay + (video_duration * 1000.0).max(stats.audio_duration)) / 1000.0;
+    metadata.file_size = Some(counter.get_ref().0);
+    if let Some(video) = &mut metadata.video {
+        video.codec_id = Tag::VIDEO_CODEC_AVC;
+        video.width = stats.width;
+        video.height = stats.height;
+        video.frame_rate = stats.frame_rate;
+        if video_duration > 0.0 {
+            video.data_rate = stats.video_bytes as f64 * 8.0 / 1000.0 / video_duration;
+        }
+    }
+    if let Some(audio) = &mut metadata.audio {
+        audio.codec_id = Tag::SOUND_FORMAT_AAC;
+        audio.sample_rate = stats.sample_rate;
+        audio.stereo = stats.stereo;
+        if stats.audio_duration > 0.0 {
+            audio.data_rate = stats.audio_bytes as f64 * 8.0 / stats.audio_duration;
+        }
+    }
+    let mut writer = Writer::new(open_output(path)?);
+    write_flv(
+        &mut writer,
+        video,
+        audio,
+        Some(stats.frame_rate),
+        &metadata,
+        Some(&timing),
+    )?;
+    Ok(())
+}
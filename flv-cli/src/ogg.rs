@@ -0,0 +1,183 @@
+//! Ogg page writer for a single logical stream.
+
+use std::io::{self, Write};
+
+///Payload size after which a page is flushed
+const PAGE_SIZE: usize = 4096;
+
+///CRC-32 of ogg pages,polynomial 0x04c11db7 without reflection
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0_u32, |mut crc, b| {
+        crc ^= (*b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+///Pack packets of one logical bitstream into ogg pages
+pub struct OggWriter<W: Write> {
+    output: W,
+    serial: u32,
+    sequence: u32,
+    ///Lacing values and data of the page being filled
+    lacing: Vec<u8>,
+    data: Vec<u8>,
+    ///Granule position of the last packet written
+    granule: i64,
+    first_page: bool,
+    ///The page being filled starts with the rest of a packet
+    continued: bool,
+}
+
+impl<W: Write> OggWriter<W> {
+    pub fn new(output: W, serial: u32) -> Self {
+        Self {
+            output,
+            serial,
+            sequence: 0,
+            lacing: vec![],
+            data: vec![],
+            granule: 0,
+            first_page: true,
+            continued: false,
+        }
+    }
+
+    fn write_page(&mut self, last: bool) -> io::Result<()> {
+        let mut header_type = 0;
+        if self.continued {
+            header_type |= 0x01;
+        }
+        if self.first_page {
+            header_type |= 0x02;
+        }
+        if last {
+            header_type |= 0x04;
+        }
+        let mut page = Vec::with_capacity(27 + self.lacing.len() + self.data.len());
+        page.extend_from_slice(b"OggS");
+        page.push(0);
+        page.push(header_type);
+        //-1 if no packet ends on the page
+        let granule = if self.lacing.iter().all(|l| *l == 255) && !self.lacing.is_empty() {
+            -1
+        } else {
+            self.granule
+        };
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&0_u32.to_le_bytes());
+        page.push(self.lacing.len() as u8);
+        page.extend_from_slice(&self.lacing);
+        page.extend_from_slice(&self.data);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.output.write_all(&page)?;
+        self.sequence += 1;
+        self.first_page = false;
+        self.continued = self.lacing.last() == Some(&255);
+        self.lacing.clear();
+        self.data.clear();
+        Ok(())
+    }
+
+    ///Append a packet ending at granule,flush forces it to end its page.
+    ///A packet needing more than the 255 lacing values of a page continues on the next pages.
+    pub fn write_packet(&mut self, packet: &[u8], granule: i64, flush: bool) -> io::Result<()> {
+        let segments = packet.len() / 255 + 1;
+        if !self.lacing.is_empty() && self.lacing.len() + segments > 255 {
+            self.write_page(false)?;
+        }
+        let mut rest = packet;
+        while rest.len() >= 255 * (255 - self.lacing.len()) {
+            let (head, tail) = rest.split_at(255 * (255 - self.lacing.len()));
+            self.lacing.resize(255, 255);
+            self.data.extend_from_slice(head);
+            self.write_page(false)?;
+            rest = tail;
+        }
+        for _ in 0..rest.len() / 255 {
+            self.lacing.push(255);
+        }
+        self.lacing.push((rest.len() % 255) as u8);
+        self.data.extend_from_slice(rest);
+        self.granule = granule;
+        if flush || self.data.len() >= PAGE_SIZE {
+            self.write_page(false)?;
+        }
+        Ok(())
+    }
+
+    ///Write the pending packets as the last page of the stream
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_page(true)?;
+        Ok(self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///(header type,granule,lacing values,data size) of every page
+    fn pages(mut data: &[u8]) -> Vec<(u8, i64, Vec<u8>, usize)> {
+        let mut pages = vec![];
+        while !data.is_empty() {
+            assert_eq!(&data[..4], b"OggS");
+            let mut page = data.to_vec();
+            page[22..26].copy_from_slice(&[0; 4]);
+            let count = data[26] as usize;
+            let lacing = data[27..27 + count].to_vec();
+            let size: usize = lacing.iter().map(|l| *l as usize).sum();
+            page.truncate(27 + count + size);
+            assert_eq!(crc32(&page).to_le_bytes(), data[22..26]);
+            let mut granule = [0; 8];
+            granule.copy_from_slice(&data[6..14]);
+            pages.push((data[5], i64::from_le_bytes(granule), lacing, size));
+            data = &data[27 + count + size..];
+        }
+        pages
+    }
+
+    #[test]
+    fn continues_packets_longer_than_a_page() {
+        let mut writer = OggWriter::new(vec![], 1);
+        writer.write_packet(&[1; 10], 100, false).unwrap();
+        //needs 255 * 255 / 255 + 1 lacing values
+        writer.write_packet(&[2; 255 * 255], 200, false).unwrap();
+        writer.write_packet(&[3; 5], 300, false).unwrap();
+        let pages = pages(&writer.finish().unwrap());
+
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0], (0x02, 100, vec![10], 10));
+        //no packet ends on the second page
+        assert_eq!(pages[1].0, 0x00);
+        assert_eq!(pages[1].1, -1);
+        assert_eq!(pages[1].2, vec![255; 255]);
+        let (header_type, granule, lacing, size) = &pages[2];
+        assert_eq!((*header_type, *granule), (0x01 | 0x04, 300));
+        assert_eq!(lacing, &[0, 5]);
+        assert_eq!(*size, 5);
+    }
+
+    #[test]
+    fn keeps_short_packets_on_one_page() {
+        let mut writer = OggWriter::new(vec![], 1);
+        writer.write_packet(&[1; 300], 10, false).unwrap();
+        writer.write_packet(&[2; 254 * 255], 20, false).unwrap();
+        let pages = pages(&writer.finish().unwrap());
+        //the second packet fills its page,the last page is empty
+        assert_eq!(pages.len(), 3);
+        assert_eq!((pages[0].0, pages[0].1, pages[0].3), (0x02, 10, 300));
+        assert_eq!((pages[1].0, pages[1].1), (0x00, 20));
+        assert_eq!(pages[1].2.len(), 255);
+        assert_eq!(pages[2], (0x04, 20, vec![], 0));
+    }
+}
@@ -0,0 +1,1074 @@
+//! `remux` command: convert between flv and other containers without re-encoding.
+
+use crate::media::{
+    AudioMetadata, CodecConfig, Demuxer, Frame, MediaEvent, Metadata, TrackKind, VideoMetadata,
+};
+use crate::mkv;
+use crate::mp4::{self, Sample, SampleTable, Track};
+use crate::mp4_reader::{self, InputTrack};
+use crate::ts::TsMuxer;
+use crate::ts_reader::{TsDemuxer, TsEvent, TsFrame};
+use crate::{open_input, open_output};
+use flv_parser::amf::{Amf0Value, Pair};
+use flv_parser::flv::{Header, ParseResult, Reader, TagData, Writer};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+///Time to wait for both streams before writing output headers of streamed formats
+pub(crate) const PROBE_DURATION: u32 = 1000;
+///Timestamp jump in milliseconds between frames of one stream treated as a discontinuity
+pub(crate) const DISCONTINUITY_THRESHOLD: u32 = 1000;
+
+///Temporary file holding sample data,removed on drop
+pub(crate) struct Spool {
+    path: PathBuf,
+    file: BufWriter<File>,
+    pub size: u64,
+}
+
+impl Spool {
+    pub fn new() -> io::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let path =
+            std::env::temp_dir().join(format!("flv-cli-{}-{}.mdat", std::process::id(), nanos));
+        let file = BufWriter::new(
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)?,
+        );
+        Ok(Self {
+            path,
+            file,
+            size: 0,
+        })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    ///Copy all spooled data to writer
+    pub fn copy_to(&mut self, writer: &mut impl Write) -> io::Result<()> {
+        self.file.flush()?;
+        let file = self.file.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut file.take(self.size), writer)?;
+        Ok(())
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+///Collect the decoder configuration of each stream and the frames after it,
+///warning about configuration changes the output can't carry
+pub(crate) struct FrameSource<R: Read> {
+    reader: Reader<R>,
+    demuxer: Demuxer,
+    tracks: Vec<Track>,
+    ///Tracks are fixed,configs of new streams are ignored
+    locked: bool,
+    ///Entries of the first onMetaData
+    metadata: Option<Vec<Pair<String, Amf0Value>>>,
+}
+
+impl<R: Read> FrameSource<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            reader: Reader::new(input),
+            demuxer: Demuxer::new(),
+            tracks: vec![],
+            locked: false,
+            metadata: None,
+        }
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn lock_tracks(&mut self) {
+        self.locked = true;
+    }
+
+    pub fn track(&self, kind: TrackKind) -> Option<&Track> {
+        self.tracks.iter().find(|t| t.kind == kind)
+    }
+
+    ///Entries of the onMetaData read so far
+    pub fn metadata(&self) -> Option<&[Pair<String, Amf0Value>]> {
+        self.metadata.as_deref()
+    }
+
+    ///Read frames until both streams are known or the frames span duration milliseconds,
+    ///then lock the tracks.Returns the frames read.
+    pub fn probe(&mut self, duration: u32) -> io::Result<Vec<Frame>> {
+        let mut frames: Vec<Frame> = vec![];
+        while let Some(frame) = self.next_frame()? {
+            let span = frame
+                .timestamp
+                .saturating_sub(frames.first().unwrap_or(&frame).timestamp);
+            frames.push(frame);
+            if self.tracks.len() == 2 || span >= duration {
+                break;
+            }
+        }
+        if self.tracks.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "No AVC/HEVC video or AAC/MP3 audio found",
+            ));
+        }
+        self.lock_tracks();
+        Ok(frames)
+    }
+
+    ///Return the next frame of a known track,None at the end of input
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        while let Some(result) = self.reader.read_next()? {
+            let tag = match result {
+                ParseResult::Tag(tag) => tag,
+                _ => continue,
+            };
+            if let (TagData::Script(values), None) = (tag.data(), &self.metadata) {
+                if let [Amf0Value::String(name), Amf0Value::EcmaArray { entries } | Amf0Value::Object { entries, .. }, ..] =
+                    values.as_slice()
+                {
+                    if name == "onMetaData" {
+                        self.metadata = Some(entries.clone());
+                    }
+                }
+            }
+            for event in self.demuxer.push(&tag)? {
+                match event {
+                    MediaEvent::Config(kind, config) => {
+                        if let Some(track) = self.tracks.iter().find(|t| t.kind == kind) {
+                            if track.config != config {
+                                eprintln!(
+                                    "Warning: {:?} configuration changed at {}ms,keeping the first one",
+                                    kind,
+                                    tag.timestamp()
+                                );
+                            }
+                        } else if self.locked {
+                            eprintln!(
+                                "Warning: {:?} stream starts at {}ms after the output header,ignored",
+                                kind,
+                                tag.timestamp()
+                            );
+                        } else {
+                            let id = match kind {
+                                TrackKind::Video => 1,
+                                TrackKind::Audio => 2,
+                            };
+                            self.tracks.push(Track::new(id, kind, config));
+                            self.tracks.sort_by_key(|t| t.id);
+                        }
+                    }
+                    MediaEvent::Frame(frame) => {
+                        if self.track(frame.kind).is_some() {
+                            return Ok(Some(frame));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+///Turn frames of one track into samples,the duration of a sample is the gap to the next one
+fn build_samples(track: &Track, frames: &[(u32, i32, bool, u32)], base: u32) -> Vec<Sample> {
+    let mut samples: Vec<Sample> = frames
+        .iter()
+        .map(|(timestamp, cts, keyframe, size)| Sample {
+            dts: track.millis_to_timescale(timestamp.saturating_sub(base) as i64) as u64,
+            duration: 0,
+            cts_offset: track.millis_to_timescale(*cts as i64) as i32,
+            size: *size,
+            keyframe: *keyframe,
+        })
+        .collect();
+    if track.kind == TrackKind::Audio {
+        //keep the nominal frame duration unless timestamps really drift,
+        //millisecond timestamps are too coarse for audio sample rates
+        let tolerance = track.millis_to_timescale(2) as u64;
+        for i in 1..samples.len() {
+            let expected = samples[i - 1].dts + track.frame_duration() as u64;
+            if (samples[i].dts as i64 - expected as i64).unsigned_abs() <= tolerance {
+                samples[i].dts = expected;
+            }
+        }
+    }
+    for i in 0..samples.len() {
+        let duration = match samples.get(i + 1) {
+            Some(next) => next.dts.saturating_sub(samples[i].dts) as u32,
+            None if i > 0 => samples[i - 1].duration,
+            None => track.frame_duration(),
+        };
+        samples[i].duration = duration;
+    }
+    samples
+}
+
+fn remux_progressive_mp4<R: Read>(
+    source: &mut FrameSource<R>,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let mut spool = Spool::new()?;
+    //(track kind,timestamp,composition time,keyframe,size) in input order
+    let mut frames: Vec<(TrackKind, u32, i32, bool, u32)> = vec![];
+    while let Some(frame) = source.next_frame()? {
+        spool.write(&frame.data)?;
+        frames.push((
+            frame.kind,
+            frame.timestamp,
+            frame.composition_time,
+            frame.keyframe,
+            frame.data.len() as u32,
+        ));
+    }
+    if source.tracks().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No AVC/HEVC video or AAC/MP3 audio found",
+        ));
+    }
+    let base = frames.iter().map(|f| f.1).min().unwrap_or(0);
+    let mut tables: Vec<(Track, SampleTable)> = vec![];
+    for track in source.tracks() {
+        let track_frames: Vec<(u32, i32, bool, u32)> = frames
+            .iter()
+            .filter(|f| f.0 == track.kind)
+            .map(|f| (f.1, f.2, f.3, f.4))
+            .collect();
+        let table = SampleTable {
+            samples: build_samples(track, &track_frames, base),
+            ..Default::default()
+        };
+        tables.push((track.clone(), table));
+    }
+
+    //a chunk is a run of samples of one track in input order
+    let mut chunks: Vec<(TrackKind, u64, u32)> = vec![];
+    let mut offset = 0_u64;
+    for (kind, _, _, _, size) in &frames {
+        match chunks.last_mut() {
+            Some((last, _, count)) if last == kind && *count < 256 => *count += 1,
+            _ => chunks.push((*kind, offset, 1)),
+        }
+        offset += *size as u64;
+    }
+    let ftyp = mp4::ftyp(b"isom", 0x200, &[b"isom", b"iso2", b"avc1", b"mp41"]);
+    let mdat_header = mp4::mdat_header(spool.size);
+    let set_chunks = |tables: &mut Vec<(Track, SampleTable)>, base_offset: u64| {
+        for (track, table) in tables.iter_mut() {
+            table.chunks = chunks
+                .iter()
+                .filter(|c| c.0 == track.kind)
+                .map(|c| (c.1 + base_offset, c.2))
+                .collect();
+        }
+    };
+    //offsets change the moov size only when they need 64 bits
+    set_chunks(&mut tables, 0);
+    let headers_size = (ftyp.len() + mdat_header.len()) as u64;
+    let moov_size = mp4::progressive_moov(&tables).len() as u64;
+    if headers_size + moov_size + spool.size > u32::MAX as u64 {
+        for (_, table) in tables.iter_mut() {
+            table.large_offsets = true;
+        }
+    }
+    let moov_size = mp4::progressive_moov(&tables).len() as u64;
+    set_chunks(&mut tables, headers_size + moov_size);
+    let moov = mp4::progressive_moov(&tables);
+    output.write_all(&ftyp)?;
+    output.write_all(&moov)?;
+    output.write_all(&mdat_header)?;
+    spool.copy_to(output)
+}
+
+///Write the buffered frames of the given tracks as one fragment,
+///returning the decode time and duration of each track fragment in its timescale
+pub(crate) fn write_fragment(
+    output: &mut impl Write,
+    tracks: &[Track],
+    frames: &[Frame],
+    sequence: u32,
+    base: u32,
+    cts_shift: i32,
+) -> io::Result<Vec<(TrackKind, u64, u64)>> {
+    let mut fragments: Vec<(&Track, Vec<Sample>)> = vec![];
+    for track in tracks {
+        let track_frames: Vec<(u32, i32, bool, u32)> = frames
+            .iter()
+            .filter(|f| f.kind == track.kind)
+            .map(|f| {
+                let cts = if track.kind == TrackKind::Video {
+                    f.composition_time - cts_shift
+                } else {
+                    0
+                };
+                (f.timestamp, cts, f.keyframe, f.data.len() as u32)
+            })
+            .collect();
+        if !track_frames.is_empty() {
+            fragments.push((track, build_samples(track, &track_frames, base)));
+        }
+    }
+    let refs: Vec<(&Track, &[Sample])> =
+        fragments.iter().map(|(t, s)| (*t, s.as_slice())).collect();
+    output.write_all(&mp4::moof(sequence, &refs))?;
+    let size: u64 = fragments
+        .iter()
+        .flat_map(|(_, samples)| samples.iter().map(|s| s.size as u64))
+        .sum();
+    output.write_all(&mp4::mdat_header(size))?;
+    for track in tracks {
+        for frame in frames.iter().filter(|f| f.kind == track.kind) {
+            output.write_all(&frame.data)?;
+        }
+    }
+    Ok(fragments
+        .iter()
+        .map(|(track, samples)| {
+            let duration = samples.iter().map(|s| s.duration as u64).sum();
+            (track.kind, samples[0].dts, duration)
+        })
+        .collect())
+}
+
+fn remux_fragmented_mp4<R: Read>(
+    source: &mut FrameSource<R>,
+    output: &mut impl Write,
+    fragment_duration: u32,
+) -> io::Result<()> {
+    let mut pending: Vec<Frame> = vec![];
+    let mut sequence = 0;
+    let mut base: Option<u32> = None;
+    let mut cts_shift = 0;
+    loop {
+        let frame = source.next_frame()?;
+        let cut = match (&frame, pending.first()) {
+            (None, Some(_)) => true,
+            (Some(frame), Some(first)) => {
+                let is_boundary = frame.keyframe
+                    && (frame.kind == TrackKind::Video || source.track(TrackKind::Video).is_none());
+                is_boundary && frame.timestamp.saturating_sub(first.timestamp) >= fragment_duration
+            }
+            _ => false,
+        };
+        if cut {
+            if sequence == 0 {
+                source.lock_tracks();
+                output.write_all(&mp4::ftyp(b"isom", 0x200, &[b"isom", b"iso6", b"mp41"]))?;
+                output.write_all(&mp4::fragmented_moov(source.tracks()))?;
+                //the tfdt of every track counts from the earliest frame,so a track starting
+                //later keeps its start offset like the empty edit of progressive files
+                base = pending.iter().map(|f| f.timestamp).min();
+                cts_shift = pending
+                    .iter()
+                    .find(|f| f.kind == TrackKind::Video)
+                    .map(|f| f.composition_time)
+                    .unwrap_or(0);
+            }
+            sequence += 1;
+            write_fragment(
+                output,
+                source.tracks(),
+                &pending,
+                sequence,
+                base.unwrap_or(0),
+                cts_shift,
+            )?;
+            pending.clear();
+        }
+        match frame {
+            Some(frame) => pending.push(frame),
+            None => break,
+        }
+    }
+    if sequence == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No AVC/HEVC video or AAC/MP3 audio found",
+        ));
+    }
+    Ok(())
+}
+
+///Remux src to a transport stream,frames are written as soon as the streams are known
+pub fn remux_ts(src: &str, path: &str) -> io::Result<()> {
+    let mut source = FrameSource::new(open_input(src)?);
+    let pending = source.probe(PROBE_DURATION)?;
+    let mut muxer = TsMuxer::new(open_output(path)?, source.tracks());
+    for frame in &pending {
+        muxer.write_frame(frame)?;
+    }
+    while let Some(frame) = source.next_frame()? {
+        muxer.write_frame(&frame)?;
+    }
+    muxer.into_inner().flush()
+}
+
+///Remux src to an mp4 file,moov first for progressive files
+pub fn remux_mp4(
+    src: &str,
+    path: &str,
+    fragmented: bool,
+    fragment_duration: u32,
+) -> io::Result<()> {
+    let mut source = FrameSource::new(open_input(src)?);
+    let mut output = open_output(path)?;
+    if fragmented {
+        remux_fragmented_mp4(&mut source, &mut output, fragment_duration)?;
+    } else {
+        remux_progressive_mp4(&mut source, &mut output)?;
+    }
+    output.flush()
+}
+
+///Length of clusters without video keyframes in milliseconds
+const CLUSTER_DURATION: u64 = 5000;
+
+///Remux src to a Matroska file,clusters start at video keyframes and are spooled
+///so the duration and seek head come before them
+pub fn remux_mkv(src: &str, path: &str) -> io::Result<()> {
+    let mut source = FrameSource::new(open_input(src)?);
+    let pending = source.probe(PROBE_DURATION)?;
+    let tracks = source.tracks().to_vec();
+    let has_video = source.track(TrackKind::Video).is_some();
+    let mut spool = Spool::new()?;
+    let mut blocks: Vec<u8> = vec![];
+    let mut cluster_time: Option<u64> = None;
+    //(time,track number,cluster offset in the spool)
+    let mut cue_points: Vec<(u64, u64, u64)> = vec![];
+    //(frame count,first timestamp,last timestamp,end of presentation) of every track
+    let mut stats = vec![(0_u64, 0_u32, 0_u32, 0_u64); tracks.len()];
+    //earliest presentation time of all tracks
+    let mut start: Option<u64> = None;
+
+    let mut frames = pending.into_iter();
+    loop {
+        let frame = match frames.next() {
+            Some(frame) => frame,
+            None => match source.next_frame()? {
+                Some(frame) => frame,
+                None => break,
+            },
+        };
+        let index = match tracks.iter().position(|t| t.kind == frame.kind) {
+            Some(index) => index,
+            None => continue,
+        };
+        let number = index as u64 + 1;
+        let pts = (frame.timestamp as i64 + frame.composition_time as i64).max(0) as u64;
+        let keyframe = frame.keyframe && frame.kind == TrackKind::Video;
+        let relative = cluster_time.map(|time| pts as i64 - time as i64);
+        let new_cluster = match relative {
+            Some(relative) => {
+                keyframe
+                    || (!has_video && relative >= CLUSTER_DURATION as i64)
+                    || relative < i16::MIN as i64
+                    || relative > i16::MAX as i64
+            }
+            None => true,
+        };
+        if new_cluster {
+            if let Some(time) = cluster_time {
+                spool.write(&mkv::cluster(time, &blocks))?;
+                blocks.clear();
+            }
+            cluster_time = Some(pts);
+            if keyframe || !has_video {
+                cue_points.push((pts, number, spool.size));
+            }
+        }
+        let relative = (pts as i64 - cluster_time.unwrap_or(0) as i64) as i16;
+        blocks.extend_from_slice(&mkv::simple_block(
+            number,
+            relative,
+            frame.keyframe,
+            &frame.data,
+        ));
+        let stat = &mut stats[index];
+        if stat.0 == 0 {
+            stat.1 = frame.timestamp;
+        }
+        stat.0 += 1;
+        stat.2 = frame.timestamp;
+        stat.3 = stat.3.max(pts);
+        start = Some(start.map_or(pts, |start| start.min(pts)));
+    }
+    if let Some(time) = cluster_time {
+        spool.write(&mkv::cluster(time, &blocks))?;
+    }
+
+    //the last frame lasts as long as the average one
+    let end = stats
+        .iter()
+        .map(|(count, first, last, end)| {
+            let average = if *count > 1 {
+                (last - first) as f64 / (count - 1) as f64
+            } else {
+                0.0
+            };
+            *end as f64 + average
+        })
+        .fold(0.0, f64::max);
+    //blocks keep their flv times,the duration counts from the first of them
+    let duration = (end - start.unwrap_or(0) as f64).max(0.0);
+    let info = mkv::info(duration);
+    let track_list: Vec<(TrackKind, &CodecConfig)> =
+        tracks.iter().map(|t| (t.kind, &t.config)).collect();
+    let track_entries = mkv::tracks(&track_list);
+    let tags = source.metadata().map(mkv::tags).unwrap_or_default();
+    let mut elements = vec![(mkv::INFO, info.len()), (mkv::TRACKS, track_entries.len())];
+    if !tags.is_empty() {
+        elements.push((mkv::TAGS, tags.len()));
+    }
+    //seek positions have a fixed size,so the seek head size is known before them
+    let mut seek_entries: Vec<(u32, u64)> = elements.iter().map(|e| (e.0, 0)).collect();
+    seek_entries.push((mkv::CUES, 0));
+    let mut position = mkv::seek_head(&seek_entries).len() as u64;
+    for (entry, (_, size)) in seek_entries.iter_mut().zip(&elements) {
+        entry.1 = position;
+        position += *size as u64;
+    }
+    let clusters_position = position;
+    let cues_position = clusters_position + spool.size;
+    seek_entries.last_mut().unwrap().1 = cues_position;
+    let seek_head = mkv::seek_head(&seek_entries);
+    let cue_points: Vec<(u64, u64, u64)> = cue_points
+        .into_iter()
+        .map(|(time, track, offset)| (time, track, clusters_position + offset))
+        .collect();
+    let cues = mkv::cues(&cue_points);
+
+    let mut output = open_output(path)?;
+    output.write_all(&mkv::ebml_header())?;
+    output.write_all(&mkv::segment_header(cues_position + cues.len() as u64))?;
+    output.write_all(&seek_head)?;
+    output.write_all(&info)?;
+    output.write_all(&track_entries)?;
+    output.write_all(&tags)?;
+    spool.copy_to(&mut output)?;
+    output.write_all(&cues)?;
+    output.flush()
+}
+
+///A step of the flv written from mp4 tracks
+enum Mp4Item {
+    ///Sequence header of a sample description of a track
+    Config(usize, usize),
+    ///Sample of a track
+    Sample(usize, usize),
+}
+
+///Convert a progressive mp4 file to flv,samples are interleaved by decode time
+pub fn remux_from_mp4(src: &str, path: &str) -> io::Result<()> {
+    if src == "-" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "mp4 input can't be read from a pipe",
+        ));
+    }
+    let mut input = BufReader::new(File::open(src)?);
+    let mut tracks: Vec<InputTrack> = vec![];
+    for track in mp4_reader::read_tracks(&mut input)? {
+        let kind = match track.kind {
+            Some(kind) => kind,
+            None => continue,
+        };
+        if track.configs.iter().all(Option::is_none) || tracks.iter().any(|t| t.kind == Some(kind))
+        {
+            eprintln!(
+                "Warning: track {}({}) skipped",
+                track.id,
+                track.format_name()
+            );
+            continue;
+        }
+        tracks.push(track);
+    }
+    if tracks.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No AVC/HEVC video or AAC/MP3 audio track found",
+        ));
+    }
+    tracks.sort_by_key(|t| t.kind != Some(TrackKind::Video));
+
+    //decode and composition times in milliseconds,shifted so the first decode time is 0
+    let mut times: Vec<Vec<(i64, i32)>> = tracks
+        .iter()
+        .map(|track| {
+            track
+                .samples
+                .iter()
+                .map(|stored| {
+                    let dts = track.to_millis(stored.sample.dts as i64).round();
+                    let pts = track
+                        .to_millis(stored.sample.dts as i64 + stored.sample.cts_offset as i64)
+                        .round();
+                    (dts as i64, (pts - dts) as i32)
+                })
+                .collect()
+        })
+        .collect();
+    let start = times.iter().flatten().map(|t| t.0).min().unwrap_or(0);
+    for time in times.iter_mut().flatten() {
+        time.0 -= start;
+    }
+
+    let mut order: Vec<(i64, usize, usize)> = vec![];
+    let mut skipped = 0;
+    for (track_index, track) in tracks.iter().enumerate() {
+        for (sample_index, stored) in track.samples.iter().enumerate() {
+            if track
+                .configs
+                .get(stored.description)
+                .and_then(Option::as_ref)
+                .is_some()
+            {
+                order.push((
+                    times[track_index][sample_index].0,
+                    track_index,
+                    sample_index,
+                ));
+            } else {
+                skipped += 1;
+            }
+        }
+    }
+    if skipped > 0 {
+        eprintln!(
+            "Warning: {} samples of unsupported sample descriptions skipped",
+            skipped
+        );
+    }
+    order.sort();
+    let mut items = vec![];
+    let mut current: Vec<Option<usize>> = vec![None; tracks.len()];
+    for (_, track_index, sample_index) in order {
+        let description = tracks[track_index].samples[sample_index].description;
+        if current[track_index] != Some(description) {
+            current[track_index] = Some(description);
+            items.push(Mp4Item::Config(track_index, description));
+        }
+        items.push(Mp4Item::Sample(track_index, sample_index));
+    }
+
+    let config =
+        |track: usize, description: usize| tracks[track].configs[description].as_ref().unwrap();
+    let mut metadata = Metadata {
+        file_size: Some(0),
+        ..Default::default()
+    };
+    for (index, track) in tracks.iter().enumerate() {
+        let first = match track.samples.first() {
+            Some(first) => first,
+            None => continue,
+        };
+        let track_config = config(index, first.description);
+        let end = track
+            .samples
+            .iter()
+            .zip(&times[index])
+            .map(|(stored, time)| {
+                let duration = stored.sample.duration as f64 * 1000.0 / track.timescale as f64;
+                time.0 + time.1 as i64 + duration.round() as i64
+            })
+            .max()
+            .unwrap_or(0);
+        let duration = (end - times[index][0].0) as f64 / 1000.0;
+        metadata.duration = metadata.duration.max(end as f64 / 1000.0);
+        let bytes: u64 = track.samples.iter().map(|s| s.sample.size as u64).sum();
+        let data_rate = if duration > 0.0 {
+            bytes as f64 * 8.0 / 1000.0 / duration
+        } else {
+            0.0
+        };
+        match track.kind {
+            Some(TrackKind::Video) => {
+                let (width, height) = track_config.picture_size().unwrap_or((0, 0));
+                metadata.video = Some(VideoMetadata {
+                    codec_id: track_config.flv_codec_id(),
+                    width,
+                    height,
+                    frame_rate: if duration > 0.0 {
+                        track.samples.len() as f64 / duration
+                    } else {
+                        0.0
+                    },
+                    data_rate,
+                });
+            }
+            _ => {
+                let (sample_rate, channels) = track_config.audio_format().unwrap_or((0, 0));
+                metadata.audio = Some(AudioMetadata {
+                    codec_id: track_config.flv_codec_id(),
+                    sample_rate,
+                    stereo: channels > 1,
+                    data_rate,
+                });
+            }
+        }
+    }
+    //every size is known up front,the metadata tag size doesn't depend on its values
+    let mut file_size = Header::HEADER_SIZE as u64 + 4 + metadata.to_tag().tag_size() as u64 + 4;
+    for item in &items {
+        file_size += match *item {
+            Mp4Item::Config(track, description) => config(track, description)
+                .sequence_header_tag(0)
+                .map_or(0, |tag| tag.tag_size() as u64 + 4),
+            Mp4Item::Sample(track, sample) => {
+                let stored = &tracks[track].samples[sample];
+                config(track, stored.description)
+                    .frame_tag_size(tracks[track].kind.unwrap(), stored.sample.size)
+            }
+        };
+    }
+    metadata.file_size = Some(file_size);
+
+    let mut writer = Writer::new(open_output(path)?);
+    writer.write_header(&Header::new(
+        metadata.video.is_some(),
+        metadata.audio.is_some(),
+    ))?;
+    writer.write_tag(&metadata.to_tag())?;
+    let mut position = input.stream_position()?;
+    for item in items {
+        match item {
+            Mp4Item::Config(track, description) => {
+                let timestamp = times[track]
+                    .iter()
+                    .zip(&tracks[track].samples)
+                    .find(|(_, s)| s.description == description)
+                    .map_or(0, |(t, _)| t.0);
+                if let Some(tag) = config(track, description).sequence_header_tag(timestamp as u32)
+                {
+                    writer.write_tag(&tag)?;
+                }
+            }
+            Mp4Item::Sample(track, sample) => {
+                let stored = &tracks[track].samples[sample];
+                if stored.offset != position {
+                    input.seek(SeekFrom::Start(stored.offset))?;
+                }
+                let mut data = vec![0_u8; stored.sample.size as usize];
+                input.read_exact(&mut data)?;
+                position = stored.offset + data.len() as u64;
+                let (timestamp, composition_time) = times[track][sample];
+                let frame = Frame {
+                    kind: tracks[track].kind.unwrap(),
+                    timestamp: timestamp as u32,
+                    composition_time,
+                    keyframe: stored.sample.keyframe,
+                    data,
+                };
+                writer.write_tag(&config(track, stored.description).frame_tag(&frame))?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+///A track of the ts input in the flv output
+struct TsTrack {
+    kind: TrackKind,
+    config: CodecConfig,
+    ///Frames waiting to be interleaved,with the sequence header to write before each
+    queue: VecDeque<(Option<CodecConfig>, TsFrame)>,
+    ///Sequence header to write before the next frame
+    pending_config: Option<CodecConfig>,
+    first: Option<u32>,
+    last: u32,
+    frames: u64,
+    bytes: u64,
+}
+
+impl TsTrack {
+    ///Queued span in 90kHz units
+    fn span(&self) -> i64 {
+        match (self.queue.front(), self.queue.back()) {
+            (Some(front), Some(back)) => back.1.dts - front.1.dts,
+            _ => 0,
+        }
+    }
+
+    ///Duration in milliseconds,the last frame lasts as long as the average one
+    fn duration(&self) -> f64 {
+        let first = self.first.unwrap_or(0);
+        let span = (self.last - first) as f64;
+        if self.frames > 1 {
+            span * self.frames as f64 / (self.frames - 1) as f64
+        } else {
+            span
+        }
+    }
+}
+
+///Index of the track whose queued frame goes next.
+///A track waits for the others unless they fell too far behind.
+fn next_ts_track(tracks: &[TsTrack], flush: bool) -> Option<usize> {
+    let waiting = tracks.iter().any(|t| t.queue.is_empty());
+    tracks
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| !t.queue.is_empty())
+        .filter(|(_, t)| flush || !waiting || t.span() >= PROBE_DURATION as i64 * 90)
+        .min_by_key(|(_, t)| t.queue[0].1.dts)
+        .map(|(index, _)| index)
+}
+
+///Write the interleaved frames of ts tracks,base is the decode time of time 0 in 90kHz units
+fn write_ts_frames<W: Write>(
+    writer: &mut Writer<W>,
+    tracks: &mut [TsTrack],
+    base: i64,
+    flush: bool,
+) -> io::Result<()> {
+    while let Some(index) = next_ts_track(tracks, flush) {
+        let track = &mut tracks[index];
+        let (config, frame) = track.queue.pop_front().unwrap();
+        let timestamp = ((frame.dts - base) / 90).max(0) as u32;
+        let composition_time = ((frame.pts - base) / 90).max(0) as i32 - timestamp as i32;
+        if let Some(config) = config {
+            if let Some(tag) = config.sequence_header_tag(timestamp) {
+                writer.write_tag(&tag)?;
+            }
+            track.config = config;
+        }
+        track.first.get_or_insert(timestamp);
+        track.last = track.last.max(timestamp);
+        track.frames += 1;
+        track.bytes += frame.data.len() as u64;
+        let frame = Frame {
+            kind: track.kind,
+            timestamp,
+            composition_time,
+            keyframe: frame.keyframe,
+            data: frame.data,
+        };
+        writer.write_tag(&track.config.frame_tag(&frame))?;
+    }
+    Ok(())
+}
+
+///Convert the events of a transport stream to flv,frames are written as soon as they are interleaved.
+///Returns the metadata written at the start,completed with the stream statistics.
+fn write_flv_from_ts<R: Read, W: Write>(
+    demuxer: &mut TsDemuxer<R>,
+    writer: &mut Writer<W>,
+    file_size: Option<u64>,
+) -> io::Result<Metadata> {
+    //wait for the config and first frame of every stream of the pmt
+    let mut pending = vec![];
+    let mut configs: Vec<(TrackKind, CodecConfig)> = vec![];
+    let mut first_frames: Vec<(TrackKind, i64)> = vec![];
+    while let Some(event) = demuxer.next_event()? {
+        match &event {
+            TsEvent::Config(kind, config) => {
+                if configs.iter().all(|c| c.0 != *kind) {
+                    configs.push((*kind, config.clone()));
+                }
+            }
+            TsEvent::Frame(frame) => {
+                if first_frames.iter().all(|f| f.0 != frame.kind) {
+                    first_frames.push((frame.kind, frame.dts));
+                }
+            }
+        }
+        pending.push(event);
+        let kinds = demuxer.kinds();
+        let span = match (first_frames.first(), &pending[pending.len() - 1]) {
+            (Some(first), TsEvent::Frame(frame)) => frame.dts - first.1,
+            _ => 0,
+        };
+        if (!kinds.is_empty() && kinds.iter().all(|k| first_frames.iter().any(|f| f.0 == *k)))
+            || span >= PROBE_DURATION as i64 * 90
+        {
+            break;
+        }
+    }
+    if configs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No H.264 video or AAC audio found",
+        ));
+    }
+    configs.sort_by_key(|c| c.0 != TrackKind::Video);
+    let base = first_frames.iter().map(|f| f.1).min().unwrap_or(0);
+
+    let mut metadata = Metadata {
+        file_size,
+        ..Default::default()
+    };
+    for (kind, config) in &configs {
+        match kind {
+            TrackKind::Video => {
+                let (width, height) = config.picture_size().unwrap_or((0, 0));
+                let frame_rate = match config {
+                    CodecConfig::Avc(config) => config
+                        .parse_sps()
+                        .ok()
+                        .and_then(|sps| sps.frame_rate())
+                        .unwrap_or(0.0),
+                    _ => 0.0,
+                };
+                metadata.video = Some(VideoMetadata {
+                    codec_id: config.flv_codec_id(),
+                    width,
+                    height,
+                    frame_rate,
+                    data_rate: 0.0,
+                });
+            }
+            TrackKind::Audio => {
+                let (sample_rate, channels) = config.audio_format().unwrap_or((0, 0));
+                metadata.audio = Some(AudioMetadata {
+                    codec_id: config.flv_codec_id(),
+                    sample_rate,
+                    stereo: channels > 1,
+                    data_rate: 0.0,
+                });
+            }
+        }
+    }
+    writer.write_header(&Header::new(
+        metadata.video.is_some(),
+        metadata.audio.is_some(),
+    ))?;
+    writer.write_tag(&metadata.to_tag())?;
+
+    let mut tracks: Vec<TsTrack> = configs
+        .into_iter()
+        .map(|(kind, config)| TsTrack {
+            kind,
+            config,
+            queue: VecDeque::new(),
+            pending_config: None,
+            first: None,
+            last: 0,
+            frames: 0,
+            bytes: 0,
+        })
+        .collect();
+    let mut ignored = vec![];
+    let mut events = pending.into_iter();
+    loop {
+        let event = match events.next() {
+            Some(event) => event,
+            None => match demuxer.next_event()? {
+                Some(event) => event,
+                None => break,
+            },
+        };
+        match event {
+            TsEvent::Config(kind, config) => match tracks.iter_mut().find(|t| t.kind == kind) {
+                Some(track) => track.pending_config = Some(config),
+                None => {
+                    if !ignored.contains(&kind) {
+                        ignored.push(kind);
+                        eprintln!(
+                            "Warning: {:?} stream starts after the output header,ignored",
+                            kind
+                        );
+                    }
+                }
+            },
+            TsEvent::Frame(frame) => {
+                if let Some(track) = tracks.iter_mut().find(|t| t.kind == frame.kind) {
+                    let config = track.pending_config.take();
+                    track.queue.push_back((config, frame));
+                    write_ts_frames(writer, &mut tracks, base, false)?;
+                }
+            }
+        }
+    }
+    write_ts_frames(writer, &mut tracks, base, true)?;
+
+    for track in &tracks {
+        let duration = track.duration();
+        let data_rate = if duration > 0.0 {
+            track.bytes as f64 * 8.0 / duration
+        } else {
+            0.0
+        };
+        metadata.duration = metadata
+            .duration
+            .max((track.first.unwrap_or(0) as f64 + duration) / 1000.0);
+        match track.kind {
+            TrackKind::Video => {
+                if let Some(video) = &mut metadata.video {
+                    video.data_rate = data_rate;
+                    if duration > 0.0 {
+                        video.frame_rate = track.frames as f64 * 1000.0 / duration;
+                    }
+                }
+            }
+            TrackKind::Audio => {
+                if let Some(audio) = &mut metadata.audio {
+                    audio.data_rate = data_rate;
+                }
+            }
+        }
+    }
+    Ok(metadata)
+}
+
+///Convert a transport stream with H.264 and AAC to flv.
+///When writing a file the duration,data rates and file size of onMetaData are filled at the end.
+pub fn remux_from_ts(src: &str, path: &str) -> io::Result<()> {
+    let mut demuxer = TsDemuxer::new(open_input(src)?);
+    if path == "-" {
+        let mut writer = Writer::new(open_output(path)?);
+        write_flv_from_ts(&mut demuxer, &mut writer, None)?;
+        writer.flush()?;
+    } else {
+        let mut writer = Writer::new(BufWriter::new(File::create(path)?));
+        let mut metadata = write_flv_from_ts(&mut demuxer, &mut writer, Some(0))?;
+        writer.flush()?;
+        let file = writer.get_mut().get_mut();
+        metadata.file_size = Some(file.stream_position()?);
+        //the metadata tag keeps its size,only the values change
+        file.seek(SeekFrom::Start(Header::HEADER_SIZE as u64 + 4))?;
+        file.write_all(&metadata.to_tag().into_bytes())?;
+    }
+    let reader = demuxer.reader();
+    if reader.skipped_bytes > 0 {
+        eprintln!(
+            "Warning: {} bytes skipped to find the ts sync byte",
+            reader.skipped_bytes
+        );
+    }
+    if reader.dropped_pes > 0 {
+        eprintln!(
+            "Warning: {} pes packets dropped because of lost or broken packets",
+            reader.dropped_pes
+        );
+    }
+    if demuxer.skipped_frames > 0 {
+        eprintln!(
+            "Warning: {} frames without a timestamp,sequence header or preceding keyframe skipped",
+            demuxer.skipped_frames
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,449 @@
+//! `repair` command: rewrite a damaged flv,eg. a live recording cut off or corrupted on the way.
+
+use crate::media::{is_metadata, stream_index, CodecConfig, Demuxer, MediaEvent, Metadata, Span};
+use crate::open_output;
+use flv_parser::flv::{
+    Discontinuity, Header, Tag, TagData, TimestampFilter, TimestampFilterOptions, Writer,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read},
+};
+
+///Backward jumps of more than this many milliseconds are a restart of the timestamps,
+///smaller ones are clamped
+const JUMP_THRESHOLD: u32 = 1000;
+
+///Everything repaired,for the summary
+#[derive(Debug, Default)]
+struct Fixes {
+    ///Input position and length of corrupted bytes skipped to find the next tag
+    skipped: Vec<(u64, u64)>,
+    truncated_bytes: usize,
+    tag_sizes: u64,
+    zero_sized: u64,
+    ///Script tags whose data can't be decoded
+    undecodable: u64,
+    ///Input position and the jump back of a stream
+    jumps: Vec<(u64, Discontinuity)>,
+    clamped: u64,
+    missing_header: bool,
+    ///Flags of the input header and of the content
+    header_flags: Option<([bool; 2], [bool; 2])>,
+}
+
+impl Fixes {
+    fn report(&self, metadata: &Metadata) {
+        for (position, length) in &self.skipped {
+            eprintln!("Skipped {} corrupted bytes at byte {}", length, position);
+        }
+        if self.truncated_bytes > 0 {
+            eprintln!(
+                "Dropped {} bytes of a truncated tag at the end",
+                self.truncated_bytes
+            );
+        }
+        if self.tag_sizes > 0 {
+            eprintln!("Fixed {} previous tag sizes", self.tag_sizes);
+        }
+        if self.zero_sized > 0 {
+            eprintln!("Removed {} zero sized tags", self.zero_sized);
+        }
+        if self.undecodable > 0 {
+            eprintln!(
+                "Removed {} script tags which can't be decoded",
+                self.undecodable
+            );
+        }
+        for (position, jump) in &self.jumps {
+            eprintln!(
+                "{} timestamps jump back to {}ms at byte {},continued at {}ms",
+                if jump.tag_type == Tag::TYPE_VIDEO {
+                    "Video"
+                } else {
+                    "Audio"
+                },
+                jump.found,
+                position,
+                jump.timestamp
+            );
+        }
+        if self.clamped > 0 {
+            eprintln!(
+                "Clamped {} timestamps going back less than {}ms",
+                self.clamped, JUMP_THRESHOLD
+            );
+        }
+        if self.missing_header {
+            eprintln!("Added the missing flv header");
+        }
+        if let Some((found, content)) = self.header_flags {
+            let yes_no = |b: bool| if b { "yes" } else { "no" };
+            eprintln!(
+                "Fixed header flags,video {} -> {},audio {} -> {}",
+                yes_no(found[0]),
+                yes_no(content[0]),
+                yes_no(found[1]),
+                yes_no(content[1])
+            );
+        }
+        eprintln!(
+            "Regenerated onMetaData,duration {:.3}s,file size {}",
+            metadata.duration,
+            metadata.file_size.unwrap_or(0)
+        );
+    }
+}
+
+///Data size of the tag whose header starts bytes,None if it can't be a tag header.
+///strict also checks the stream id and the first data byte,it needs 12 bytes.
+fn tag_data_size(bytes: &[u8], strict: bool) -> Option<usize> {
+    let tp = *bytes.first()?;
+    if bytes.len() < 11 || ![Tag::TYPE_AUDIO, Tag::TYPE_VIDEO, Tag::TYPE_SCRIPT].contains(&tp) {
+        return None;
+    }
+    let size = u32::from_be_bytes([0, bytes[1], bytes[2], bytes[3]]) as usize;
+    if !strict {
+        return Some(size);
+    }
+    let first = *bytes.get(11)?;
+    let plausible = match tp {
+        Tag::TYPE_VIDEO => (1..=5).contains(&(first >> 4)) && matches!(first & 0x0F, 1..=7 | 12),
+        Tag::TYPE_AUDIO => first >> 4 != 9,
+        _ => first == 0x02,
+    };
+    if bytes[8..11] == [0, 0, 0] && size > 0 && plausible {
+        Some(size)
+    } else {
+        None
+    }
+}
+
+enum Check {
+    ///A tag of data size,whether its previous tag size is right,bytes to consume
+    Tag(usize, bool, usize),
+    ///A tag header whose data goes past the end of input
+    Truncated,
+    ///Less than a tag header left
+    End,
+    Invalid,
+}
+
+///Find tags in bytes which may be corrupted
+struct Scanner<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    ///Start of the unread bytes in buffer
+    start: usize,
+    ///Input position of buffer[start]
+    position: u64,
+    eof: bool,
+}
+
+impl<R: Read> Scanner<R> {
+    fn new(inner: R) -> Self {
+        Scanner {
+            inner,
+            buffer: vec![],
+            start: 0,
+            position: 0,
+            eof: false,
+        }
+    }
+
+    fn available(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+
+    ///Buffer count unread bytes,false if the input ends before
+    fn fill(&mut self, count: usize) -> io::Result<bool> {
+        while self.available() < count && !self.eof {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+            let length = self.buffer.len();
+            self.buffer
+                .resize(length + (count - length).max(64 * 1024), 0);
+            let read = loop {
+                match self.inner.read(&mut self.buffer[length..]) {
+                    Ok(read) => break read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            };
+            self.buffer.truncate(length + read);
+            self.eof = read == 0;
+        }
+        Ok(self.available() >= count)
+    }
+
+    fn bytes(&self, offset: usize, length: usize) -> &[u8] {
+        let start = self.start + offset;
+        &self.buffer[start..(start + length).min(self.buffer.len())]
+    }
+
+    fn advance(&mut self, count: usize) {
+        self.start += count;
+        self.position += count as u64;
+    }
+
+    ///Read the flv header and the first previous tag size,None if the input doesn't start with one
+    fn header(&mut self) -> io::Result<Option<Header>> {
+        //a header cut short is scanned like any other damaged bytes
+        if !self.fill(Header::HEADER_SIZE as usize)? || self.bytes(0, 3) != b"FLV" {
+            return Ok(None);
+        }
+        let header = Header::from_reader(&mut self.bytes(0, Header::HEADER_SIZE as usize))?;
+        let size = match header.size() {
+            size @ 9..=1024 => size as usize,
+            _ => Header::HEADER_SIZE as usize,
+        };
+        self.fill(size + 4)?;
+        self.advance((size + 4).min(self.available()));
+        Ok(Some(header))
+    }
+
+    ///Check whether a tag starts at offset,strict also needs a right previous tag size
+    fn check(&mut self, offset: usize, strict: bool) -> io::Result<Check> {
+        self.fill(offset + 12)?;
+        if self.available() < offset + 11 {
+            return Ok(Check::End);
+        }
+        let size = match tag_data_size(self.bytes(offset, 12), strict) {
+            Some(size) => size,
+            None => return Ok(Check::Invalid),
+        };
+        let end = offset + 11 + size;
+        if !self.fill(end + 4)? {
+            //the last tag may miss its previous tag size
+            return Ok(if self.available() >= end {
+                Check::Tag(size, true, self.available() - offset)
+            } else {
+                Check::Truncated
+            });
+        }
+        let tag_size = self.bytes(end, 4);
+        if u32::from_be_bytes([tag_size[0], tag_size[1], tag_size[2], tag_size[3]]) as usize
+            == 11 + size
+        {
+            return Ok(Check::Tag(size, true, end + 4 - offset));
+        }
+        if strict {
+            return Ok(Check::Invalid);
+        }
+        //a wrong previous tag size followed by a tag
+        self.fill(end + 4 + 12)?;
+        if self.available() == end + 4 || tag_data_size(self.bytes(end + 4, 12), true).is_some() {
+            Ok(Check::Tag(size, false, end + 4 - offset))
+        } else {
+            Ok(Check::Invalid)
+        }
+    }
+
+    ///Skip bytes until a tag with a right previous tag size or the end of input
+    fn resync(&mut self, fixes: &mut Fixes) -> io::Result<()> {
+        let from = self.position;
+        loop {
+            //nothing left to skip,eg. an empty input
+            if !self.fill(1)? {
+                break;
+            }
+            self.advance(1);
+            match self.check(0, true)? {
+                Check::Invalid => {}
+                Check::End => {
+                    self.advance(self.available());
+                    break;
+                }
+                _ => break,
+            }
+        }
+        if self.position > from {
+            fixes.skipped.push((from, self.position - from));
+        }
+        Ok(())
+    }
+
+    ///The next tag which can be decoded with its input position
+    fn next_tag(&mut self, fixes: &mut Fixes) -> io::Result<Option<(u64, Tag)>> {
+        loop {
+            let (size, size_ok, consumed) = match self.check(0, false)? {
+                Check::Tag(size, size_ok, consumed) => (size, size_ok, consumed),
+                Check::Truncated | Check::End => {
+                    fixes.truncated_bytes += self.available();
+                    self.advance(self.available());
+                    return Ok(None);
+                }
+                Check::Invalid => {
+                    self.resync(fixes)?;
+                    continue;
+                }
+            };
+            let position = self.position;
+            let tag = Tag::from_reader(&mut self.bytes(0, 11 + size));
+            self.advance(consumed);
+            if !size_ok {
+                fixes.tag_sizes += 1;
+            }
+            match tag {
+                _ if size == 0 => fixes.zero_sized += 1,
+                Ok(tag) => return Ok(Some((position, tag))),
+                Err(_) => fixes.undecodable += 1,
+            }
+        }
+    }
+}
+
+///Make timestamps of every stream increase,restarts continue after the latest frame
+struct Retimer {
+    filter: TimestampFilter,
+    spans: [Span; 2],
+}
+
+impl Retimer {
+    fn new() -> Self {
+        Retimer {
+            //forward gaps are left alone
+            filter: TimestampFilter::new(TimestampFilterOptions {
+                max_backward: JUMP_THRESHOLD,
+                max_gap: u32::MAX,
+            }),
+            spans: [Span::default(); 2],
+        }
+    }
+
+    fn retime(&mut self, position: u64, tag: &mut Tag, fixes: &mut Fixes) {
+        if let Some(discontinuity) = self.filter.push(tag) {
+            fixes.jumps.push((position, discontinuity));
+        }
+        fixes.clamped = self.filter.clamped();
+        if let Some(index) = stream_index(tag) {
+            if !tag.is_sequence_header() {
+                self.spans[index].push(tag.timestamp(), tag.data_size());
+            }
+        }
+    }
+}
+
+///Call f with the repaired tags of input,without onMetaData
+fn repaired_tags(
+    input: impl Read,
+    fixes: &mut Fixes,
+    retimer: &mut Retimer,
+    mut f: impl FnMut(Tag) -> io::Result<()>,
+) -> io::Result<Option<Header>> {
+    let mut scanner = Scanner::new(input);
+    let header = scanner.header()?;
+    if header.is_none() {
+        scanner.resync(fixes)?;
+    }
+    while let Some((position, mut tag)) = scanner.next_tag(fixes)? {
+        if matches!(tag.data(), TagData::Script(values) if is_metadata(values)) {
+            continue;
+        }
+        if let TagData::Script(values) = tag.data() {
+            //the data size of a script tag follows its decoded values
+            tag = Tag::new(tag.timestamp(), TagData::Script(values.clone()));
+        }
+        retimer.retime(position, &mut tag, fixes);
+        f(tag)?;
+    }
+    Ok(header)
+}
+
+///Input read twice,stdin is kept in memory
+enum Source<'a> {
+    File(&'a str),
+    Memory(Vec<u8>),
+}
+
+impl<'a> Source<'a> {
+    fn open(&self) -> io::Result<Box<dyn Read + '_>> {
+        match self {
+            Source::File(path) => Ok(Box::new(BufReader::new(File::open(path)?))),
+            Source::Memory(bytes) => Ok(Box::new(Cursor::new(bytes))),
+        }
+    }
+}
+
+///Rewrite src:corrupted bytes and a truncated tail are dropped,previous tag sizes fixed,
+///zero sized tags removed,timestamps going back made continuous,the header flags follow
+///the content and onMetaData is regenerated.A summary of the fixes is printed.
+pub fn repair(src: &str, path: &str) -> io::Result<()> {
+    let source = if src == "-" {
+        let mut bytes = vec![];
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        Source::Memory(bytes)
+    } else {
+        Source::File(src)
+    };
+
+    //first pass:fixes and the values of onMetaData
+    let mut fixes = Fixes::default();
+    let mut retimer = Retimer::new();
+    let mut codecs: [Option<u8>; 2] = [None, None];
+    let mut sound = None;
+    let mut configs: [Option<CodecConfig>; 2] = [None, None];
+    let mut demuxer = Demuxer::new();
+    let mut tags_size = 0;
+    let header = repaired_tags(source.open()?, &mut fixes, &mut retimer, |tag| {
+        tags_size += tag.tag_size() as u64 + 4;
+        match stream_index(&tag) {
+            Some(0) => codecs[0] = codecs[0].or(tag.video_codec_id()),
+            Some(_) => {
+                codecs[1] = codecs[1].or(tag.sound_format());
+                sound = sound.or(tag.sound_rate().zip(tag.sound_channels()));
+            }
+            None => {}
+        }
+        //a broken sequence header doesn't stop the repair
+        for event in demuxer.push(&tag).unwrap_or_default() {
+            if let MediaEvent::Config(kind, config) = event {
+                configs[kind.index()].get_or_insert(config);
+            }
+        }
+        Ok(())
+    })?;
+    if codecs.iter().all(Option::is_none) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No audio or video tags found",
+        ));
+    }
+    let content = [codecs[0].is_some(), codecs[1].is_some()];
+    match header {
+        None => fixes.missing_header = true,
+        Some(header) => {
+            let found = [header.has_video(), header.has_audio()];
+            if found != content {
+                fixes.header_flags = Some((found, content));
+            }
+        }
+    }
+    let metadata = metadata(&retimer.spans, codecs, &configs, sound, tags_size);
+
+    let mut writer = Writer::new(open_output(path)?);
+    writer.write_header(&Header::new(content[0], content[1]))?;
+    writer.write_tag(&metadata.to_tag())?;
+    repaired_tags(
+        source.open()?,
+        &mut Fixes::default(),
+        &mut Retimer::new(),
+        |tag| writer.write_tag(&tag),
+    )?;
+    writer.flush()?;
+    fixes.report(&metadata);
+    Ok(())
+}
+
+fn metadata(
+    spans: &[Span; 2],
+    codecs: [Option<u8>; 2],
+    configs: &[Option<CodecConfig>; 2],
+    sound: Option<(u32, u8)>,
+    tags_size: u64,
+) -> Metadata {
+    let mut metadata = Metadata::from_spans(spans, codecs, configs, sound);
+    metadata.file_size =
+        Some(Header::HEADER_SIZE as u64 + 4 + metadata.to_tag().tag_size() as u64 + 4 + tags_size);
+    metadata
+}
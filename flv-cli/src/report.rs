@@ -0,0 +1,334 @@
+//! Machine-readable `info` report.
+//!
+//! The schema is documented in README.md and versioned by `schema_version`;
+//! fields are only ever added within a version.
+
+use crate::scan::{Scan, StreamStats};
+use crate::{audio_codec_name, video_codec_name};
+use flv_parser::amf::Amf0Value;
+use serde_json::{json, Map, Value};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+///Convert an amf0 value to json.
+///Objects and ecma arrays become json objects keeping the key order,
+///dates become `{"date": unix ms, "time_zone": minutes}`.
+pub fn amf_to_json(value: &Amf0Value) -> Value {
+    match value {
+        Amf0Value::Number(n) => number(*n),
+        Amf0Value::Boolean(b) => Value::Bool(*b),
+        Amf0Value::String(s) | Amf0Value::XmlDocument(s) => Value::String(s.clone()),
+        Amf0Value::Object { entries, .. } | Amf0Value::EcmaArray { entries } => {
+            let mut map = Map::new();
+            for pair in entries {
+                map.insert(pair.key.clone(), amf_to_json(&pair.value));
+            }
+            Value::Object(map)
+        }
+        Amf0Value::Array { entries } => Value::Array(entries.iter().map(amf_to_json).collect()),
+        Amf0Value::Date {
+            unix_time,
+            time_zone,
+        } => json!({
+            "date": unix_time.as_millis() as u64,
+            "time_zone": time_zone,
+        }),
+        Amf0Value::Null | Amf0Value::Undefined => Value::Null,
+        Amf0Value::AvmPlus(value) => Value::String(format!("{:?}", value)),
+    }
+}
+
+///Json number,non-finite numbers become null
+fn number(n: f64) -> Value {
+    serde_json::Number::from_f64(n)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+fn stream_stats(stats: &StreamStats) -> Value {
+    json!({
+        "tags": stats.tags,
+        "frames": stats.frames,
+        "bytes": stats.bytes,
+        "first_timestamp": stats.first_timestamp,
+        "last_timestamp": stats.first_timestamp.map(|_| stats.last_timestamp),
+        "duration": number(stats.duration()),
+        "average_bitrate": number(stats.average_bitrate()),
+        "peak_bitrate": number(stats.peak_bitrate()),
+    })
+}
+
+fn scan_report(scan: &Scan) -> Value {
+    let mut video = stream_stats(&scan.video);
+    let params = &scan.video_params;
+    let interval = scan.keyframe_interval_range();
+    if let Value::Object(map) = &mut video {
+        map.insert("codec_id".into(), json!(params.codec_id));
+        map.insert(
+            "codec_name".into(),
+            json!(params.codec_id.map(|id| video_codec_name(&(id as f64)))),
+        );
+        map.insert("width".into(), json!(params.width));
+        map.insert("height".into(), json!(params.height));
+        map.insert("profile".into(), json!(params.profile));
+        map.insert("level".into(), json!(params.level));
+        map.insert("frame_rate".into(), number(scan.video.frame_rate()));
+        map.insert("keyframes".into(), json!(scan.keyframes));
+        map.insert(
+            "keyframe_interval".into(),
+            json!({
+                "average": scan.keyframe_interval(),
+                "min": interval.map(|i| i.0),
+                "max": interval.map(|i| i.1),
+            }),
+        );
+    }
+    let mut audio = stream_stats(&scan.audio);
+    let params = &scan.audio_params;
+    if let Value::Object(map) = &mut audio {
+        map.insert("codec_id".into(), json!(params.sound_format));
+        map.insert(
+            "codec_name".into(),
+            json!(params.sound_format.map(|id| audio_codec_name(&(id as f64)))),
+        );
+        map.insert("sample_rate".into(), json!(params.sample_rate));
+        map.insert("sample_size".into(), json!(params.sample_size));
+        map.insert("channels".into(), json!(params.channels));
+    }
+    json!({
+        "duration": number(scan.duration()),
+        "script_tags": scan.script_tags,
+        "video": video,
+        "audio": audio,
+    })
+}
+
+///Build the info report of a full scan
+pub fn info(file: &str, scan: &Scan) -> Value {
+    let header = scan.header.as_ref().map(|header| {
+        json!({
+            "signature": header.signature(),
+            "version": header.version(),
+            "has_video": header.has_video(),
+            "has_audio": header.has_audio(),
+            "size": header.size(),
+        })
+    });
+    let metadata = scan
+        .metadata
+        .as_ref()
+        .map(|values| Value::Array(values.iter().map(amf_to_json).collect()));
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "file": file,
+        "header": header,
+        "metadata": metadata,
+        "scan": scan_report(scan),
+        "error": scan.error,
+    })
+}
+
+///Render a json value as a yaml document
+pub fn to_yaml(value: &Value) -> String {
+    let mut out = String::from("---\n");
+    match value {
+        Value::Object(map) if !map.is_empty() => write_yaml_map(map, 0, &mut out),
+        Value::Array(items) if !items.is_empty() => write_yaml_list(items, 0, &mut out),
+        _ => {
+            out.push_str(&yaml_scalar(value));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Object(_) => "{}".to_string(),
+        Value::Array(_) => "[]".to_string(),
+        //a json string literal is a valid yaml double-quoted scalar
+        _ => value.to_string(),
+    }
+}
+
+fn is_nested(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => !map.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => false,
+    }
+}
+
+fn yaml_key(key: &str) -> String {
+    let plain = key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !["null", "true", "false", "yes", "no", "on", "off"]
+            .contains(&key.to_lowercase().as_str());
+    if plain {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    }
+}
+
+fn write_yaml_map(map: &Map<String, Value>, indent: usize, out: &mut String) {
+    for (key, value) in map {
+        out.push_str(&" ".repeat(indent));
+        out.push_str(&yaml_key(key));
+        if is_nested(value) {
+            out.push_str(":\n");
+            write_yaml_value(value, indent + 2, out);
+        } else {
+            out.push_str(": ");
+            out.push_str(&yaml_scalar(value));
+            out.push('\n');
+        }
+    }
+}
+
+fn write_yaml_list(items: &[Value], indent: usize, out: &mut String) {
+    for item in items {
+        out.push_str(&" ".repeat(indent));
+        if is_nested(item) {
+            out.push_str("-\n");
+            write_yaml_value(item, indent + 2, out);
+        } else {
+            out.push_str("- ");
+            out.push_str(&yaml_scalar(item));
+            out.push('\n');
+        }
+    }
+}
+
+fn write_yaml_value(value: &Value, indent: usize, out: &mut String) {
+    match value {
+        Value::Object(map) => write_yaml_map(map, indent, out),
+        Value::Array(items) => write_yaml_list(items, indent, out),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flv_parser::amf::Pair;
+    use flv_parser::flv::{Header, Tag, TagData, Writer};
+    use std::time::Duration;
+
+    fn pair(key: &str, value: Amf0Value) -> Pair<String, Amf0Value> {
+        Pair {
+            key: key.to_string(),
+            value,
+        }
+    }
+
+    #[test]
+    fn info_has_the_documented_top_level_keys() {
+        let mut writer = Writer::new(vec![]);
+        writer.write_header(&Header::new(true, true)).unwrap();
+        let metadata = vec![
+            Amf0Value::String("onMetaData".to_string()),
+            Amf0Value::EcmaArray {
+                entries: vec![pair("duration", Amf0Value::Number(1.0))],
+            },
+        ];
+        writer
+            .write_tag(&Tag::new(0, TagData::Script(metadata)))
+            .unwrap();
+        writer
+            .write_tag(&Tag::video(0, 1, Tag::VIDEO_CODEC_AVC, 1, 0, &[0; 8]))
+            .unwrap();
+        writer.write_tag(&Tag::audio(20, 0xAF, 1, &[0; 4])).unwrap();
+        let scan = Scan::from_reader(&writer.into_inner()[..], true);
+
+        let report = info("a.flv", &scan);
+        let keys: Vec<&str> = report
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "schema_version",
+                "file",
+                "header",
+                "metadata",
+                "scan",
+                "error"
+            ]
+        );
+        assert_eq!(report["schema_version"], json!(1));
+        assert_eq!(report["file"], json!("a.flv"));
+        assert_eq!(report["header"]["has_video"], json!(true));
+        assert_eq!(report["metadata"][1]["duration"], json!(1.0));
+        assert_eq!(report["scan"]["video"]["frames"], json!(1));
+        assert_eq!(report["scan"]["audio"]["first_timestamp"], json!(20));
+        assert_eq!(report["error"], Value::Null);
+    }
+
+    #[test]
+    fn converts_every_amf_value() {
+        let object = Amf0Value::Object {
+            class_name: None,
+            entries: vec![
+                pair("b", Amf0Value::Boolean(true)),
+                pair("a", Amf0Value::Null),
+            ],
+        };
+        assert_eq!(amf_to_json(&object).to_string(), r#"{"b":true,"a":null}"#);
+        let array = Amf0Value::EcmaArray {
+            entries: vec![
+                pair("width", Amf0Value::Number(1280.0)),
+                pair("name", Amf0Value::String("x".to_string())),
+            ],
+        };
+        assert_eq!(amf_to_json(&array), json!({"width": 1280.0, "name": "x"}));
+        let strict = Amf0Value::Array {
+            entries: vec![Amf0Value::Number(1.5), Amf0Value::Undefined],
+        };
+        assert_eq!(amf_to_json(&strict), json!([1.5, null]));
+        let date = Amf0Value::Date {
+            unix_time: Duration::from_millis(1_590_796_800_000),
+            time_zone: -60,
+        };
+        assert_eq!(
+            amf_to_json(&date),
+            json!({"date": 1_590_796_800_000_u64, "time_zone": -60})
+        );
+        assert_eq!(amf_to_json(&Amf0Value::Null), Value::Null);
+        assert_eq!(amf_to_json(&Amf0Value::Undefined), Value::Null);
+        //json has no NaN
+        assert_eq!(amf_to_json(&Amf0Value::Number(f64::NAN)), Value::Null);
+    }
+
+    #[test]
+    fn quotes_yaml_keys_that_would_change_meaning() {
+        let value = json!({"on": 1, "No": 2, "plain_key": 3, "2x": 4, "a b": 5, "größe": 6});
+        assert_eq!(
+            to_yaml(&value),
+            "---\n\"on\": 1\n\"No\": 2\nplain_key: 3\n\"2x\": 4\n\"a b\": 5\n\"größe\": 6\n"
+        );
+    }
+
+    #[test]
+    fn quotes_strings_and_writes_empty_containers_inline() {
+        let value = json!({
+            "title": "été: \"live\"",
+            "list": [[], {}, "yes"],
+            "empty": {},
+            "none": null,
+        });
+        assert_eq!(
+            to_yaml(&value),
+            "---\ntitle: \"été: \\\"live\\\"\"\nlist:\n  - []\n  - {}\n  - \"yes\"\nempty: {}\nnone: null\n"
+        );
+        assert_eq!(to_yaml(&json!([])), "---\n[]\n");
+        assert_eq!(
+            to_yaml(&json!({"a": {"b": [1]}})),
+            "---\na:\n  b:\n    - 1\n"
+        );
+    }
+}
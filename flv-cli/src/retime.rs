@@ -0,0 +1,277 @@
+//! Commands rewriting timestamps: `fix-timestamps` and `retime`.
+
+use crate::media::{is_metadata, stream_index, Span};
+use crate::{open_input, open_output};
+use flv_parser::amf::Amf0Value;
+use flv_parser::flv::{
+    DiscontinuityKind, Header, ParseResult, Reader, ScriptTagDataTrait, Tag, TagData,
+    TimestampFilter, TimestampFilterOptions, Writer,
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+};
+
+fn stream_name(tag: &Tag) -> &'static str {
+    if tag.is_video_tag() {
+        "Video"
+    } else {
+        "Audio"
+    }
+}
+
+///Where onMetaData was written,so its duration can be updated at the end
+struct MetadataTag {
+    position: u64,
+    tag: Tag,
+}
+
+///Copy the tags of src to writer through f,which rewrites tags and returns false to drop one.
+///header may change the flags of the header.
+///Returns the onMetaData written and the duration of the output in seconds.
+fn copy_tags<W: Write>(
+    src: &str,
+    writer: &mut Writer<W>,
+    header: impl FnOnce(&mut Header),
+    mut f: impl FnMut(&mut Tag) -> bool,
+) -> io::Result<(Option<MetadataTag>, f64)> {
+    let mut reader = Reader::new(open_input(src)?);
+    let mut position = 0;
+    let mut metadata = None;
+    let mut spans = [Span::default(); 2];
+    let mut header = Some(header);
+    while let Some(result) = reader.read_next()? {
+        match result {
+            ParseResult::Header(mut flv_header) => {
+                if let Some(header) = header.take() {
+                    header(&mut flv_header);
+                }
+                writer.write_header(&flv_header)?;
+                position = flv_header.into_bytes().len() as u64 + 4;
+            }
+            ParseResult::Tag(mut tag) => {
+                if let TagData::Script(values) = tag.data() {
+                    let values = values.clone();
+                    //the data size of a script tag follows its encoded values
+                    tag = Tag::new(tag.timestamp(), TagData::Script(values));
+                }
+                if !f(&mut tag) {
+                    continue;
+                }
+                if let TagData::Script(values) = tag.data() {
+                    if metadata.is_none() && is_metadata(values) {
+                        metadata = Some(MetadataTag {
+                            position,
+                            tag: tag.clone(),
+                        });
+                    }
+                }
+                if let Some(index) = stream_index(&tag) {
+                    let end_of_sequence =
+                        tag.packet_type() == Some(Tag::PACKET_TYPE_END_OF_SEQUENCE);
+                    if !tag.is_sequence_header() && !end_of_sequence {
+                        spans[index].push(tag.timestamp(), tag.data_size());
+                    }
+                }
+                writer.write_tag(&tag)?;
+                position += tag.tag_size() as u64 + 4;
+            }
+            _ => {}
+        }
+    }
+    if reader.pending() > 0 {
+        eprintln!(
+            "Warning: {} bytes of a truncated tag ignored",
+            reader.pending()
+        );
+    }
+    let end = spans.iter().filter_map(Span::end).max().unwrap_or(0);
+    Ok((metadata, end as f64 / 1000.0))
+}
+
+///Copy src to path through header and f,the duration of onMetaData is updated when writing a file
+fn rewrite(
+    src: &str,
+    path: &str,
+    header: impl FnOnce(&mut Header),
+    f: impl FnMut(&mut Tag) -> bool,
+) -> io::Result<()> {
+    if path == "-" {
+        let mut writer = Writer::new(open_output(path)?);
+        copy_tags(src, &mut writer, header, f)?;
+        return writer.flush();
+    }
+    let mut writer = Writer::new(BufWriter::new(File::create(path)?));
+    let (metadata, duration) = copy_tags(src, &mut writer, header, f)?;
+    writer.flush()?;
+    if let Some(MetadataTag { position, tag }) = metadata {
+        if let TagData::Script(values) = tag.data() {
+            let mut values = values.clone();
+            values.set_duration(duration);
+            let updated = Tag::new(tag.timestamp(), TagData::Script(values));
+            //only a value changes,a tag of another size would move the following tags
+            if updated.tag_size() == tag.tag_size() {
+                let file = writer.get_mut().get_mut();
+                file.seek(SeekFrom::Start(position))?;
+                file.write_all(&updated.into_bytes())?;
+                file.flush()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+///Rewrite the timestamps of src to be continuous,reporting every discontinuity bridged
+pub fn fix_timestamps(src: &str, path: &str, options: TimestampFilterOptions) -> io::Result<()> {
+    let mut filter = TimestampFilter::new(options);
+    let mut count = 0;
+    rewrite(
+        src,
+        path,
+        |_| {},
+        |tag| {
+            if let Some(discontinuity) = filter.push(tag) {
+                count += 1;
+                let previous = match discontinuity.previous {
+                    Some(previous) => format!("from {}ms ", previous),
+                    None => String::new(),
+                };
+                eprintln!(
+                    "{} timestamps jump {} {}to {}ms,continued at {}ms",
+                    stream_name(tag),
+                    match discontinuity.kind {
+                        DiscontinuityKind::Backward => "back",
+                        DiscontinuityKind::Gap => "forward",
+                    },
+                    previous,
+                    discontinuity.found,
+                    discontinuity.timestamp
+                );
+            }
+            true
+        },
+    )?;
+    if filter.clamped() > 0 {
+        eprintln!(
+            "Clamped {} timestamps going back less than {}ms",
+            filter.clamped(),
+            options.max_backward
+        );
+    }
+    if count == 0 && filter.clamped() == 0 {
+        eprintln!("No discontinuities found");
+    }
+    Ok(())
+}
+
+///Metadata keys describing the audio stream
+const AUDIO_METADATA_KEYS: [&str; 6] = [
+    "audiocodecid",
+    "audiosamplerate",
+    "audiosamplesize",
+    "audiodatarate",
+    "audiosize",
+    "stereo",
+];
+
+///Timestamp changes of the retime command,in milliseconds
+#[derive(Debug, Clone, Copy)]
+pub struct RetimeOptions {
+    ///Move the first audio or video tag to 0
+    pub rebase: bool,
+    ///Added to every timestamp
+    pub shift: i64,
+    ///Added to audio timestamps only,positive values delay the audio
+    pub audio_offset: i64,
+    ///Playback speed,video and script timestamps are divided by it
+    pub speed: f64,
+    ///Keep audio tags with unscaled timestamps,or drop them
+    pub keep_audio: bool,
+}
+
+impl Default for RetimeOptions {
+    fn default() -> Self {
+        RetimeOptions {
+            rebase: false,
+            shift: 0,
+            audio_offset: 0,
+            speed: 1.0,
+            keep_audio: true,
+        }
+    }
+}
+
+///onMetaData for a playback speed,values that no longer apply are multiplied or removed
+fn speed_metadata(values: &mut [Amf0Value], speed: f64, keep_audio: bool) {
+    for value in values.iter_mut() {
+        if let Amf0Value::EcmaArray { entries } | Amf0Value::Object { entries, .. } = value {
+            if !keep_audio {
+                entries.retain(|pair| !AUDIO_METADATA_KEYS.contains(&pair.key.as_str()));
+            }
+            for pair in entries.iter_mut() {
+                match (pair.key.as_str(), &mut pair.value) {
+                    ("framerate" | "videodatarate", Amf0Value::Number(n)) => *n *= speed,
+                    //written to files again once the output is measured
+                    ("duration", Amf0Value::Number(n)) => *n /= speed,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+///Rebase,scale and shift the timestamps of src,timestamps below 0 are clamped.
+///Composition times are relative to the timestamp and stay,unless a clamped frame
+///would be presented late:then they shrink to keep the presentation time when possible.
+pub fn retime(src: &str, path: &str, options: RetimeOptions) -> io::Result<()> {
+    let mut base = None;
+    let mut clamped = 0;
+    let scaled = options.speed != 1.0;
+    let header = |header: &mut Header| {
+        if !options.keep_audio {
+            header.set_has_audio(false);
+        }
+    };
+    rewrite(src, path, header, |tag| {
+        if tag.is_audio_tag() && !options.keep_audio {
+            return false;
+        }
+        if scaled {
+            if let TagData::Script(values) = tag.data() {
+                if is_metadata(values) {
+                    let mut values = values.clone();
+                    speed_metadata(&mut values, options.speed, options.keep_audio);
+                    *tag = Tag::new(tag.timestamp(), TagData::Script(values));
+                }
+            }
+        }
+        let mut timestamp = tag.timestamp() as i64;
+        if tag.is_audio_tag() {
+            timestamp += options.audio_offset;
+        }
+        if options.rebase && base.is_none() && stream_index(tag).is_some() {
+            base = Some(timestamp);
+        }
+        timestamp -= base.unwrap_or(0);
+        if scaled && !tag.is_audio_tag() {
+            timestamp = (timestamp as f64 / options.speed).round() as i64;
+            let composition_time = tag.composition_time() as f64 / options.speed;
+            tag.set_composition_time(composition_time.round() as i32);
+        }
+        timestamp += options.shift;
+        if timestamp < 0 {
+            clamped += 1;
+            let composition_time = tag.composition_time() as i64;
+            if composition_time > 0 {
+                tag.set_composition_time((composition_time + timestamp).max(0) as i32);
+            }
+            timestamp = 0;
+        }
+        tag.set_timestamp(timestamp as u32);
+        true
+    })?;
+    if clamped > 0 {
+        eprintln!("Clamped {} timestamps below 0", clamped);
+    }
+    Ok(())
+}
@@ -0,0 +1,299 @@
+use flv_parser::amf::Amf0Value;
+use flv_parser::flv::{
+    aac::AudioSpecificConfig, avc::AvcDecoderConfig, hevc::HevcDecoderConfig, Header, ParseResult,
+    Reader, Tag, TagData,
+};
+use std::{collections::BTreeMap, io::Read};
+
+///Statistics of the tags of one stream
+#[derive(Debug, Default, Clone)]
+pub struct StreamStats {
+    pub tags: u64,
+    pub frames: u64,
+    pub bytes: u64,
+    pub first_timestamp: Option<u32>,
+    pub last_timestamp: u32,
+    bytes_per_second: BTreeMap<u32, u64>,
+}
+
+impl StreamStats {
+    fn add(&mut self, tag: &Tag, is_frame: bool) {
+        let timestamp = tag.timestamp();
+        self.tags += 1;
+        self.bytes += tag.data_size() as u64;
+        if !is_frame {
+            return;
+        }
+        self.frames += 1;
+        if self.first_timestamp.is_none() {
+            self.first_timestamp = Some(timestamp);
+        }
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+        *self.bytes_per_second.entry(timestamp / 1000).or_insert(0) += tag.data_size() as u64;
+    }
+
+    ///Span between the first and last frame plus one average frame duration,in seconds
+    pub fn duration(&self) -> f64 {
+        let first = match self.first_timestamp {
+            Some(first) => first,
+            None => return 0.0,
+        };
+        let span = (self.last_timestamp - first) as f64 / 1000.0;
+        if self.frames > 1 {
+            span + span / (self.frames - 1) as f64
+        } else {
+            span
+        }
+    }
+
+    ///Frames per second
+    pub fn frame_rate(&self) -> f64 {
+        let duration = self.duration();
+        if duration > 0.0 {
+            self.frames as f64 / duration
+        } else {
+            0.0
+        }
+    }
+
+    ///Average bitrate in kbps
+    pub fn average_bitrate(&self) -> f64 {
+        let duration = self.duration();
+        if duration > 0.0 {
+            self.bytes as f64 * 8.0 / duration / 1000.0
+        } else {
+            0.0
+        }
+    }
+
+    ///The highest bitrate of any whole second in kbps
+    pub fn peak_bitrate(&self) -> f64 {
+        self.bytes_per_second
+            .values()
+            .max()
+            .map(|bytes| *bytes as f64 * 8.0 / 1000.0)
+            .unwrap_or(0.0)
+    }
+}
+
+///Video parameters found in the tag headers and the sequence header
+#[derive(Debug, Default, Clone)]
+pub struct VideoParams {
+    pub codec_id: Option<u8>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub profile: Option<u8>,
+    pub level: Option<u8>,
+}
+
+///Audio parameters found in the tag headers and the sequence header
+#[derive(Debug, Default, Clone)]
+pub struct AudioParams {
+    pub sound_format: Option<u8>,
+    pub sample_rate: Option<u32>,
+    pub sample_size: Option<u8>,
+    pub channels: Option<u8>,
+}
+
+///Result of walking a whole flv file
+#[derive(Debug, Default, Clone)]
+pub struct Scan {
+    pub header: Option<Header>,
+    pub metadata: Option<Vec<Amf0Value>>,
+    pub video: StreamStats,
+    pub audio: StreamStats,
+    pub script_tags: u64,
+    pub keyframes: u64,
+    pub keyframe_intervals: Vec<u32>,
+    pub video_params: VideoParams,
+    pub audio_params: AudioParams,
+    ///Error stopped the scan before the end of input
+    pub error: Option<String>,
+    last_keyframe: Option<u32>,
+}
+
+impl Scan {
+    ///Walk the flv from reader,collecting statistics of every tag.
+    ///Unless full is set,it stops at the first script tag.
+    pub fn from_reader(reader: impl Read, full: bool) -> Self {
+        let mut scan = Scan::default();
+        for result in Reader::new(reader) {
+            match result {
+                Ok(ParseResult::Header(header)) => {
+                    scan.header = Some(header);
+                }
+                Ok(ParseResult::Tag(tag)) => {
+                    scan.push(&tag);
+                    if !full && scan.metadata.is_some() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    scan.error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        scan
+    }
+
+    fn push(&mut self, tag: &Tag) {
+        match tag.data() {
+            TagData::Script(values) => {
+                self.script_tags += 1;
+                if self.metadata.is_none() {
+                    self.metadata = Some(values.clone());
+                }
+            }
+            TagData::Video(_) => {
+                let is_frame =
+                    tag.packet_type().unwrap_or(Tag::PACKET_TYPE_NALU) == Tag::PACKET_TYPE_NALU;
+                self.video.add(tag, is_frame);
+                self.push_video_params(tag);
+                if is_frame && tag.is_keyframe() {
+                    self.keyframes += 1;
+                    if let Some(last) = self.last_keyframe {
+                        self.keyframe_intervals
+                            .push(tag.timestamp().saturating_sub(last));
+                    }
+                    self.last_keyframe = Some(tag.timestamp());
+                }
+            }
+            TagData::Audio(_) => {
+                self.audio.add(tag, !tag.is_sequence_header());
+                self.push_audio_params(tag);
+            }
+        }
+    }
+
+    fn push_video_params(&mut self, tag: &Tag) {
+        let params = &mut self.video_params;
+        if params.codec_id.is_none() {
+            params.codec_id = tag.video_codec_id();
+        }
+        if !tag.is_sequence_header() {
+            if params.width.is_none() {
+                let size = match tag.video_codec_id() {
+                    Some(Tag::VIDEO_CODEC_H263) => h263_picture_size(tag.body()),
+                    Some(Tag::VIDEO_CODEC_VP6) => vp6_picture_size(tag.body(), false),
+                    Some(Tag::VIDEO_CODEC_VP6_ALPHA) => vp6_picture_size(tag.body(), true),
+                    _ => None,
+                };
+                if let Some((width, height)) = size {
+                    params.width = Some(width);
+                    params.height = Some(height);
+                }
+            }
+            return;
+        }
+        match tag.video_codec_id() {
+            Some(Tag::VIDEO_CODEC_AVC) => {
+                if let Ok(sps) =
+                    AvcDecoderConfig::from_bytes(tag.body()).and_then(|c| c.parse_sps())
+                {
+                    params.width = Some(sps.width());
+                    params.height = Some(sps.height());
+                    params.profile = Some(sps.profile());
+                    params.level = Some(sps.level());
+                }
+            }
+            Some(Tag::VIDEO_CODEC_HEVC) => {
+                if let Ok(sps) =
+                    HevcDecoderConfig::from_bytes(tag.body()).and_then(|c| c.parse_sps())
+                {
+                    params.width = Some(sps.width());
+                    params.height = Some(sps.height());
+                    params.profile = Some(sps.profile());
+                    params.level = Some(sps.level());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push_audio_params(&mut self, tag: &Tag) {
+        let params = &mut self.audio_params;
+        if params.sound_format.is_none() {
+            params.sound_format = tag.sound_format();
+            params.sample_rate = tag.sound_rate();
+            params.sample_size = tag.sound_size();
+            params.channels = tag.sound_channels();
+        }
+        if tag.sound_format() == Some(Tag::SOUND_FORMAT_AAC) && tag.is_sequence_header() {
+            if let Ok(config) = AudioSpecificConfig::from_bytes(tag.body()) {
+                params.sample_rate = Some(config.sampling_frequency());
+                params.channels = Some(config.channels());
+            }
+        }
+    }
+
+    ///The longer duration of audio and video,in seconds
+    pub fn duration(&self) -> f64 {
+        self.video.duration().max(self.audio.duration())
+    }
+
+    ///Average keyframe interval in seconds
+    pub fn keyframe_interval(&self) -> Option<f64> {
+        if self.keyframe_intervals.is_empty() {
+            return None;
+        }
+        let total: u64 = self.keyframe_intervals.iter().map(|i| *i as u64).sum();
+        Some(total as f64 / self.keyframe_intervals.len() as f64 / 1000.0)
+    }
+
+    ///Shortest and longest keyframe interval in seconds
+    pub fn keyframe_interval_range(&self) -> Option<(f64, f64)> {
+        let min = self.keyframe_intervals.iter().min()?;
+        let max = self.keyframe_intervals.iter().max()?;
+        Some((*min as f64 / 1000.0, *max as f64 / 1000.0))
+    }
+}
+
+///Picture size from the picture header of a Sorenson H.263 frame
+pub fn h263_picture_size(data: &[u8]) -> Option<(u32, u32)> {
+    if data.len() < 9 {
+        return None;
+    }
+    let mut bits = 0_u64;
+    for byte in &data[..8] {
+        bits = (bits << 8) | *byte as u64;
+    }
+    //skip 17 bits start code,5 bits version,8 bits temporal reference
+    let read =
+        |offset: u32, count: u32| ((bits >> (64 - offset - count)) & ((1 << count) - 1)) as u32;
+    match read(30, 3) {
+        0 => Some((read(33, 8), read(41, 8))),
+        1 => Some((read(33, 16), read(49, 16))),
+        2 => Some((352, 288)),
+        3 => Some((176, 144)),
+        4 => Some((128, 96)),
+        5 => Some((320, 240)),
+        6 => Some((160, 120)),
+        _ => None,
+    }
+}
+
+///Picture size from the header of a VP6 keyframe,data starts with the flv adjustment byte
+pub fn vp6_picture_size(data: &[u8], alpha: bool) -> Option<(u32, u32)> {
+    let adjustment = *data.first()?;
+    //the alpha variant has the offset of the alpha frame first
+    let frame = data.get(if alpha { 4 } else { 1 }..)?;
+    //only intra frames carry the size
+    if *frame.first()? & 0x80 != 0 {
+        return None;
+    }
+    let separated_coefficients = frame[0] & 0x01 != 0;
+    let filter_header = *frame.get(1)? & 0x06;
+    let offset = if separated_coefficients || filter_header == 0 {
+        4
+    } else {
+        2
+    };
+    let rows = *frame.get(offset)? as u32;
+    let cols = *frame.get(offset + 1)? as u32;
+    Some((
+        (cols * 16).checked_sub((adjustment >> 4) as u32)?,
+        (rows * 16).checked_sub((adjustment & 0x0F) as u32)?,
+    ))
+}
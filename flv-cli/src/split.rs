@@ -0,0 +1,433 @@
+//! `split` command: cut a flv into numbered self-contained files at keyframes.
+
+use crate::media::{
+    is_metadata, stream_index, AudioMetadata, CodecConfig, Demuxer, MediaEvent, Metadata, Span,
+    VideoMetadata,
+};
+use crate::open_input;
+use flv_parser::flv::{Header, ParseResult, Reader, Tag, TagData, Writer};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+};
+
+///When a new file is started,checked before every group of pictures
+#[derive(Debug, Clone, Copy)]
+pub enum SplitRule {
+    ///Milliseconds since the start of the file
+    Duration(u32),
+    ///Bytes,a file only grows beyond it when it holds a single group of pictures
+    Size(u64),
+    ///Groups of pictures per file
+    Gops(u32),
+}
+
+///Parse a duration like 10m,90s,1.5h or 00:10:00 to milliseconds
+pub fn parse_duration(text: &str) -> Option<u32> {
+    let units = [
+        ("ms", 1.0),
+        ("h", 3_600_000.0),
+        ("m", 60_000.0),
+        ("s", 1000.0),
+    ];
+    for (suffix, scale) in units.iter() {
+        if let Some(number) = text.strip_suffix(suffix) {
+            let value: f64 = number.parse().ok()?;
+            return if value >= 0.0 {
+                Some((value * scale).round() as u32)
+            } else {
+                None
+            };
+        }
+    }
+    crate::cut::parse_time(text)
+}
+
+///Parse a size like 500M,2G,100k or 1048576 to bytes
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.strip_suffix(|c| c == 'B' || c == 'b').unwrap_or(text);
+    let (number, scale) = match text.char_indices().last()? {
+        (index, 'k') | (index, 'K') => (&text[..index], 1 << 10),
+        (index, 'm') | (index, 'M') => (&text[..index], 1 << 20),
+        (index, 'g') | (index, 'G') => (&text[..index], 1 << 30),
+        _ => (text, 1),
+    };
+    let value: f64 = number.parse().ok()?;
+    if value > 0.0 {
+        Some((value * scale as f64) as u64)
+    } else {
+        None
+    }
+}
+
+///Replace the %d or %03d of a file name template with number
+pub fn segment_path(template: &str, number: u32) -> Option<String> {
+    let start = template.find('%')?;
+    let rest = &template[start + 1..];
+    let end = rest.find('d')?;
+    let width: usize = match &rest[..end] {
+        "" => 0,
+        digits if digits.chars().all(|c| c.is_ascii_digit()) => digits.parse().ok()?,
+        _ => return None,
+    };
+    Some(format!(
+        "{}{:0width$}{}",
+        &template[..start],
+        number,
+        &rest[end + 1..],
+        width = width
+    ))
+}
+
+///Tags from a video keyframe or sequence header up to the next one
+#[derive(Debug, Default)]
+struct Gop {
+    tags: Vec<Tag>,
+    has_video_frames: bool,
+    size: u64,
+}
+
+impl Gop {
+    fn push(&mut self, tag: Tag) {
+        self.has_video_frames |= tag.is_video_tag() && !tag.is_sequence_header();
+        self.size += tag.tag_size() as u64 + 4;
+        self.tags.push(tag);
+    }
+
+    ///Earliest timestamp,the start of a file beginning with this group
+    fn start(&self) -> u32 {
+        self.tags.iter().map(Tag::timestamp).min().unwrap_or(0)
+    }
+
+    ///Sequence headers which apply to the first frames,the group's own or the current ones
+    fn sequence_headers(&self, current: &[Option<Tag>; 2]) -> [Option<Tag>; 2] {
+        let mut headers = current.clone();
+        let mut framed = [false; 2];
+        for tag in &self.tags {
+            if let Some(index) = stream_index(tag) {
+                if tag.is_sequence_header() && !framed[index] {
+                    headers[index] = Some(tag.clone());
+                }
+                framed[index] |= !tag.is_sequence_header();
+            }
+        }
+        headers
+    }
+}
+
+///The file being written
+struct Output {
+    path: String,
+    writer: Writer<BufWriter<File>>,
+    metadata: Metadata,
+    ///Timestamp which becomes 0
+    base: u32,
+    spans: [Span; 2],
+    gops: u32,
+    size: u64,
+    ///Bodies of the sequence headers in effect
+    sequence_headers: [Option<Vec<u8>>; 2],
+}
+
+impl Output {
+    fn write_tag(&mut self, tag: &Tag) -> io::Result<()> {
+        self.writer.write_tag(tag)?;
+        self.size += tag.tag_size() as u64 + 4;
+        Ok(())
+    }
+
+    ///Write the duration,data rates and file size into the onMetaData placeholder
+    fn close(mut self, configs: &[Option<CodecConfig>; 2]) -> io::Result<()> {
+        //values of the placeholder stay when the configurations don't have them
+        let codecs = [
+            self.metadata.video.as_ref().map(|video| video.codec_id),
+            self.metadata.audio.as_ref().map(|audio| audio.codec_id),
+        ];
+        let sound = self
+            .metadata
+            .audio
+            .as_ref()
+            .map(|audio| (audio.sample_rate, 1 + audio.stereo as u8));
+        self.metadata = Metadata {
+            file_size: Some(self.size),
+            ..Metadata::from_spans(&self.spans, codecs, configs, sound)
+        };
+        self.writer.flush()?;
+        let file = self.writer.get_mut().get_mut();
+        //the metadata tag keeps its size,only the values change
+        file.seek(SeekFrom::Start(Header::HEADER_SIZE as u64 + 4))?;
+        file.write_all(&self.metadata.to_tag().into_bytes())?;
+        file.flush()
+    }
+}
+
+struct Splitter<'a> {
+    template: &'a str,
+    rule: Option<SplitRule>,
+    ///Start a new file when a decoder configuration changes
+    on_change: bool,
+    has_streams: [bool; 2],
+    ///Sequence headers of the tags written so far
+    sequence_headers: [Option<Tag>; 2],
+    ///Codec id of video tags and sound format of audio tags
+    codecs: [Option<u8>; 2],
+    ///Sample rate and channels of the last audio tag
+    sound: Option<(u32, u8)>,
+    demuxer: Demuxer,
+    configs: [Option<CodecConfig>; 2],
+    output: Option<Output>,
+    count: u32,
+}
+
+impl<'a> Splitter<'a> {
+    fn new(template: &'a str, rule: Option<SplitRule>, on_change: bool, header: &Header) -> Self {
+        Splitter {
+            template,
+            rule,
+            on_change,
+            has_streams: [header.has_video(), header.has_audio()],
+            sequence_headers: [None, None],
+            codecs: [None, None],
+            sound: None,
+            demuxer: Demuxer::new(),
+            configs: [None, None],
+            output: None,
+            count: 0,
+        }
+    }
+
+    fn starts_file(&self, gop: &Gop) -> bool {
+        let output = match &self.output {
+            Some(output) => output,
+            None => return true,
+        };
+        match self.rule {
+            Some(SplitRule::Duration(duration)) => {
+                gop.start().saturating_sub(output.base) >= duration
+            }
+            Some(SplitRule::Size(size)) => output.size + gop.size > size,
+            Some(SplitRule::Gops(gops)) => output.gops >= gops,
+            None => false,
+        }
+    }
+
+    ///Start a new file,configs are those of the tags before gop
+    fn open(&mut self, gop: &Gop, configs: &[Option<CodecConfig>; 2]) -> io::Result<()> {
+        self.count += 1;
+        let path = segment_path(self.template, self.count).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The file name template needs a number like %d or %03d",
+            )
+        })?;
+        let headers = gop.sequence_headers(&self.sequence_headers);
+        for tag in gop.tags.iter().chain(headers.iter().flatten()) {
+            if let Some(index) = stream_index(tag) {
+                self.has_streams[index] = true;
+            }
+        }
+        let [has_video, has_audio] = self.has_streams;
+        let (sample_rate, channels) = self.sound.unwrap_or((0, 0));
+        let metadata = Metadata {
+            file_size: Some(0),
+            video: if has_video {
+                Some(VideoMetadata {
+                    codec_id: self.codecs[0].unwrap_or(0),
+                    ..Default::default()
+                })
+            } else {
+                None
+            },
+            audio: if has_audio {
+                Some(AudioMetadata {
+                    codec_id: self.codecs[1].unwrap_or(0),
+                    sample_rate,
+                    stereo: channels > 1,
+                    ..Default::default()
+                })
+            } else {
+                None
+            },
+            ..Default::default()
+        };
+        let mut writer = Writer::new(BufWriter::new(File::create(&path)?));
+        writer.write_header(&Header::new(has_video, has_audio))?;
+        let mut output = Output {
+            path,
+            writer,
+            metadata,
+            base: gop.start(),
+            spans: [Span::default(); 2],
+            gops: 0,
+            size: Header::HEADER_SIZE as u64 + 4,
+            sequence_headers: [None, None],
+        };
+        output.write_tag(&output.metadata.to_tag())?;
+        for (index, tag) in headers.iter().enumerate() {
+            if let Some(tag) = tag {
+                let mut tag = tag.clone();
+                tag.set_timestamp(0);
+                output.write_tag(&tag)?;
+                output.sequence_headers[index] = Some(tag.body().to_vec());
+            }
+        }
+        if let Some(previous) = self.output.replace(output) {
+            previous.close(configs)?;
+        }
+        Ok(())
+    }
+
+    ///Write a group of pictures,into a new file if the rule or a configuration change says so
+    fn push(&mut self, gop: Gop) -> io::Result<()> {
+        if gop.tags.is_empty() {
+            return Ok(());
+        }
+        let configs = self.configs.clone();
+        let changes = self.inspect(&gop.tags)?;
+        if self.starts_file(&gop) || (self.on_change && !changes.is_empty()) {
+            self.open(&gop, &configs)?;
+        }
+        let output = self.output.as_mut().unwrap();
+        for change in changes {
+            eprintln!("{}: {}", output.path, change);
+        }
+        for mut tag in gop.tags {
+            let index = stream_index(&tag);
+            if let Some(index) = index {
+                if tag.is_sequence_header() {
+                    self.sequence_headers[index] = Some(tag.clone());
+                    if output.sequence_headers[index].as_deref() == Some(tag.body()) {
+                        continue;
+                    }
+                    output.sequence_headers[index] = Some(tag.body().to_vec());
+                }
+            }
+            tag.set_timestamp(tag.timestamp().saturating_sub(output.base));
+            if let Some(index) = index {
+                if !tag.is_sequence_header() {
+                    output.spans[index].push(tag.timestamp(), tag.data_size());
+                }
+            }
+            output.write_tag(&tag)?;
+        }
+        output.gops += 1;
+        Ok(())
+    }
+
+    ///Follow codecs and decoder configurations,returns descriptions of their changes
+    fn inspect(&mut self, tags: &[Tag]) -> io::Result<Vec<String>> {
+        let mut changes = vec![];
+        for tag in tags {
+            let (index, codec) = if tag.is_video_tag() {
+                (0, tag.video_codec_id())
+            } else if tag.is_audio_tag() {
+                self.sound = tag.sound_rate().zip(tag.sound_channels()).or(self.sound);
+                (1, tag.sound_format())
+            } else {
+                continue;
+            };
+            //codecs without a decoded configuration only report the codec id
+            let mut codec_change = match (self.codecs[index], codec) {
+                (Some(current), Some(codec)) if current != codec => {
+                    let name = [crate::video_codec_name, crate::audio_codec_name][index];
+                    Some(format!(
+                        "{} codec changes at {}ms,{} -> {}",
+                        ["Video", "Audio"][index],
+                        tag.timestamp(),
+                        name(&(current as f64)),
+                        name(&(codec as f64))
+                    ))
+                }
+                _ => None,
+            };
+            self.codecs[index] = codec.or(self.codecs[index]);
+            for event in self.demuxer.push(tag)? {
+                if let MediaEvent::Config(kind, config) = event {
+                    let index = kind.index();
+                    if let Some(current) = &self.configs[index] {
+                        codec_change = None;
+                        changes.push(format!(
+                            "{:?} configuration changes at {}ms,{} -> {}",
+                            kind,
+                            tag.timestamp(),
+                            current.description(),
+                            config.description()
+                        ));
+                    }
+                    self.configs[index] = Some(config);
+                }
+            }
+            changes.extend(codec_change);
+        }
+        Ok(changes)
+    }
+
+    fn finish(mut self) -> io::Result<u32> {
+        if let Some(output) = self.output.take() {
+            output.close(&self.configs)?;
+        }
+        Ok(self.count)
+    }
+}
+
+///Split src into files named by template,each starting at a video keyframe with the sequence
+///headers and an onMetaData of its own and timestamps starting at 0.
+///With on_change every change of codec or decoder configuration starts a file too,
+///a change between keyframes at the keyframe before it.
+pub fn split(
+    src: &str,
+    template: &str,
+    rule: Option<SplitRule>,
+    on_change: bool,
+) -> io::Result<()> {
+    if segment_path(template, 0).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The file name template needs a number like %d or %03d",
+        ));
+    }
+    let mut reader = Reader::new(open_input(src)?);
+    let header = match reader.read_next()? {
+        Some(ParseResult::Header(header)) => header,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "No flv header found",
+            ))
+        }
+    };
+    let mut splitter = Splitter::new(template, rule, on_change, &header);
+    let mut gop = Gop::default();
+    let mut has_video_tags = false;
+    while let Some(result) = reader.read_next()? {
+        let tag = match result {
+            ParseResult::Tag(tag) => tag,
+            _ => continue,
+        };
+        if matches!(tag.data(), TagData::Script(values) if is_metadata(values)) {
+            continue;
+        }
+        //files without video may start at any audio frame
+        let boundary = if tag.is_video_tag() {
+            tag.is_sequence_header() || tag.is_keyframe()
+        } else {
+            tag.is_audio_tag() && !has_video_tags && !tag.is_sequence_header()
+        };
+        if boundary && (gop.has_video_frames || !has_video_tags) {
+            splitter.push(std::mem::take(&mut gop))?;
+        }
+        has_video_tags |= tag.is_video_tag();
+        gop.push(tag);
+    }
+    splitter.push(gop)?;
+    if reader.pending() > 0 {
+        eprintln!(
+            "Warning: {} bytes of a truncated tag ignored",
+            reader.pending()
+        );
+    }
+    if splitter.finish()? == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "No tags found"));
+    }
+    Ok(())
+}
@@ -0,0 +1,421 @@
+//! MPEG transport stream muxer.
+
+use crate::media::{self, CodecConfig, Frame, TrackKind};
+use crate::mp4::Track;
+use std::io::{self, Write};
+
+pub const PACKET_SIZE: usize = 188;
+const PAT_PID: u16 = 0;
+const PMT_PID: u16 = 0x1000;
+const VIDEO_PID: u16 = 0x100;
+const AUDIO_PID: u16 = 0x101;
+///Added to all timestamps so the pcr,which runs behind,never goes negative
+const TIMESTAMP_OFFSET: u64 = 126_000;
+///Distance between pcr and dts in 90kHz units
+const PCR_DELAY: u64 = 63_000;
+///Interval of pat/pmt repetition in milliseconds for streams without video
+const TABLE_INTERVAL: u32 = 500;
+const TIMESTAMP_MASK: u64 = (1 << 33) - 1;
+
+///CRC-32/MPEG-2 of psi sections
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0xFFFF_FFFF_u32, |mut crc, b| {
+        crc ^= (*b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+///Encode a pts or dts with the 4 bits prefix
+fn timestamp_field(prefix: u8, ts: u64) -> [u8; 5] {
+    [
+        (prefix << 4) | ((ts >> 29) as u8 & 0x0E) | 0x01,
+        (ts >> 22) as u8,
+        ((ts >> 14) as u8 & 0xFE) | 0x01,
+        (ts >> 7) as u8,
+        ((ts << 1) as u8 & 0xFE) | 0x01,
+    ]
+}
+
+///Encode a pcr with a zero extension
+fn pcr_field(base: u64) -> [u8; 6] {
+    [
+        (base >> 25) as u8,
+        (base >> 17) as u8,
+        (base >> 9) as u8,
+        (base >> 1) as u8,
+        ((base & 0x01) << 7) as u8 | 0x7E,
+        0,
+    ]
+}
+
+struct Stream {
+    kind: TrackKind,
+    pid: u16,
+    stream_type: u8,
+    stream_id: u8,
+    config: CodecConfig,
+    continuity: u8,
+}
+
+///Write frames of the given tracks as a single program transport stream
+pub struct TsMuxer<W: Write> {
+    output: W,
+    streams: Vec<Stream>,
+    pcr_pid: u16,
+    pat_continuity: u8,
+    pmt_continuity: u8,
+    tables_pending: bool,
+    last_tables: Option<u32>,
+}
+
+impl<W: Write> TsMuxer<W> {
+    pub fn new(output: W, tracks: &[Track]) -> Self {
+        let streams: Vec<Stream> = tracks
+            .iter()
+            .map(|track| {
+                let (pid, stream_id) = match track.kind {
+                    TrackKind::Video => (VIDEO_PID, 0xE0),
+                    TrackKind::Audio => (AUDIO_PID, 0xC0),
+                };
+                let stream_type = match &track.config {
+                    CodecConfig::Avc(_) => 0x1B,
+                    CodecConfig::Hevc(_) => 0x24,
+                    CodecConfig::Aac(_) => 0x0F,
+                    CodecConfig::Mp3(header) if header.version() == 1 => 0x03,
+                    CodecConfig::Mp3(_) => 0x04,
+                };
+                Stream {
+                    kind: track.kind,
+                    pid,
+                    stream_type,
+                    stream_id,
+                    config: track.config.clone(),
+                    continuity: 0,
+                }
+            })
+            .collect();
+        let pcr_pid = streams
+            .iter()
+            .find(|s| s.kind == TrackKind::Video)
+            .or_else(|| streams.first())
+            .map(|s| s.pid)
+            .unwrap_or(0x1FFF);
+        Self {
+            output,
+            streams,
+            pcr_pid,
+            pat_continuity: 0,
+            pmt_continuity: 0,
+            tables_pending: true,
+            last_tables: None,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    ///Repeat pat and pmt before the next frame
+    pub fn write_tables(&mut self) {
+        self.tables_pending = true;
+    }
+
+    ///Write one ts packet,returning the count of payload bytes consumed
+    fn write_packet(
+        &mut self,
+        pid: u16,
+        start: bool,
+        random_access: bool,
+        pcr: Option<u64>,
+        payload: &[u8],
+    ) -> io::Result<usize> {
+        let continuity = if pid == PAT_PID {
+            &mut self.pat_continuity
+        } else if pid == PMT_PID {
+            &mut self.pmt_continuity
+        } else {
+            match self.streams.iter_mut().find(|s| s.pid == pid) {
+                Some(stream) => &mut stream.continuity,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Unknown pid")),
+            }
+        };
+        //adaptation field content after its length byte
+        let mut adaptation = vec![];
+        if random_access || pcr.is_some() {
+            let mut flags = 0_u8;
+            if random_access {
+                flags |= 0x40;
+            }
+            if pcr.is_some() {
+                flags |= 0x10;
+            }
+            adaptation.push(flags);
+            if let Some(pcr) = pcr {
+                adaptation.extend_from_slice(&pcr_field(pcr));
+            }
+        }
+        let mut adaptation_size = if adaptation.is_empty() {
+            0
+        } else {
+            1 + adaptation.len()
+        };
+        let room = PACKET_SIZE - 4 - adaptation_size;
+        if payload.len() < room {
+            //stuff the adaptation field to fill the packet
+            let stuffing = room - payload.len();
+            if adaptation.is_empty() && stuffing > 1 {
+                adaptation.push(0);
+                adaptation.resize(stuffing - 1, 0xFF);
+            } else if !adaptation.is_empty() {
+                adaptation.resize(adaptation.len() + stuffing, 0xFF);
+            }
+            adaptation_size += stuffing;
+        }
+        let has_adaptation = adaptation_size > 0;
+        let size = payload.len().min(PACKET_SIZE - 4 - adaptation_size);
+
+        let mut packet = Vec::with_capacity(PACKET_SIZE);
+        packet.push(0x47);
+        packet.push(((start as u8) << 6) | ((pid >> 8) as u8 & 0x1F));
+        packet.push(pid as u8);
+        packet.push(if has_adaptation { 0x30 } else { 0x10 } | *continuity);
+        *continuity = (*continuity + 1) & 0x0F;
+        if has_adaptation {
+            packet.push(adaptation.len() as u8);
+            packet.extend_from_slice(&adaptation);
+        }
+        packet.extend_from_slice(&payload[..size]);
+        self.output.write_all(&packet)?;
+        Ok(size)
+    }
+
+    ///Write a psi section in a single packet
+    fn write_section(&mut self, pid: u16, table_id: u8, id: u16, body: &[u8]) -> io::Result<()> {
+        let length = 5 + body.len() + 4;
+        let mut section = vec![
+            table_id,
+            0xB0 | (length >> 8) as u8,
+            length as u8,
+            (id >> 8) as u8,
+            id as u8,
+            0xC1,
+            0,
+            0,
+        ];
+        section.extend_from_slice(body);
+        section.extend_from_slice(&crc32(&section).to_be_bytes());
+        let mut payload = vec![0];
+        payload.extend_from_slice(&section);
+        payload.resize(PACKET_SIZE - 4, 0xFF);
+        self.write_packet(pid, true, false, None, &payload)?;
+        Ok(())
+    }
+
+    fn write_pat_pmt(&mut self) -> io::Result<()> {
+        self.write_section(
+            PAT_PID,
+            0x00,
+            1,
+            &[0, 1, 0xE0 | (PMT_PID >> 8) as u8, PMT_PID as u8],
+        )?;
+        let mut body = vec![
+            0xE0 | (self.pcr_pid >> 8) as u8,
+            self.pcr_pid as u8,
+            0xF0,
+            0,
+        ];
+        for stream in &self.streams {
+            body.extend_from_slice(&[
+                stream.stream_type,
+                0xE0 | (stream.pid >> 8) as u8,
+                stream.pid as u8,
+                0xF0,
+                0,
+            ]);
+        }
+        self.write_section(PMT_PID, 0x02, 1, &body)
+    }
+
+    ///Packetize one pes packet
+    fn write_pes(
+        &mut self,
+        index: usize,
+        data: &[u8],
+        pts: u64,
+        dts: u64,
+        random_access: bool,
+    ) -> io::Result<()> {
+        let stream = &self.streams[index];
+        let pid = stream.pid;
+        let mut header = vec![0, 0, 1, stream.stream_id, 0, 0, 0x80];
+        if pts != dts {
+            header.extend_from_slice(&[0xC0, 10]);
+            header.extend_from_slice(&timestamp_field(0x03, pts));
+            header.extend_from_slice(&timestamp_field(0x01, dts));
+        } else {
+            header.extend_from_slice(&[0x80, 5]);
+            header.extend_from_slice(&timestamp_field(0x02, pts));
+        }
+        //video may leave the length unbounded
+        let length = header.len() - 6 + data.len();
+        if length <= 0xFFFF {
+            header[4] = (length >> 8) as u8;
+            header[5] = length as u8;
+        } else if stream.kind != TrackKind::Video {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Audio frame too large for a pes packet",
+            ));
+        }
+        let pcr = if pid == self.pcr_pid {
+            Some(dts.wrapping_sub(PCR_DELAY) & TIMESTAMP_MASK)
+        } else {
+            None
+        };
+        let mut pes = header;
+        pes.extend_from_slice(data);
+        let mut offset = self.write_packet(pid, true, random_access, pcr, &pes)?;
+        while offset < pes.len() {
+            offset += self.write_packet(pid, false, false, None, &pes[offset..])?;
+        }
+        Ok(())
+    }
+
+    ///Write a frame of one of the tracks,video is converted to Annex B and aac gets adts headers
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let index = match self.streams.iter().position(|s| s.kind == frame.kind) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let stream = &self.streams[index];
+        if frame.keyframe && stream.pid == self.pcr_pid {
+            let due = match self.last_tables {
+                Some(last) => {
+                    frame.kind == TrackKind::Video
+                        || frame.timestamp.saturating_sub(last) >= TABLE_INTERVAL
+                }
+                None => true,
+            };
+            self.tables_pending |= due;
+        }
+        let data = match &stream.config {
+            CodecConfig::Avc(_) | CodecConfig::Hevc(_) => {
+                media::to_annex_b(&stream.config, &frame.data, true)?
+            }
+            CodecConfig::Aac(config) => {
+                let mut data = config.adts_header(frame.data.len())?.to_vec();
+                data.extend_from_slice(&frame.data);
+                data
+            }
+            CodecConfig::Mp3(_) => frame.data.clone(),
+        };
+        if self.tables_pending {
+            self.write_pat_pmt()?;
+            self.tables_pending = false;
+            self.last_tables = Some(frame.timestamp);
+        }
+        let dts = (frame.timestamp as u64 * 90 + TIMESTAMP_OFFSET) & TIMESTAMP_MASK;
+        let pts = ((frame.timestamp as i64 + frame.composition_time as i64) * 90
+            + TIMESTAMP_OFFSET as i64)
+            .max(0) as u64
+            & TIMESTAMP_MASK;
+        let random_access = frame.keyframe && frame.kind == TrackKind::Video;
+        self.write_pes(index, &data, pts, dts, random_access)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flv_parser::flv::aac::AudioSpecificConfig;
+
+    #[test]
+    fn computes_the_mpeg2_crc() {
+        assert_eq!(crc32(b"123456789"), 0x0376_E6E7);
+        //pat of program 1 on pid 0x1000
+        let pat = [
+            0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01, 0xF0, 0x00,
+        ];
+        assert_eq!(crc32(&pat), 0x2AB1_04B2);
+    }
+
+    #[test]
+    fn packs_timestamp_fields() {
+        assert_eq!(
+            timestamp_field(0x02, 126_000),
+            [0x21, 0x00, 0x07, 0xD8, 0x61]
+        );
+        assert_eq!(
+            timestamp_field(0x03, TIMESTAMP_MASK),
+            [0x3F, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(pcr_field(63_000), [0x00, 0x00, 0x7B, 0x0C, 0x7E, 0x00]);
+        assert_eq!(
+            pcr_field(TIMESTAMP_MASK),
+            [0xFF, 0xFF, 0xFF, 0xFF, 0xFE, 0x00]
+        );
+    }
+
+    #[test]
+    fn writes_tables_then_a_pes_with_pcr() {
+        let config = AudioSpecificConfig::from_bytes(&[0x12, 0x10]).unwrap();
+        let track = Track::new(1, TrackKind::Audio, CodecConfig::Aac(config));
+        let mut muxer = TsMuxer::new(vec![], &[track]);
+        muxer
+            .write_frame(&Frame {
+                kind: TrackKind::Audio,
+                timestamp: 0,
+                composition_time: 0,
+                keyframe: true,
+                data: vec![0; 8],
+            })
+            .unwrap();
+        let output = muxer.into_inner();
+        let packets: Vec<&[u8]> = output.chunks(PACKET_SIZE).collect();
+        assert_eq!(packets.len(), 3);
+        assert!(packets
+            .iter()
+            .all(|p| p.len() == PACKET_SIZE && p[0] == 0x47));
+
+        //pat with pointer field 0
+        assert_eq!(packets[0][1..4], [0x40, 0x00, 0x10]);
+        assert_eq!(
+            packets[0][5..21],
+            [
+                0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01, 0xF0, 0x00, 0x2A, 0xB1,
+                0x04, 0xB2
+            ]
+        );
+        //pmt on pid 0x1000 with the audio pid as pcr pid and an aac stream
+        assert_eq!(packets[1][1..3], [0x50, 0x00]);
+        assert_eq!(
+            packets[1][13..22],
+            [0xE1, 0x01, 0xF0, 0x00, 0x0F, 0xE1, 0x01, 0xF0, 0x00]
+        );
+
+        //pcr runs PCR_DELAY behind the dts
+        let pes = packets[2];
+        assert_eq!(pes[1..4], [0x41, 0x01, 0x30]);
+        assert_eq!(pes[5], 0x10);
+        assert_eq!(pes[6..12], pcr_field(TIMESTAMP_OFFSET - PCR_DELAY));
+        let start = 5 + pes[4] as usize;
+        assert_eq!(
+            pes[start..start + 14],
+            [0x00, 0x00, 0x01, 0xC0, 0x00, 0x17, 0x80, 0x80, 0x05, 0x21, 0x00, 0x07, 0xD8, 0x61]
+        );
+        //adts header,then the frame
+        assert_eq!(pes[start + 14..start + 16], [0xFF, 0xF1]);
+        assert_eq!(pes.len() - start - 21, 8);
+    }
+}
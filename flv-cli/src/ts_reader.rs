@@ -0,0 +1,551 @@
+//! MPEG transport stream reader,the H.264 and AAC streams of the first program.
+
+use crate::media::{CodecConfig, TrackKind};
+use crate::mux::{AdtsReader, NalReader};
+use crate::ts::{self, PACKET_SIZE};
+use flv_parser::flv::avc::{self, AvcDecoderConfig, Sps};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::{self, Read},
+};
+
+pub const STREAM_TYPE_AAC: u8 = 0x0F;
+pub const STREAM_TYPE_AVC: u8 = 0x1B;
+const PAT_PID: u16 = 0;
+const NULL_PID: u16 = 0x1FFF;
+///Timestamps are 33 bits of 90kHz
+const TIMESTAMP_WRAP: i64 = 1 << 33;
+
+///A complete pes packet of an elementary stream
+#[derive(Debug, Clone)]
+pub struct Pes {
+    pub pid: u16,
+    pub stream_type: u8,
+    pub pts: Option<u64>,
+    pub dts: Option<u64>,
+    ///Payload without the pes header
+    pub data: Vec<u8>,
+}
+
+///Pes packet being reassembled
+#[derive(Debug, Default)]
+struct PesBuffer {
+    stream_type: u8,
+    continuity: Option<u8>,
+    ///Data since the last payload unit start,empty if waiting for one
+    data: Vec<u8>,
+}
+
+///Read a 33 bits pts or dts field
+fn timestamp(data: &[u8]) -> u64 {
+    ((data[0] as u64 >> 1) & 0x07) << 30
+        | (data[1] as u64) << 22
+        | (data[2] as u64 >> 1) << 15
+        | (data[3] as u64) << 7
+        | data[4] as u64 >> 1
+}
+
+///Difference of two 33 bits timestamps,assuming it is less than half the range
+fn timestamp_diff(a: u64, b: u64) -> i64 {
+    let diff = (a as i64 - b as i64).rem_euclid(TIMESTAMP_WRAP);
+    if diff >= TIMESTAMP_WRAP / 2 {
+        diff - TIMESTAMP_WRAP
+    } else {
+        diff
+    }
+}
+
+///Split packets into pes packets of the streams the pmt lists
+pub struct TsReader<R: Read> {
+    inner: R,
+    pmt_pid: Option<u16>,
+    ///Psi sections being reassembled by pid
+    sections: HashMap<u16, Vec<u8>>,
+    streams: BTreeMap<u16, PesBuffer>,
+    ready: VecDeque<Pes>,
+    eof: bool,
+    ///Bytes skipped to find the sync byte
+    pub skipped_bytes: u64,
+    ///Pes packets dropped because of lost packets
+    pub dropped_pes: u64,
+}
+
+impl<R: Read> TsReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            pmt_pid: None,
+            sections: HashMap::new(),
+            streams: BTreeMap::new(),
+            ready: VecDeque::new(),
+            eof: false,
+            skipped_bytes: 0,
+            dropped_pes: 0,
+        }
+    }
+
+    ///Pid and stream type of the streams of the pmt
+    pub fn streams(&self) -> Vec<(u16, u8)> {
+        self.streams
+            .iter()
+            .map(|(pid, stream)| (*pid, stream.stream_type))
+            .collect()
+    }
+
+    ///Read the next packet,resyncing on the sync byte.Returns false at the end of input.
+    fn read_packet(&mut self, packet: &mut [u8; PACKET_SIZE]) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        let mut filled = 0;
+        loop {
+            match self.inner.read(&mut packet[filled..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    self.skipped_bytes += filled as u64;
+                    return Ok(false);
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            if filled < PACKET_SIZE {
+                continue;
+            }
+            if packet[0] == 0x47 {
+                return Ok(true);
+            }
+            let start = packet
+                .iter()
+                .position(|b| *b == 0x47)
+                .unwrap_or(PACKET_SIZE);
+            packet.copy_within(start.., 0);
+            filled = PACKET_SIZE - start;
+            self.skipped_bytes += start as u64;
+        }
+    }
+
+    ///Next pes packet,None at the end of input
+    pub fn next_pes(&mut self) -> io::Result<Option<Pes>> {
+        let mut packet = [0_u8; PACKET_SIZE];
+        while self.ready.is_empty() {
+            if !self.read_packet(&mut packet)? {
+                let pids: Vec<u16> = self.streams.keys().copied().collect();
+                for pid in pids {
+                    self.finish_pes(pid);
+                }
+                break;
+            }
+            self.push_packet(&packet);
+        }
+        Ok(self.ready.pop_front())
+    }
+
+    fn push_packet(&mut self, packet: &[u8; PACKET_SIZE]) {
+        //transport error indicator
+        if packet[1] & 0x80 != 0 {
+            return;
+        }
+        let start = packet[1] & 0x40 != 0;
+        let pid = ((packet[1] as u16 & 0x1F) << 8) | packet[2] as u16;
+        let adaptation = packet[3] & 0x20 != 0;
+        let has_payload = packet[3] & 0x10 != 0;
+        let continuity = packet[3] & 0x0F;
+        let mut offset = 4;
+        if adaptation {
+            offset += 1 + packet[4] as usize;
+        }
+        if !has_payload || offset >= PACKET_SIZE || pid == NULL_PID {
+            return;
+        }
+        let payload = &packet[offset..];
+        if pid == PAT_PID || Some(pid) == self.pmt_pid {
+            self.push_section(pid, start, payload);
+            return;
+        }
+        let stream = match self.streams.get_mut(&pid) {
+            Some(stream) => stream,
+            None => return,
+        };
+        let expected = stream.continuity.map(|c| (c + 1) & 0x0F);
+        if stream.continuity == Some(continuity) {
+            //duplicate packet
+            return;
+        }
+        stream.continuity = Some(continuity);
+        if start {
+            self.finish_pes(pid);
+            let stream = self.streams.get_mut(&pid).unwrap();
+            stream.data.extend_from_slice(payload);
+        } else if !stream.data.is_empty() {
+            if expected.is_some() && expected != Some(continuity) {
+                stream.data.clear();
+                self.dropped_pes += 1;
+                return;
+            }
+            stream.data.extend_from_slice(payload);
+        } else {
+            return;
+        }
+        //finish a bounded pes packet as soon as it is complete
+        let data = &self.streams[&pid].data;
+        if data.len() >= 6 {
+            let length = ((data[4] as usize) << 8) | data[5] as usize;
+            if length > 0 && data.len() >= 6 + length {
+                self.finish_pes(pid);
+            }
+        }
+    }
+
+    ///Parse the buffered pes packet of pid
+    fn finish_pes(&mut self, pid: u16) {
+        let stream = match self.streams.get_mut(&pid) {
+            Some(stream) => stream,
+            None => return,
+        };
+        let data = std::mem::take(&mut stream.data);
+        if data.is_empty() {
+            return;
+        }
+        if data.len() < 9 || data[..3] != [0, 0, 1] {
+            self.dropped_pes += 1;
+            return;
+        }
+        let length = ((data[4] as usize) << 8) | data[5] as usize;
+        let end = if length > 0 {
+            (6 + length).min(data.len())
+        } else {
+            data.len()
+        };
+        let flags = data[7];
+        let header_end = 9 + data[8] as usize;
+        if header_end > end {
+            self.dropped_pes += 1;
+            return;
+        }
+        let pts = if flags & 0x80 != 0 && header_end >= 14 {
+            Some(timestamp(&data[9..14]))
+        } else {
+            None
+        };
+        let dts = if flags & 0xC0 == 0xC0 && header_end >= 19 {
+            Some(timestamp(&data[14..19]))
+        } else {
+            pts
+        };
+        self.ready.push_back(Pes {
+            pid,
+            stream_type: stream.stream_type,
+            pts,
+            dts,
+            data: data[header_end..end].to_vec(),
+        });
+    }
+
+    ///Collect psi sections,they may span several packets
+    fn push_section(&mut self, pid: u16, start: bool, payload: &[u8]) {
+        let buffer = self.sections.entry(pid).or_default();
+        if start {
+            let pointer = payload[0] as usize;
+            buffer.clear();
+            if let Some(section) = payload.get(1 + pointer..) {
+                buffer.extend_from_slice(section);
+            }
+        } else if !buffer.is_empty() {
+            buffer.extend_from_slice(payload);
+        }
+        if buffer.len() < 3 {
+            return;
+        }
+        let size = 3 + (((buffer[1] as usize) & 0x0F) << 8 | buffer[2] as usize);
+        if buffer.len() < size {
+            return;
+        }
+        let section = std::mem::take(buffer);
+        let section = &section[..size];
+        if size < 12 || ts::crc32(section) != 0 {
+            return;
+        }
+        let body = &section[8..size - 4];
+        match section[0] {
+            0x00 if pid == PAT_PID => {
+                self.pmt_pid = body
+                    .chunks_exact(4)
+                    .find(|entry| entry[0] != 0 || entry[1] != 0)
+                    .map(|entry| ((entry[2] as u16 & 0x1F) << 8) | entry[3] as u16);
+            }
+            0x02 => self.read_pmt(body),
+            _ => {}
+        }
+    }
+
+    fn read_pmt(&mut self, body: &[u8]) {
+        if body.len() < 4 {
+            return;
+        }
+        let info_length = ((body[2] as usize & 0x0F) << 8) | body[3] as usize;
+        let mut entries = body.get(4 + info_length..).unwrap_or(&[]);
+        while entries.len() >= 5 {
+            let stream_type = entries[0];
+            let pid = ((entries[1] as u16 & 0x1F) << 8) | entries[2] as u16;
+            let info_length = ((entries[3] as usize & 0x0F) << 8) | entries[4] as usize;
+            let stream = self.streams.entry(pid).or_default();
+            if stream.stream_type != stream_type {
+                *stream = PesBuffer {
+                    stream_type,
+                    ..Default::default()
+                };
+            }
+            entries = entries.get(5 + info_length..).unwrap_or(&[]);
+        }
+    }
+}
+
+///A coded frame with 90kHz timestamps,unwrapped to keep increasing
+#[derive(Debug, Clone)]
+pub struct TsFrame {
+    pub kind: TrackKind,
+    pub dts: i64,
+    pub pts: i64,
+    pub keyframe: bool,
+    ///Length prefixed nal units or a raw AAC frame
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TsEvent {
+    ///A new or changed decoder configuration
+    Config(TrackKind, CodecConfig),
+    Frame(TsFrame),
+}
+
+///Per stream state of the demuxer
+#[derive(Debug, Default)]
+struct StreamState {
+    pid: u16,
+    config: Option<CodecConfig>,
+    sps: BTreeMap<u32, Vec<u8>>,
+    pps: BTreeMap<u32, Vec<u8>>,
+    ///Last unwrapped timestamp and the raw value it came from
+    last_timestamp: Option<(i64, u64)>,
+    ///Expected pts of the next audio frame
+    next_audio: Option<i64>,
+    ///Waiting for a keyframe after the sequence header
+    started: bool,
+}
+
+impl StreamState {
+    ///Extend a 33 bits timestamp to continue from the previous one
+    fn unwrap(&mut self, raw: u64) -> i64 {
+        let value = match self.last_timestamp {
+            Some((last, last_raw)) => last + timestamp_diff(raw, last_raw),
+            None => raw as i64,
+        };
+        self.last_timestamp = Some((value, raw));
+        value
+    }
+}
+
+///Turn the H.264 and AAC pes packets of a transport stream into decoder configurations and frames.
+///Sequence headers are built from in-band sps/pps and ADTS headers.
+pub struct TsDemuxer<R: Read> {
+    reader: TsReader<R>,
+    video: StreamState,
+    audio: StreamState,
+    ///Pids already warned about
+    ignored: Vec<u16>,
+    events: VecDeque<TsEvent>,
+    ///Frames dropped before the first keyframe or sequence header
+    pub skipped_frames: u64,
+}
+
+impl<R: Read> TsDemuxer<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            reader: TsReader::new(input),
+            video: StreamState::default(),
+            audio: StreamState::default(),
+            ignored: vec![],
+            events: VecDeque::new(),
+            skipped_frames: 0,
+        }
+    }
+
+    pub fn reader(&self) -> &TsReader<R> {
+        &self.reader
+    }
+
+    ///Kinds of the streams that can be converted,known once the pmt is read
+    pub fn kinds(&self) -> Vec<TrackKind> {
+        let streams = self.reader.streams();
+        let mut kinds = vec![];
+        if streams.iter().any(|s| s.1 == STREAM_TYPE_AVC) {
+            kinds.push(TrackKind::Video);
+        }
+        if streams.iter().any(|s| s.1 == STREAM_TYPE_AAC) {
+            kinds.push(TrackKind::Audio);
+        }
+        kinds
+    }
+
+    ///Next event,None at the end of input
+    pub fn next_event(&mut self) -> io::Result<Option<TsEvent>> {
+        while self.events.is_empty() {
+            let pes = match self.reader.next_pes()? {
+                Some(pes) => pes,
+                None => break,
+            };
+            let (state, kind) = match pes.stream_type {
+                STREAM_TYPE_AVC => (&mut self.video, TrackKind::Video),
+                STREAM_TYPE_AAC => (&mut self.audio, TrackKind::Audio),
+                stream_type => {
+                    if !self.ignored.contains(&pes.pid) {
+                        self.ignored.push(pes.pid);
+                        eprintln!(
+                            "Warning: stream type 0x{:02X} of pid {} is not supported,skipped",
+                            stream_type, pes.pid
+                        );
+                    }
+                    continue;
+                }
+            };
+            if state.pid == 0 {
+                state.pid = pes.pid;
+            } else if state.pid != pes.pid {
+                if !self.ignored.contains(&pes.pid) {
+                    self.ignored.push(pes.pid);
+                    eprintln!(
+                        "Warning: {:?} stream of pid {} skipped,only the first one is kept",
+                        kind, pes.pid
+                    );
+                }
+                continue;
+            }
+            match kind {
+                TrackKind::Video => self.push_video(pes)?,
+                TrackKind::Audio => self.push_audio(pes)?,
+            }
+        }
+        Ok(self.events.pop_front())
+    }
+
+    ///A pes packet holds an access unit
+    fn push_video(&mut self, pes: Pes) -> io::Result<()> {
+        let state = &mut self.video;
+        let (pts, dts) = match (pes.pts, pes.dts) {
+            (Some(pts), Some(dts)) => (pts, dts),
+            _ => {
+                self.skipped_frames += 1;
+                return Ok(());
+            }
+        };
+        let offset = timestamp_diff(pts, dts);
+        let dts = state.unwrap(dts);
+        let pts = dts + offset;
+        let mut nals = NalReader::new(pes.data.as_slice());
+        let mut data = vec![];
+        let mut keyframe = false;
+        let mut changed = false;
+        while let Some(nal) = nals.next_nal()? {
+            if nal.is_empty() {
+                continue;
+            }
+            match nal[0] & 0x1F {
+                avc::NALU_TYPE_SPS => {
+                    if let Ok(sps) = Sps::from_nal(&nal) {
+                        changed |= state.sps.get(&sps.id()) != Some(&nal);
+                        state.sps.insert(sps.id(), nal);
+                    }
+                }
+                avc::NALU_TYPE_PPS => {
+                    if let Ok((pps_id, _)) = avc::pps_ids(&nal) {
+                        changed |= state.pps.get(&pps_id) != Some(&nal);
+                        state.pps.insert(pps_id, nal);
+                    }
+                }
+                //access unit delimiter,end of sequence/stream and filler data
+                avc::NALU_TYPE_AUD | 10..=12 => {}
+                nal_type => {
+                    keyframe |= nal_type == avc::NALU_TYPE_IDR;
+                    data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    data.extend_from_slice(&nal);
+                }
+            }
+        }
+        if changed && !state.sps.is_empty() && !state.pps.is_empty() {
+            let config = AvcDecoderConfig::new(
+                state.sps.values().cloned().collect(),
+                state.pps.values().cloned().collect(),
+            )
+            .ok()
+            .map(CodecConfig::Avc);
+            if let Some(config) = config.filter(|c| state.config.as_ref() != Some(c)) {
+                state.config = Some(config.clone());
+                state.started = false;
+                self.events
+                    .push_back(TsEvent::Config(TrackKind::Video, config));
+            }
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        state.started |= state.config.is_some() && keyframe;
+        if !state.started {
+            self.skipped_frames += 1;
+            return Ok(());
+        }
+        self.events.push_back(TsEvent::Frame(TsFrame {
+            kind: TrackKind::Video,
+            dts,
+            pts,
+            keyframe,
+            data,
+        }));
+        Ok(())
+    }
+
+    ///A pes packet holds one or more ADTS frames
+    fn push_audio(&mut self, pes: Pes) -> io::Result<()> {
+        let state = &mut self.audio;
+        let skipped_frames = &mut self.skipped_frames;
+        let mut pts = match pes.pts {
+            Some(pts) => state.unwrap(pts),
+            None => match state.next_audio {
+                Some(pts) => pts,
+                None => {
+                    *skipped_frames += 1;
+                    return Ok(());
+                }
+            },
+        };
+        let mut frames = AdtsReader::new(pes.data.as_slice());
+        //a damaged frame loses the rest of the packet
+        while let Some((config, data)) = frames.next_frame().unwrap_or_else(|_| {
+            *skipped_frames += 1;
+            None
+        }) {
+            let config = CodecConfig::Aac(config);
+            if state.config.as_ref() != Some(&config) {
+                state.config = Some(config.clone());
+                self.events
+                    .push_back(TsEvent::Config(TrackKind::Audio, config.clone()));
+            }
+            self.events.push_back(TsEvent::Frame(TsFrame {
+                kind: TrackKind::Audio,
+                dts: pts,
+                pts,
+                keyframe: true,
+                data,
+            }));
+            let (sample_rate, _) = config.audio_format().unwrap_or((0, 0));
+            if sample_rate > 0 {
+                pts += 1024 * 90_000 / sample_rate as i64;
+            }
+        }
+        if frames.truncated {
+            *skipped_frames += 1;
+        }
+        state.next_audio = Some(pts);
+        Ok(())
+    }
+}
@@ -1,13 +1,27 @@
+pub mod aac;
+pub mod avc;
+mod bits;
 mod header;
+pub mod hevc;
+mod interleave;
+pub mod mp3;
+mod reader;
 mod segment;
 mod tag;
+mod timestamp;
+mod writer;
 
 use byteorder::{BigEndian, ReadBytesExt};
 use std::io::{self, Read};
 
+pub use bits::unescape_rbsp;
 pub use header::Header;
+pub use interleave::{Interleaver, LateTag};
+pub use reader::Reader;
 pub use segment::Segment;
 pub use tag::{ScriptTagDataTrait, Tag, TagData};
+pub use timestamp::{Discontinuity, DiscontinuityKind, TimestampFilter, TimestampFilterOptions};
+pub use writer::Writer;
 
 use self::tag::be_bytes_to_u32;
 
@@ -20,14 +34,10 @@ impl Container {
     pub fn from_reader(reader: &mut impl Read) -> io::Result<Self> {
         let header = Header::from_reader(reader)?;
         let mut body = vec![];
-        loop {
-            if let Ok(seg) = Segment::from_reader(reader) {
-                body.push(seg);
-            } else {
-                break;
-            }
+        while let Ok(seg) = Segment::from_reader(reader) {
+            body.push(seg);
         }
-        return Ok(Self { header, body });
+        Ok(Self { header, body })
     }
 }
 
@@ -59,6 +69,12 @@ pub struct Parser {
     buffer: Vec<u8>,
 }
 
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Parser {
     ///Create a new praser
     pub fn new() -> Self {
@@ -70,15 +86,20 @@ impl Parser {
 
     ///Feed the parser with some data
     pub fn feed(&mut self, data: &[u8]) {
-        if data.len() > 0 {
+        if !data.is_empty() {
             self.buffer.extend_from_slice(data);
         }
     }
 
+    ///Count of bytes fed but not parsed yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
     ///Start parse the data in buffer,you need to call this util it
     ///returns a ParserResult::MoreDataRequired.
     /// #Example
-    ///```
+    ///```ignore
     ///loop {
     ///  parser.feed(&buf);
     ///  loop {
@@ -108,7 +129,7 @@ impl Parser {
                 let header = Header::from_reader(&mut &self.buffer[..required])?;
                 let _ = self.buffer.drain(..required);
                 self.state = ParserState::PreTagSize(std::mem::size_of::<u32>());
-                return Ok(ParseResult::Header(header));
+                Ok(ParseResult::Header(header))
             }
             ParserState::PreTagSize(required) => {
                 if required > self.buffer.len() {
@@ -117,7 +138,7 @@ impl Parser {
                 let pre_tag_size = (&mut &self.buffer[..required]).read_u32::<BigEndian>()?;
                 let _ = self.buffer.drain(..required);
                 self.state = ParserState::Tag(Tag::TAG_HEADER_SIZE as usize);
-                return Ok(ParseResult::PreTagSize(pre_tag_size));
+                Ok(ParseResult::PreTagSize(pre_tag_size))
             }
             ParserState::Tag(required) => {
                 if required > self.buffer.len() {
@@ -134,12 +155,12 @@ impl Parser {
                     let tag_size = required + data_size as usize;
                     if tag_size > self.buffer.len() {
                         self.state = ParserState::Tag(tag_size);
-                        return Ok(ParseResult::MoreDataRequired(tag_size - self.buffer.len()));
+                        Ok(ParseResult::MoreDataRequired(tag_size - self.buffer.len()))
                     } else {
-                        return self.parse_tag(tag_size);
+                        self.parse_tag(tag_size)
                     }
                 } else {
-                    return self.parse_tag(required);
+                    self.parse_tag(required)
                 }
             }
         }
@@ -150,6 +171,6 @@ impl Parser {
         let tag = Tag::from_reader(&mut &self.buffer[..required])?;
         let _ = self.buffer.drain(..required);
         self.state = ParserState::PreTagSize(std::mem::size_of::<u32>());
-        return Ok(ParseResult::Tag(tag));
+        Ok(ParseResult::Tag(tag))
     }
 }
@@ -0,0 +1,215 @@
+use super::bits::BitReader;
+use std::io;
+
+pub const SAMPLING_FREQUENCIES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+///AudioSpecificConfig carried by the AAC sequence header
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioSpecificConfig {
+    object_type: u8,
+    sampling_frequency_index: u8,
+    sampling_frequency: u32,
+    channel_configuration: u8,
+    bytes: Vec<u8>,
+}
+
+impl AudioSpecificConfig {
+    ///Build a two bytes config from its fields
+    pub fn new(object_type: u8, sampling_frequency_index: u8, channel_configuration: u8) -> Self {
+        let bytes = vec![
+            (object_type << 3) | (sampling_frequency_index >> 1),
+            (sampling_frequency_index << 7) | (channel_configuration << 3),
+        ];
+        Self {
+            object_type,
+            sampling_frequency_index,
+            sampling_frequency: SAMPLING_FREQUENCIES
+                .get(sampling_frequency_index as usize)
+                .copied()
+                .unwrap_or(0),
+            channel_configuration,
+            bytes,
+        }
+    }
+
+    ///Parse the config from the body of an AAC sequence header
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let mut reader = BitReader::new(data);
+        let mut object_type = reader.read_bits(5)? as u8;
+        if object_type == 31 {
+            object_type = 32 + reader.read_bits(6)? as u8;
+        }
+        let sampling_frequency_index = reader.read_bits(4)? as u8;
+        let sampling_frequency = if sampling_frequency_index == 0x0F {
+            reader.read_bits(24)?
+        } else {
+            *SAMPLING_FREQUENCIES
+                .get(sampling_frequency_index as usize)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Unexcepted sampling frequency index:{}",
+                            sampling_frequency_index
+                        ),
+                    )
+                })?
+        };
+        let channel_configuration = reader.read_bits(4)? as u8;
+        Ok(Self {
+            object_type,
+            sampling_frequency_index,
+            sampling_frequency,
+            channel_configuration,
+            bytes: data.to_vec(),
+        })
+    }
+
+    ///Audio object type,2 for AAC LC
+    pub fn object_type(&self) -> u8 {
+        self.object_type
+    }
+
+    pub fn sampling_frequency_index(&self) -> u8 {
+        self.sampling_frequency_index
+    }
+
+    pub fn sampling_frequency(&self) -> u32 {
+        self.sampling_frequency
+    }
+
+    pub fn channel_configuration(&self) -> u8 {
+        self.channel_configuration
+    }
+
+    ///Count of channels,0 means defined in program config element
+    pub fn channels(&self) -> u8 {
+        match self.channel_configuration {
+            7 => 8,
+            c => c,
+        }
+    }
+
+    ///Return the raw bytes of this config
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    ///Build the 7 bytes ADTS header of a raw frame of payload_len bytes.
+    ///HE-AAC is signaled as AAC LC at the core rate,decoders detect SBR implicitly.
+    ///ADTS has no explicit sampling frequency,one without an index can't be written.
+    pub fn adts_header(&self, payload_len: usize) -> io::Result<[u8; 7]> {
+        let profile = match self.object_type {
+            1..=4 => self.object_type - 1,
+            _ => 1,
+        };
+        let frequency_index = match self.sampling_frequency_index {
+            0x0F => SAMPLING_FREQUENCIES
+                .iter()
+                .position(|f| *f == self.sampling_frequency)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Sampling frequency {} can't be written in adts",
+                            self.sampling_frequency
+                        ),
+                    )
+                })? as u8,
+            index => index,
+        };
+        let len = payload_len + 7;
+        if len > 0x1FFF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Aac frame of {} bytes is too long for adts", payload_len),
+            ));
+        }
+        Ok([
+            0xFF,
+            0xF1,
+            (profile << 6) | (frequency_index << 2) | ((self.channel_configuration >> 2) & 0x01),
+            ((self.channel_configuration & 0x03) << 6) | ((len >> 11) & 0x03) as u8,
+            ((len >> 3) & 0xFF) as u8,
+            (((len & 0x07) << 5) | 0x1F) as u8,
+            0xFC,
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_an_aac_lc_config() {
+        let config = AudioSpecificConfig::from_bytes(&[0x12, 0x10]).unwrap();
+        assert_eq!(config.object_type(), 2);
+        assert_eq!(config.sampling_frequency_index(), 4);
+        assert_eq!(config.sampling_frequency(), 44100);
+        assert_eq!(config.channels(), 2);
+        assert_eq!(config, AudioSpecificConfig::new(2, 4, 2));
+    }
+
+    #[test]
+    fn parses_escaped_object_types_and_explicit_frequencies() {
+        //object type 31+(42-32),explicit 44100,channel configuration 7
+        let config =
+            AudioSpecificConfig::from_bytes(&[0xF9, 0x5E, 0x01, 0x58, 0x88, 0xE0]).unwrap();
+        assert_eq!(config.object_type(), 42);
+        assert_eq!(config.sampling_frequency_index(), 0x0F);
+        assert_eq!(config.sampling_frequency(), 44100);
+        assert_eq!(config.channel_configuration(), 7);
+        assert_eq!(config.channels(), 8);
+    }
+
+    #[test]
+    fn rejects_reserved_frequency_indexes() {
+        let error = AudioSpecificConfig::from_bytes(&[0x16, 0x90]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(AudioSpecificConfig::from_bytes(&[0x12]).is_err());
+    }
+
+    #[test]
+    fn builds_adts_headers() {
+        let config = AudioSpecificConfig::new(2, 4, 2);
+        assert_eq!(
+            config.adts_header(100).unwrap(),
+            [0xFF, 0xF1, 0x50, 0x80, 0x0D, 0x7F, 0xFC]
+        );
+        //the frame length has 13 bits,split over three bytes
+        let header = AudioSpecificConfig::new(2, 3, 1)
+            .adts_header(0x1FFF - 7)
+            .unwrap();
+        assert_eq!(header, [0xFF, 0xF1, 0x4C, 0x43, 0xFF, 0xFF, 0xFC]);
+        //HE-AAC is written as AAC LC
+        let header = AudioSpecificConfig::from_bytes(&[0x2B, 0x92, 0x08, 0x00])
+            .unwrap()
+            .adts_header(0)
+            .unwrap();
+        assert_eq!(header[2] >> 6, 1);
+    }
+
+    #[test]
+    fn writes_explicit_frequencies_as_their_index() {
+        let config = AudioSpecificConfig::from_bytes(&[0x17, 0x80, 0x56, 0x22, 0x10]).unwrap();
+        assert_eq!(config.sampling_frequency_index(), 0x0F);
+        assert_eq!(
+            config.adts_header(100).unwrap(),
+            AudioSpecificConfig::new(2, 4, 2).adts_header(100).unwrap()
+        );
+        //12345Hz has no index
+        let config = AudioSpecificConfig::from_bytes(&[0x17, 0x80, 0x18, 0x1C, 0x90]).unwrap();
+        let error = config.adts_header(100).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_frames_too_long_for_adts() {
+        let config = AudioSpecificConfig::new(2, 4, 2);
+        assert!(config.adts_header(0x1FFF - 7).is_ok());
+        assert!(config.adts_header(0x1FFF - 6).is_err());
+    }
+}
@@ -0,0 +1,597 @@
+use super::bits::{unescape_rbsp, BitReader};
+use std::io;
+
+pub const NALU_TYPE_IDR: u8 = 5;
+pub const NALU_TYPE_SEI: u8 = 6;
+pub const NALU_TYPE_SPS: u8 = 7;
+pub const NALU_TYPE_PPS: u8 = 8;
+pub const NALU_TYPE_AUD: u8 = 9;
+
+///AVCDecoderConfigurationRecord carried by the AVC sequence header
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvcDecoderConfig {
+    profile_indication: u8,
+    profile_compatibility: u8,
+    level_indication: u8,
+    length_size_minus_one: u8,
+    sps: Vec<Vec<u8>>,
+    pps: Vec<Vec<u8>>,
+    extension: Vec<u8>,
+}
+
+impl AvcDecoderConfig {
+    ///Build a config from the sps and pps nal units, with 4 bytes nalu length
+    pub fn new(sps: Vec<Vec<u8>>, pps: Vec<Vec<u8>>) -> io::Result<Self> {
+        let first = sps
+            .first()
+            .filter(|s| s.len() >= 4)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing or short sps"))?;
+        Ok(Self {
+            profile_indication: first[1],
+            profile_compatibility: first[2],
+            level_indication: first[3],
+            length_size_minus_one: 3,
+            sps,
+            pps,
+            extension: vec![],
+        })
+    }
+
+    ///Parse the record from the body of an AVC sequence header
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        if data.len() < 7 || data[0] != 1 {
+            return Err(truncated());
+        }
+        let mut offset = 6;
+        let sps = read_parameter_sets(data, &mut offset, (data[5] & 0x1F) as usize)?;
+        let pps_count = *data.get(offset).ok_or_else(truncated)? as usize;
+        offset += 1;
+        let pps = read_parameter_sets(data, &mut offset, pps_count)?;
+        Ok(Self {
+            profile_indication: data[1],
+            profile_compatibility: data[2],
+            level_indication: data[3],
+            length_size_minus_one: data[4] & 0x03,
+            sps,
+            pps,
+            extension: data[offset..].to_vec(),
+        })
+    }
+
+    ///Return the bytes of this record
+    pub fn into_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![
+            0x01,
+            self.profile_indication,
+            self.profile_compatibility,
+            self.level_indication,
+            0xFC | self.length_size_minus_one,
+            0xE0 | self.sps.len() as u8,
+        ];
+        for sps in &self.sps {
+            bytes.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(sps);
+        }
+        bytes.push(self.pps.len() as u8);
+        for pps in &self.pps {
+            bytes.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(pps);
+        }
+        bytes.extend_from_slice(&self.extension);
+        bytes
+    }
+
+    pub fn profile(&self) -> u8 {
+        self.profile_indication
+    }
+
+    pub fn compatibility(&self) -> u8 {
+        self.profile_compatibility
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level_indication
+    }
+
+    ///The byte count of the length prefix of every nal unit
+    pub fn nalu_length_size(&self) -> usize {
+        self.length_size_minus_one as usize + 1
+    }
+
+    pub fn sps(&self) -> &[Vec<u8>] {
+        &self.sps
+    }
+
+    pub fn pps(&self) -> &[Vec<u8>] {
+        &self.pps
+    }
+
+    ///Decode the first sps of this record
+    pub fn parse_sps(&self) -> io::Result<Sps> {
+        let sps = self
+            .sps
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No sps in avc config"))?;
+        Sps::from_nal(sps)
+    }
+}
+
+///The fields of a h.264 sequence parameter set flv-cli cares about
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sps {
+    profile_idc: u8,
+    level_idc: u8,
+    id: u32,
+    chroma_format_idc: u32,
+    separate_colour_plane: bool,
+    bit_depth: u32,
+    log2_max_frame_num: u32,
+    pic_order_cnt_type: u32,
+    log2_max_pic_order_cnt_lsb: u32,
+    frame_mbs_only: bool,
+    width: u32,
+    height: u32,
+    frame_rate: Option<f64>,
+}
+
+impl Sps {
+    ///Parse a sps nal unit,including the one byte nal header
+    pub fn from_nal(nal: &[u8]) -> io::Result<Self> {
+        let rbsp = unescape_rbsp(nal);
+        if rbsp.len() < 4 || rbsp[0] & 0x1F != NALU_TYPE_SPS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a sps"));
+        }
+        let mut reader = BitReader::new(&rbsp[1..]);
+        let profile_idc = reader.read_bits(8)? as u8;
+        reader.skip_bits(8)?;
+        let level_idc = reader.read_bits(8)? as u8;
+        let id = reader.read_ue()?;
+        let mut chroma_format_idc = 1;
+        let mut separate_colour_plane = false;
+        let mut bit_depth = 8;
+        if [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135].contains(&profile_idc) {
+            chroma_format_idc = reader.read_ue_up_to(3)?;
+            if chroma_format_idc == 3 {
+                separate_colour_plane = reader.read_bit()?;
+            }
+            bit_depth = reader.read_ue_up_to(6)? + 8;
+            reader.read_ue()?;
+            reader.skip_bits(1)?;
+            if reader.read_bit()? {
+                let count = if chroma_format_idc != 3 { 8 } else { 12 };
+                for i in 0..count {
+                    if reader.read_bit()? {
+                        skip_scaling_list(&mut reader, if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+        //the spec caps both log2 values at 16
+        let log2_max_frame_num = reader.read_ue_up_to(12)? + 4;
+        let pic_order_cnt_type = reader.read_ue()?;
+        let mut log2_max_pic_order_cnt_lsb = 0;
+        match pic_order_cnt_type {
+            0 => {
+                log2_max_pic_order_cnt_lsb = reader.read_ue_up_to(12)? + 4;
+            }
+            1 => {
+                reader.skip_bits(1)?;
+                reader.read_se()?;
+                reader.read_se()?;
+                for _ in 0..reader.read_ue()? {
+                    reader.read_se()?;
+                }
+            }
+            _ => {}
+        }
+        reader.read_ue()?;
+        reader.skip_bits(1)?;
+        let width_in_mbs = reader.read_ue()? + 1;
+        let height_in_map_units = reader.read_ue()? + 1;
+        let frame_mbs_only = reader.read_bit()? as u32;
+        if frame_mbs_only == 0 {
+            reader.skip_bits(1)?;
+        }
+        reader.skip_bits(1)?;
+        let mut crop = [0_u32; 4];
+        if reader.read_bit()? {
+            for value in crop.iter_mut() {
+                *value = reader.read_ue()?;
+            }
+        }
+        let (crop_unit_x, crop_unit_y) = match chroma_format_idc {
+            0 => (1, 2 - frame_mbs_only),
+            1 => (2, 2 * (2 - frame_mbs_only)),
+            2 => (2, 2 - frame_mbs_only),
+            _ => (1, 2 - frame_mbs_only),
+        };
+        let width = cropped_size(width_in_mbs, 16, crop_unit_x, crop[0], crop[1])?;
+        let height = cropped_size(
+            height_in_map_units,
+            (2 - frame_mbs_only) * 16,
+            crop_unit_y,
+            crop[2],
+            crop[3],
+        )?;
+        let frame_rate = if reader.read_bit().unwrap_or(false) {
+            read_vui_frame_rate(&mut reader).ok().flatten()
+        } else {
+            None
+        };
+        Ok(Self {
+            profile_idc,
+            level_idc,
+            id,
+            chroma_format_idc,
+            separate_colour_plane,
+            bit_depth,
+            log2_max_frame_num,
+            pic_order_cnt_type,
+            log2_max_pic_order_cnt_lsb,
+            frame_mbs_only: frame_mbs_only == 1,
+            width,
+            height,
+            frame_rate,
+        })
+    }
+
+    pub fn profile(&self) -> u8 {
+        self.profile_idc
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level_idc
+    }
+
+    ///seq_parameter_set_id
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn chroma_format(&self) -> u32 {
+        self.chroma_format_idc
+    }
+
+    pub fn bit_depth(&self) -> u32 {
+        self.bit_depth
+    }
+
+    ///0 and 1 code the picture order in slice headers,2 means output order is decode order
+    pub fn pic_order_cnt_type(&self) -> u32 {
+        self.pic_order_cnt_type
+    }
+
+    ///Maximum value of pic_order_cnt_lsb plus one
+    pub fn max_pic_order_cnt_lsb(&self) -> u32 {
+        1 << self.log2_max_pic_order_cnt_lsb
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    ///Frame rate from the vui timing info,if present
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.frame_rate
+    }
+}
+
+///Return pic_parameter_set_id and seq_parameter_set_id of a pps nal unit
+pub fn pps_ids(nal: &[u8]) -> io::Result<(u32, u32)> {
+    let rbsp = unescape_rbsp(nal);
+    if rbsp.len() < 2 || rbsp[0] & 0x1F != NALU_TYPE_PPS {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a pps"));
+    }
+    let mut reader = BitReader::new(&rbsp[1..]);
+    Ok((reader.read_ue()?, reader.read_ue()?))
+}
+
+///The leading fields of a slice header,up to pic_order_cnt_lsb
+#[derive(Debug, Clone, PartialEq)]
+pub struct SliceHeader {
+    first_mb_in_slice: u32,
+    slice_type: u32,
+    pps_id: u32,
+    frame_num: Option<u32>,
+    field_pic: bool,
+    pic_order_cnt_lsb: Option<u32>,
+}
+
+impl SliceHeader {
+    ///Parse the header of a coded slice nal unit.The sps is looked up by pps id,
+    ///fields after pic_parameter_set_id are None when it is unknown.
+    pub fn from_nal<'a>(
+        nal: &[u8],
+        sps_of_pps: impl FnOnce(u32) -> Option<&'a Sps>,
+    ) -> io::Result<Self> {
+        //the header is short,only unescape its start
+        let rbsp = unescape_rbsp(&nal[..nal.len().min(64)]);
+        let nal_type = rbsp.first().map(|b| b & 0x1F).unwrap_or(0);
+        if nal_type != 1 && nal_type != NALU_TYPE_IDR {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a slice"));
+        }
+        let mut reader = BitReader::new(&rbsp[1..]);
+        let mut header = Self {
+            first_mb_in_slice: reader.read_ue()?,
+            slice_type: reader.read_ue()?,
+            pps_id: reader.read_ue()?,
+            frame_num: None,
+            field_pic: false,
+            pic_order_cnt_lsb: None,
+        };
+        let sps = match sps_of_pps(header.pps_id) {
+            Some(sps) => sps,
+            None => return Ok(header),
+        };
+        if sps.separate_colour_plane {
+            reader.skip_bits(2)?;
+        }
+        header.frame_num = Some(reader.read_bits(sps.log2_max_frame_num)?);
+        if !sps.frame_mbs_only {
+            header.field_pic = reader.read_bit()?;
+            if header.field_pic {
+                reader.skip_bits(1)?;
+            }
+        }
+        if nal_type == NALU_TYPE_IDR {
+            reader.read_ue()?;
+        }
+        if sps.pic_order_cnt_type == 0 {
+            header.pic_order_cnt_lsb = Some(reader.read_bits(sps.log2_max_pic_order_cnt_lsb)?);
+        }
+        Ok(header)
+    }
+
+    pub fn first_mb_in_slice(&self) -> u32 {
+        self.first_mb_in_slice
+    }
+
+    ///Slice type,values above 4 mean all slices of the picture have this type
+    pub fn slice_type(&self) -> u32 {
+        self.slice_type
+    }
+
+    pub fn pps_id(&self) -> u32 {
+        self.pps_id
+    }
+
+    pub fn frame_num(&self) -> Option<u32> {
+        self.frame_num
+    }
+
+    ///Indicates that the slice belongs to a field instead of a frame
+    pub fn field_pic(&self) -> bool {
+        self.field_pic
+    }
+
+    pub fn pic_order_cnt_lsb(&self) -> Option<u32> {
+        self.pic_order_cnt_lsb
+    }
+}
+
+///Size in pixels of units less the cropped ones at both ends,
+///values of a corrupt sps that overflow are invalid data
+pub(crate) fn cropped_size(
+    units: u32,
+    unit_size: u32,
+    crop_unit: u32,
+    crop_start: u32,
+    crop_end: u32,
+) -> io::Result<u32> {
+    let size = units.checked_mul(unit_size);
+    let crop = crop_start
+        .checked_add(crop_end)
+        .and_then(|crop| crop.checked_mul(crop_unit));
+    match (size, crop) {
+        (Some(size), Some(crop)) => Ok(size.saturating_sub(crop)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Picture size out of range",
+        )),
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Truncated decoder config")
+}
+
+///Read `count` parameter sets,each prefixed by a 16 bits length
+pub(crate) fn read_parameter_sets(
+    data: &[u8],
+    offset: &mut usize,
+    count: usize,
+) -> io::Result<Vec<Vec<u8>>> {
+    let mut units = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len = data.get(*offset..*offset + 2).ok_or_else(truncated)?;
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        *offset += 2;
+        units.push(
+            data.get(*offset..*offset + len)
+                .ok_or_else(truncated)?
+                .to_vec(),
+        );
+        *offset += len;
+    }
+    Ok(units)
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> io::Result<()> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta_scale = reader.read_se()?;
+            if !(-128..=127).contains(&delta_scale) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Scaling list delta out of range",
+                ));
+            }
+            next_scale = (last_scale + delta_scale + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Ok(())
+}
+
+fn read_vui_frame_rate(reader: &mut BitReader) -> io::Result<Option<f64>> {
+    if reader.read_bit()? && reader.read_bits(8)? == 255 {
+        reader.skip_bits(32)?;
+    }
+    if reader.read_bit()? {
+        reader.skip_bits(1)?;
+    }
+    if reader.read_bit()? {
+        reader.skip_bits(4)?;
+        if reader.read_bit()? {
+            reader.skip_bits(24)?;
+        }
+    }
+    if reader.read_bit()? {
+        reader.read_ue()?;
+        reader.read_ue()?;
+    }
+    if reader.read_bit()? {
+        let num_units_in_tick = reader.read_bits(32)?;
+        let time_scale = reader.read_bits(32)?;
+        if num_units_in_tick > 0 {
+            return Ok(Some(time_scale as f64 / (2.0 * num_units_in_tick as f64)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flv::bits::tests::BitWriter;
+
+    ///High profile 4:2:0 sps with pic_order_cnt_type 0,only cropping at the bottom
+    fn sps(
+        width_in_mbs_minus1: u32,
+        height_in_map_units_minus1: u32,
+        crop_bottom: u32,
+        log2_max_pic_order_cnt_lsb_minus4: u32,
+    ) -> Vec<u8> {
+        let mut writer = BitWriter::default()
+            .bits(100, 8) //profile_idc
+            .bits(0, 8)
+            .bits(40, 8) //level_idc
+            .ue(0) //seq_parameter_set_id
+            .ue(1) //chroma_format_idc
+            .ue(0) //bit_depth_luma_minus8
+            .ue(0) //bit_depth_chroma_minus8
+            .bit(false)
+            .bit(false) //seq_scaling_matrix_present_flag
+            .ue(0) //log2_max_frame_num_minus4
+            .ue(0) //pic_order_cnt_type
+            .ue(log2_max_pic_order_cnt_lsb_minus4)
+            .ue(4) //max_num_ref_frames
+            .bit(false)
+            .ue(width_in_mbs_minus1)
+            .ue(height_in_map_units_minus1)
+            .bit(true) //frame_mbs_only_flag
+            .bit(true)
+            .bit(crop_bottom > 0);
+        if crop_bottom > 0 {
+            writer = writer.ue(0).ue(0).ue(0).ue(crop_bottom);
+        }
+        let mut nal = vec![0x67];
+        nal.extend(writer.bit(false).into_nal_payload());
+        nal
+    }
+
+    #[test]
+    fn parses_a_cropped_1080p_sps() {
+        let sps = Sps::from_nal(&sps(119, 67, 4, 4)).unwrap();
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert_eq!((sps.profile(), sps.level()), (100, 40));
+        assert_eq!(sps.max_pic_order_cnt_lsb(), 256);
+        assert_eq!(sps.frame_rate(), None);
+    }
+
+    #[test]
+    fn rejects_a_picture_size_that_overflows() {
+        let error = Sps::from_nal(&sps(1 << 28, 67, 0, 4)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = Sps::from_nal(&sps(119, 67, u32::MAX - 1, 4)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_log2_values_above_16() {
+        assert!(Sps::from_nal(&sps(119, 67, 4, 12)).is_ok());
+        let error = Sps::from_nal(&sps(119, 67, 4, 13)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = Sps::from_nal(&sps(119, 67, 4, 28)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn decoder_config_round_trips() {
+        let pps = vec![0x68, 0xEB, 0xE3, 0xCB, 0x22, 0xC0];
+        let config = AvcDecoderConfig::new(vec![sps(119, 67, 4, 4)], vec![pps.clone()]).unwrap();
+        assert_eq!((config.profile(), config.level()), (100, 40));
+        assert_eq!(config.nalu_length_size(), 4);
+        let bytes = config.into_bytes();
+        assert_eq!(&bytes[..6], [0x01, 100, 0, 40, 0xFF, 0xE1]);
+        let parsed = AvcDecoderConfig::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, config);
+        assert_eq!(parsed.pps(), [pps]);
+        assert_eq!(parsed.parse_sps().unwrap().width(), 1920);
+    }
+
+    #[test]
+    fn decoder_config_keeps_length_size_and_extension() {
+        let sps = sps(119, 67, 4, 4);
+        let mut bytes = vec![0x01, 100, 0, 40, 0xFD, 0xE1];
+        bytes.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&sps);
+        bytes.extend_from_slice(&[0x01, 0x00, 0x02, 0x68, 0xEB]);
+        //chroma_format and bit depths of high profiles
+        bytes.extend_from_slice(&[0xFD, 0xF8, 0xF8, 0x00]);
+        let config = AvcDecoderConfig::from_bytes(&bytes).unwrap();
+        assert_eq!(config.nalu_length_size(), 2);
+        assert_eq!(config.into_bytes(), bytes);
+    }
+
+    #[test]
+    fn rejects_a_truncated_decoder_config() {
+        let bytes = AvcDecoderConfig::new(vec![sps(119, 67, 4, 4)], vec![vec![0x68, 0xEB]])
+            .unwrap()
+            .into_bytes();
+        for len in [0, 6, 10, bytes.len() - 1] {
+            let error = AvcDecoderConfig::from_bytes(&bytes[..len]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn reads_the_slice_header_with_its_sps() {
+        let sps = Sps::from_nal(&sps(119, 67, 4, 4)).unwrap();
+        let mut nal = vec![0x65];
+        nal.extend(
+            BitWriter::default()
+                .ue(0) //first_mb_in_slice
+                .ue(7) //slice_type,I
+                .ue(0) //pic_parameter_set_id
+                .bits(5, 4) //frame_num
+                .ue(0) //idr_pic_id
+                .bits(200, 8) //pic_order_cnt_lsb
+                .into_nal_payload(),
+        );
+        let header = SliceHeader::from_nal(&nal, |_| Some(&sps)).unwrap();
+        assert_eq!((header.slice_type(), header.pps_id()), (7, 0));
+        assert_eq!(header.frame_num(), Some(5));
+        assert_eq!(header.pic_order_cnt_lsb(), Some(200));
+        let header = SliceHeader::from_nal(&nal, |_| None).unwrap();
+        assert_eq!(header.pic_order_cnt_lsb(), None);
+    }
+}
@@ -0,0 +1,238 @@
+use std::io;
+
+///Msb-first bit reader used for parsing codec parameter sets
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    ///Read a single bit
+    pub fn read_bit(&mut self) -> io::Result<bool> {
+        let byte = self
+            .data
+            .get(self.position / 8)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Bit stream exhausted"))?;
+        let bit = (byte >> (7 - (self.position % 8))) & 0x01;
+        self.position += 1;
+        Ok(bit == 1)
+    }
+
+    ///Read up to 32 bits as an unsigned integer
+    pub fn read_bits(&mut self, count: u32) -> io::Result<u32> {
+        let mut result = 0_u32;
+        for _ in 0..count {
+            result = (result << 1) | self.read_bit()? as u32;
+        }
+        Ok(result)
+    }
+
+    ///Skip some bits
+    pub fn skip_bits(&mut self, count: usize) -> io::Result<()> {
+        if self.position + count > self.data.len() * 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Bit stream exhausted",
+            ));
+        }
+        self.position += count;
+        Ok(())
+    }
+
+    ///Read an unsigned Exp-Golomb code
+    pub fn read_ue(&mut self) -> io::Result<u32> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Exp-Golomb code too long",
+                ));
+            }
+        }
+        Ok((1_u32 << leading_zeros) - 1 + self.read_bits(leading_zeros)?)
+    }
+
+    ///Read an unsigned Exp-Golomb code,values above max are invalid data
+    pub fn read_ue_up_to(&mut self, max: u32) -> io::Result<u32> {
+        let value = self.read_ue()?;
+        if value > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Exp-Golomb value {} above {}", value, max),
+            ));
+        }
+        Ok(value)
+    }
+
+    ///Read a signed Exp-Golomb code
+    pub fn read_se(&mut self) -> io::Result<i32> {
+        let value = self.read_ue()?;
+        if value % 2 == 1 {
+            Ok(((value / 2) + 1) as i32)
+        } else {
+            Ok(-((value / 2) as i32))
+        }
+    }
+}
+
+///Strip the emulation prevention bytes (0x000003) from a NAL unit payload
+pub fn unescape_rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for byte in nal {
+        if zeros >= 2 && *byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        if *byte == 0x00 {
+            zeros += 1;
+        } else {
+            zeros = 0;
+        }
+        result.push(*byte);
+    }
+    result
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    ///Msb-first bit writer building parameter sets for tests
+    #[derive(Default)]
+    pub(crate) struct BitWriter {
+        bits: Vec<bool>,
+    }
+
+    impl BitWriter {
+        pub fn bits(mut self, value: u64, count: u32) -> Self {
+            for i in (0..count).rev() {
+                self.bits.push((value >> i) & 0x01 == 1);
+            }
+            self
+        }
+
+        pub fn bit(self, value: bool) -> Self {
+            self.bits(value as u64, 1)
+        }
+
+        pub fn ue(self, value: u32) -> Self {
+            let code = value as u64 + 1;
+            let len = 64 - code.leading_zeros();
+            self.bits(0, len - 1).bits(code, len)
+        }
+
+        pub fn se(self, value: i32) -> Self {
+            let code = if value > 0 {
+                2 * value as i64 - 1
+            } else {
+                -2 * value as i64
+            };
+            self.ue(code as u32)
+        }
+
+        ///The bits followed by the rbsp stop bit and zero padding
+        pub fn into_rbsp(self) -> Vec<u8> {
+            let mut bits = self.bit(true).bits;
+            while !bits.len().is_multiple_of(8) {
+                bits.push(false);
+            }
+            bits.chunks(8)
+                .map(|byte| byte.iter().fold(0, |acc, bit| acc << 1 | *bit as u8))
+                .collect()
+        }
+
+        ///The rbsp with emulation prevention bytes,as it is sent in a nal unit
+        pub fn into_nal_payload(self) -> Vec<u8> {
+            let mut payload = vec![];
+            let mut zeros = 0;
+            for byte in self.into_rbsp() {
+                if zeros >= 2 && byte <= 0x03 {
+                    payload.push(0x03);
+                    zeros = 0;
+                }
+                zeros = if byte == 0x00 { zeros + 1 } else { 0 };
+                payload.push(byte);
+            }
+            payload
+        }
+    }
+
+    #[test]
+    fn rejects_exp_golomb_values_above_max() {
+        let data = BitWriter::default().ue(12).ue(13).into_rbsp();
+        let mut reader = BitReader::new(&data);
+        assert_eq!(reader.read_ue_up_to(12).unwrap(), 12);
+        let error = reader.read_ue_up_to(12).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_exp_golomb_codes() {
+        let data = BitWriter::default()
+            .ue(0)
+            .ue(1)
+            .ue(2)
+            .ue(254)
+            .se(0)
+            .se(1)
+            .se(-1)
+            .se(-100)
+            .into_rbsp();
+        let mut reader = BitReader::new(&data);
+        for expected in [0, 1, 2, 254] {
+            assert_eq!(reader.read_ue().unwrap(), expected);
+        }
+        for expected in [0, 1, -1, -100] {
+            assert_eq!(reader.read_se().unwrap(), expected);
+        }
+        assert!(reader.read_bit().unwrap());
+    }
+
+    #[test]
+    fn reads_the_largest_exp_golomb_code() {
+        let data = BitWriter::default().ue(u32::MAX - 1).into_rbsp();
+        assert_eq!(BitReader::new(&data).read_ue().unwrap(), u32::MAX - 1);
+        //33 leading zeros don't fit 32 bits
+        let data = BitWriter::default().bits(0, 33).bit(true).into_rbsp();
+        let error = BitReader::new(&data).read_ue().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn stops_at_the_end_of_data() {
+        let mut reader = BitReader::new(&[0x00]);
+        let error = reader.read_ue().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(BitReader::new(&[0xFF]).skip_bits(9).is_err());
+    }
+
+    #[test]
+    fn unescapes_emulation_prevention_bytes() {
+        assert_eq!(
+            unescape_rbsp(&[0x67, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x03]),
+            [0x67, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03]
+        );
+        //a 0x03 after a single zero is data
+        assert_eq!(unescape_rbsp(&[0x00, 0x03, 0x00]), [0x00, 0x03, 0x00]);
+    }
+
+    #[test]
+    fn unescapes_what_the_writer_escaped() {
+        let writer = || {
+            BitWriter::default()
+                .bits(0, 40)
+                .ue(u32::MAX - 1)
+                .bits(0, 24)
+        };
+        let payload = writer().into_nal_payload();
+        assert_ne!(payload, writer().into_rbsp());
+        assert_eq!(unescape_rbsp(&payload), writer().into_rbsp());
+    }
+}
@@ -16,6 +16,23 @@ impl Header {
     const HEADER_VIDEO_FLAG: u8 = 0b00000001;
     const HEADER_AUDIO_FLAG: u8 = 0b00000100;
 
+    ///Create a version 1 header with the given stream flags
+    pub fn new(has_video: bool, has_audio: bool) -> Self {
+        let mut flags = 0x00;
+        if has_video {
+            flags |= Self::HEADER_VIDEO_FLAG;
+        }
+        if has_audio {
+            flags |= Self::HEADER_AUDIO_FLAG;
+        }
+        Header {
+            signature: Self::HEADER_SIGNATURE,
+            version: 0x01,
+            flags,
+            header_size: Self::HEADER_SIZE,
+        }
+    }
+
     ///Build a Header from something implements Read trait
     pub fn from_reader(reader: &mut impl Read) -> io::Result<Self> {
         let mut header = Header {
@@ -65,25 +82,27 @@ impl Header {
         self.flags & Self::HEADER_AUDIO_FLAG == Self::HEADER_AUDIO_FLAG
     }
 
-    ///Set video bit of flags
+    ///Set or clear video bit of flags
     pub fn set_has_video(&mut self, has_video: bool) {
-        let flag = if has_video { 0b11111111 } else { 0b11111110 };
-        self.flags &= flag;
+        self.set_flag(Self::HEADER_VIDEO_FLAG, has_video);
     }
 
-    ///Set audio bit of flags
+    ///Set or clear audio bit of flags
     pub fn set_has_audio(&mut self, has_audio: bool) {
-        let flag = if has_audio { 0b11111111 } else { 0b11111011 };
-        self.flags &= flag;
+        self.set_flag(Self::HEADER_AUDIO_FLAG, has_audio);
+    }
+
+    fn set_flag(&mut self, flag: u8, value: bool) {
+        if value {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
     }
 
     ///Return the signature of flv.It should be "FLV"
     pub fn signature(&self) -> String {
-        let mut s = String::with_capacity(3);
-        s.insert(0, *self.signature.get(0).unwrap() as char);
-        s.insert(1, *self.signature.get(1).unwrap() as char);
-        s.insert(2, *self.signature.get(2).unwrap() as char);
-        return s;
+        self.signature.iter().map(|b| *b as char).collect()
     }
 
     ///Return the bytes of this header
@@ -93,6 +112,6 @@ impl Header {
         bytes.push(self.version);
         bytes.push(self.flags);
         bytes.extend_from_slice(&self.header_size.to_be_bytes());
-        return bytes;
+        bytes
     }
 }
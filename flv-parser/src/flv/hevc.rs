@@ -0,0 +1,282 @@
+use super::avc::{cropped_size, read_parameter_sets};
+use super::bits::{unescape_rbsp, BitReader};
+use std::io;
+
+pub const NALU_TYPE_IDR_W_RADL: u8 = 19;
+pub const NALU_TYPE_CRA: u8 = 21;
+pub const NALU_TYPE_VPS: u8 = 32;
+pub const NALU_TYPE_SPS: u8 = 33;
+pub const NALU_TYPE_PPS: u8 = 34;
+pub const NALU_TYPE_AUD: u8 = 35;
+
+///Return the nal unit type of a h.265 nal unit
+pub fn nalu_type(nal: &[u8]) -> u8 {
+    nal.first().map(|b| (b >> 1) & 0x3F).unwrap_or(0xFF)
+}
+
+///Check if the nal unit type is an IRAP picture(BLA,IDR or CRA)
+pub fn is_irap(nal_type: u8) -> bool {
+    (16..=23).contains(&nal_type)
+}
+
+///HEVCDecoderConfigurationRecord carried by the HEVC sequence header
+#[derive(Debug, Clone, PartialEq)]
+pub struct HevcDecoderConfig {
+    header: [u8; 22],
+    ///(nal unit type,nal units) of every array
+    arrays: Vec<(u8, Vec<Vec<u8>>)>,
+}
+
+impl HevcDecoderConfig {
+    ///Parse the record from the body of an HEVC sequence header
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Truncated hevc config");
+        if data.len() < 23 || data[0] != 1 {
+            return Err(invalid());
+        }
+        let mut header = [0_u8; 22];
+        header.copy_from_slice(&data[..22]);
+        let mut arrays = vec![];
+        let mut offset = 23;
+        for _ in 0..data[22] {
+            let nal_type = *data.get(offset).ok_or_else(invalid)? & 0x3F;
+            let count = data.get(offset + 1..offset + 3).ok_or_else(invalid)?;
+            let count = u16::from_be_bytes([count[0], count[1]]) as usize;
+            offset += 3;
+            arrays.push((nal_type, read_parameter_sets(data, &mut offset, count)?));
+        }
+        Ok(Self { header, arrays })
+    }
+
+    ///Return the bytes of this record
+    pub fn into_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.to_vec();
+        bytes.push(self.arrays.len() as u8);
+        for (nal_type, units) in &self.arrays {
+            bytes.push(0x80 | nal_type);
+            bytes.extend_from_slice(&(units.len() as u16).to_be_bytes());
+            for unit in units {
+                bytes.extend_from_slice(&(unit.len() as u16).to_be_bytes());
+                bytes.extend_from_slice(unit);
+            }
+        }
+        bytes
+    }
+
+    pub fn profile(&self) -> u8 {
+        self.header[1] & 0x1F
+    }
+
+    pub fn level(&self) -> u8 {
+        self.header[12]
+    }
+
+    ///The byte count of the length prefix of every nal unit
+    pub fn nalu_length_size(&self) -> usize {
+        (self.header[21] & 0x03) as usize + 1
+    }
+
+    ///Return all nal units of the given type
+    pub fn nal_units(&self, nal_type: u8) -> Vec<&[u8]> {
+        self.arrays
+            .iter()
+            .filter(|(tp, _)| *tp == nal_type)
+            .flat_map(|(_, units)| units.iter().map(|u| u.as_slice()))
+            .collect()
+    }
+
+    ///All parameter sets in the order they should be sent to a decoder
+    pub fn parameter_sets(&self) -> Vec<&[u8]> {
+        let mut units = self.nal_units(NALU_TYPE_VPS);
+        units.extend(self.nal_units(NALU_TYPE_SPS));
+        units.extend(self.nal_units(NALU_TYPE_PPS));
+        units
+    }
+
+    ///Decode the first sps of this record
+    pub fn parse_sps(&self) -> io::Result<HevcSps> {
+        let sps = self.nal_units(NALU_TYPE_SPS);
+        let sps = sps
+            .first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No sps in hevc config"))?;
+        HevcSps::from_nal(sps)
+    }
+}
+
+///The fields of a h.265 sequence parameter set flv-cli cares about
+#[derive(Debug, Clone, PartialEq)]
+pub struct HevcSps {
+    profile_idc: u8,
+    level_idc: u8,
+    chroma_format_idc: u32,
+    width: u32,
+    height: u32,
+}
+
+impl HevcSps {
+    ///Parse a sps nal unit,including the two bytes nal header
+    pub fn from_nal(nal: &[u8]) -> io::Result<Self> {
+        let rbsp = unescape_rbsp(nal);
+        if rbsp.len() < 16 || nalu_type(&rbsp) != NALU_TYPE_SPS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a hevc sps"));
+        }
+        let mut reader = BitReader::new(&rbsp[2..]);
+        reader.skip_bits(4)?;
+        let max_sub_layers_minus1 = reader.read_bits(3)? as usize;
+        reader.skip_bits(1)?;
+        reader.skip_bits(3)?;
+        let profile_idc = reader.read_bits(5)? as u8;
+        reader.skip_bits(32 + 48)?;
+        let level_idc = reader.read_bits(8)? as u8;
+        let mut sub_layers = vec![];
+        for _ in 0..max_sub_layers_minus1 {
+            sub_layers.push((reader.read_bit()?, reader.read_bit()?));
+        }
+        if max_sub_layers_minus1 > 0 {
+            reader.skip_bits(2 * (8 - max_sub_layers_minus1))?;
+        }
+        for (profile_present, level_present) in sub_layers {
+            if profile_present {
+                reader.skip_bits(88)?;
+            }
+            if level_present {
+                reader.skip_bits(8)?;
+            }
+        }
+        reader.read_ue()?;
+        let chroma_format_idc = reader.read_ue_up_to(3)?;
+        if chroma_format_idc == 3 {
+            reader.skip_bits(1)?;
+        }
+        let mut width = reader.read_ue()?;
+        let mut height = reader.read_ue()?;
+        if reader.read_bit()? {
+            let (sub_width, sub_height) = match chroma_format_idc {
+                1 => (2, 2),
+                2 => (2, 1),
+                _ => (1, 1),
+            };
+            let left = reader.read_ue()?;
+            let right = reader.read_ue()?;
+            let top = reader.read_ue()?;
+            let bottom = reader.read_ue()?;
+            width = cropped_size(width, 1, sub_width, left, right)?;
+            height = cropped_size(height, 1, sub_height, top, bottom)?;
+        }
+        Ok(Self {
+            profile_idc,
+            level_idc,
+            chroma_format_idc,
+            width,
+            height,
+        })
+    }
+
+    pub fn profile(&self) -> u8 {
+        self.profile_idc
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level_idc
+    }
+
+    pub fn chroma_format(&self) -> u32 {
+        self.chroma_format_idc
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flv::bits::tests::BitWriter;
+
+    ///Main profile sps with one sub layer
+    fn sps(chroma_format_idc: u32, width: u32, height: u32, crop: [u32; 4]) -> Vec<u8> {
+        let mut writer = BitWriter::default()
+            .bits(0, 4) //sps_video_parameter_set_id
+            .bits(0, 3) //sps_max_sub_layers_minus1
+            .bit(true)
+            .bits(0, 3) //general_profile_space,general_tier_flag
+            .bits(1, 5) //general_profile_idc
+            .bits(0x6000_0000, 32)
+            .bits(0, 48)
+            .bits(120, 8) //general_level_idc
+            .ue(0) //sps_seq_parameter_set_id
+            .ue(chroma_format_idc);
+        if chroma_format_idc == 3 {
+            writer = writer.bit(false);
+        }
+        writer = writer.ue(width).ue(height).bit(crop != [0; 4]);
+        if crop != [0; 4] {
+            for value in crop {
+                writer = writer.ue(value);
+            }
+        }
+        let mut nal = vec![NALU_TYPE_SPS << 1, 0x01];
+        nal.extend(writer.into_nal_payload());
+        nal
+    }
+
+    #[test]
+    fn parses_a_cropped_1080p_sps() {
+        let sps = HevcSps::from_nal(&sps(1, 1920, 1088, [0, 0, 0, 4])).unwrap();
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert_eq!((sps.profile(), sps.level()), (1, 120));
+        assert_eq!(sps.chroma_format(), 1);
+    }
+
+    #[test]
+    fn rejects_a_crop_that_overflows() {
+        let error = HevcSps::from_nal(&sps(1, 1920, 1088, [u32::MAX - 1, 0, 0, 0])).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_an_unknown_chroma_format() {
+        let error = HevcSps::from_nal(&sps(4, 1920, 1080, [0; 4])).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn decoder_config_round_trips() {
+        let sps = sps(1, 1920, 1088, [0, 0, 0, 4]);
+        let mut bytes = vec![
+            0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00,
+        ];
+        bytes.extend_from_slice(&[
+            0x00, 120, 0xF0, 0x00, 0xFC, 0xFD, 0xF8, 0xF8, 0x00, 0x00, 0x0F,
+        ]);
+        bytes.push(3);
+        for (nal_type, unit) in [
+            (NALU_TYPE_VPS, vec![NALU_TYPE_VPS << 1, 0x01, 0x0C]),
+            (NALU_TYPE_SPS, sps.clone()),
+            (NALU_TYPE_PPS, vec![NALU_TYPE_PPS << 1, 0x01, 0xC1]),
+        ] {
+            bytes.extend_from_slice(&[0x80 | nal_type, 0x00, 0x01]);
+            bytes.extend_from_slice(&(unit.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(&unit);
+        }
+        let config = HevcDecoderConfig::from_bytes(&bytes).unwrap();
+        assert_eq!(config.into_bytes(), bytes);
+        assert_eq!((config.profile(), config.level()), (1, 120));
+        assert_eq!(config.nalu_length_size(), 4);
+        assert_eq!(config.nal_units(NALU_TYPE_SPS), [sps.as_slice()]);
+        let types: Vec<u8> = config
+            .parameter_sets()
+            .iter()
+            .map(|u| nalu_type(u))
+            .collect();
+        assert_eq!(types, [NALU_TYPE_VPS, NALU_TYPE_SPS, NALU_TYPE_PPS]);
+        assert_eq!(config.parse_sps().unwrap().height(), 1080);
+        let error = HevcDecoderConfig::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
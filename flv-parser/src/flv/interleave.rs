@@ -0,0 +1,224 @@
+use super::Tag;
+use std::collections::BTreeMap;
+
+fn tag_type(tag: &Tag) -> u8 {
+    if tag.is_video_tag() {
+        Tag::TYPE_VIDEO
+    } else if tag.is_audio_tag() {
+        Tag::TYPE_AUDIO
+    } else {
+        Tag::TYPE_SCRIPT
+    }
+}
+
+///A tag pushed after a tag with a later timestamp was already released,
+///the window was too small to put it in order
+#[derive(Debug, Clone, Copy)]
+pub struct LateTag {
+    ///Tag::TYPE_VIDEO,Tag::TYPE_AUDIO or Tag::TYPE_SCRIPT
+    pub tag_type: u8,
+    pub timestamp: u32,
+    ///Latest timestamp released before it
+    pub released: u32,
+}
+
+///Reorder tags by timestamp within a window of milliseconds.
+///A tag is released once a tag window milliseconds later was pushed,so runs of one stream
+///up to the window are interleaved with the other.Tags of equal timestamps keep their order,
+///except that script tags and sequence headers go before frames of the other stream.
+///Within a stream a sequence header stays after the frames pushed before it,
+///they belong to the previous configuration.
+#[derive(Debug, Clone)]
+pub struct Interleaver {
+    window: u32,
+    ///Tags by timestamp,rank and order of pushing
+    pending: BTreeMap<(u32, u8, u64), Tag>,
+    pushed: u64,
+    newest: Option<u32>,
+    released: Option<u32>,
+    late: u64,
+}
+
+impl Interleaver {
+    pub fn new(window: u32) -> Self {
+        Self {
+            window,
+            pending: BTreeMap::new(),
+            pushed: 0,
+            newest: None,
+            released: None,
+            late: 0,
+        }
+    }
+
+    ///Count of tags pushed after later tags were released
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    ///Count of tags waiting to be released
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    ///Add a tag,returns how late it is if it can't be put in order any more.
+    ///A late tag is still released,right away.
+    pub fn push(&mut self, tag: Tag) -> Option<LateTag> {
+        let timestamp = tag.timestamp();
+        let late = match self.released {
+            Some(released) if timestamp < released => {
+                self.late += 1;
+                Some(LateTag {
+                    tag_type: tag_type(&tag),
+                    timestamp,
+                    released,
+                })
+            }
+            _ => None,
+        };
+        let same_stream_pending = self
+            .pending
+            .range((timestamp, 1, 0)..=(timestamp, 1, u64::MAX))
+            .any(|(_, pending)| tag_type(pending) == tag_type(&tag));
+        let rank = if tag.is_script_tag() || (tag.is_sequence_header() && !same_stream_pending) {
+            0
+        } else {
+            1
+        };
+        self.newest = Some(
+            self.newest
+                .map_or(timestamp, |newest| newest.max(timestamp)),
+        );
+        self.pending.insert((timestamp, rank, self.pushed), tag);
+        self.pushed += 1;
+        late
+    }
+
+    ///Next tag in order,None until the window has passed it
+    pub fn pop(&mut self) -> Option<Tag> {
+        let newest = self.newest?;
+        let (&(timestamp, ..), _) = self.pending.iter().next()?;
+        if (timestamp as u64) + (self.window as u64) > newest as u64 {
+            return None;
+        }
+        self.release()
+    }
+
+    ///Next tag in order regardless of the window,to drain the tags at the end of input
+    pub fn flush(&mut self) -> Option<Tag> {
+        self.release()
+    }
+
+    fn release(&mut self) -> Option<Tag> {
+        let key = *self.pending.keys().next()?;
+        let tag = self.pending.remove(&key)?;
+        self.released = Some(self.released.map_or(key.0, |released| released.max(key.0)));
+        Some(tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(timestamp: u32, packet_type: u8, id: u8) -> Tag {
+        Tag::video(
+            timestamp,
+            Tag::FRAME_TYPE_KEY,
+            Tag::VIDEO_CODEC_AVC,
+            packet_type,
+            0,
+            &[id],
+        )
+    }
+
+    fn audio(timestamp: u32, packet_type: u8, id: u8) -> Tag {
+        Tag::audio(
+            timestamp,
+            Tag::SOUND_FORMAT_AAC << 4 | 0x0F,
+            packet_type,
+            &[id],
+        )
+    }
+
+    fn drain(interleaver: &mut Interleaver) -> Vec<(u32, u8)> {
+        let mut tags = vec![];
+        while let Some(tag) = interleaver.flush() {
+            tags.push((tag.timestamp(), *tag.body().last().unwrap()));
+        }
+        tags
+    }
+
+    #[test]
+    fn sorts_runs_within_the_window() {
+        let mut interleaver = Interleaver::new(1000);
+        for (timestamp, id) in [(0, 1), (20, 2), (40, 3)] {
+            interleaver.push(audio(timestamp, 1, id));
+        }
+        for (timestamp, id) in [(0, 4), (33, 5)] {
+            interleaver.push(video(timestamp, 1, id));
+        }
+        assert!(interleaver.pop().is_none());
+        assert_eq!(
+            drain(&mut interleaver),
+            vec![(0, 1), (0, 4), (20, 2), (33, 5), (40, 3)]
+        );
+    }
+
+    #[test]
+    fn releases_tags_once_the_window_passed() {
+        let mut interleaver = Interleaver::new(100);
+        interleaver.push(video(0, 1, 1));
+        interleaver.push(video(50, 1, 2));
+        assert!(interleaver.pop().is_none());
+        interleaver.push(video(100, 1, 3));
+        assert_eq!(interleaver.pop().map(|t| t.timestamp()), Some(0));
+        assert!(interleaver.pop().is_none());
+        assert_eq!(interleaver.len(), 2);
+    }
+
+    #[test]
+    fn reports_a_tag_later_than_the_window() {
+        let mut interleaver = Interleaver::new(100);
+        for timestamp in [0, 100, 200, 300] {
+            assert!(interleaver.push(audio(timestamp, 1, 0)).is_none());
+            while interleaver.pop().is_some() {}
+        }
+        let late = interleaver.push(video(50, 1, 0)).unwrap();
+        assert_eq!(late.tag_type, Tag::TYPE_VIDEO);
+        assert_eq!(late.timestamp, 50);
+        assert_eq!(late.released, 200);
+        assert_eq!(interleaver.late(), 1);
+        //a late tag goes out next
+        assert_eq!(interleaver.pop().map(|t| t.timestamp()), Some(50));
+    }
+
+    #[test]
+    fn equal_timestamps_keep_their_order_with_headers_first() {
+        let mut interleaver = Interleaver::new(0);
+        interleaver.push(video(0, 1, 1));
+        interleaver.push(audio(0, 0, 2));
+        interleaver.push(audio(0, 1, 3));
+        interleaver.push(video(0, 0, 4));
+        interleaver.push(audio(0, 1, 5));
+        //the audio header goes before the video frame,the video header stays after the
+        //video frame pushed before it
+        assert_eq!(
+            drain(&mut interleaver),
+            vec![(0, 2), (0, 1), (0, 3), (0, 4), (0, 5)]
+        );
+    }
+
+    #[test]
+    fn sequence_header_goes_before_frames_of_the_other_stream() {
+        let mut interleaver = Interleaver::new(1000);
+        interleaver.push(audio(0, 1, 1));
+        interleaver.push(video(0, 0, 2));
+        interleaver.push(video(0, 1, 3));
+        assert_eq!(drain(&mut interleaver), vec![(0, 2), (0, 1), (0, 3)]);
+    }
+}
@@ -0,0 +1,114 @@
+use std::io;
+
+const BITRATES_V1: [[u32; 15]; 3] = [
+    [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+    [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+];
+const BITRATES_V2: [[u32; 15]; 2] = [
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+///Header of an MPEG audio(mp3) frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mp3FrameHeader {
+    ///1 for MPEG-1,2 for MPEG-2,25 for MPEG-2.5
+    version: u8,
+    layer: u8,
+    bitrate: u32,
+    sample_rate: u32,
+    channels: u8,
+    padding: bool,
+}
+
+impl Mp3FrameHeader {
+    pub const HEADER_SIZE: usize = 4;
+
+    ///Parse the four bytes frame header
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if data.len() < Self::HEADER_SIZE || data[0] != 0xFF || data[1] & 0xE0 != 0xE0 {
+            return Err(invalid("Missing mp3 frame sync"));
+        }
+        let version = match (data[1] >> 3) & 0x03 {
+            0 => 25,
+            2 => 2,
+            3 => 1,
+            _ => return Err(invalid("Reserved mpeg audio version")),
+        };
+        let layer = match (data[1] >> 1) & 0x03 {
+            1 => 3,
+            2 => 2,
+            3 => 1,
+            _ => return Err(invalid("Reserved mpeg audio layer")),
+        };
+        let bitrate_index = (data[2] >> 4) as usize;
+        if bitrate_index == 0 || bitrate_index == 15 {
+            return Err(invalid("Unsupported mp3 bitrate index"));
+        }
+        let bitrate = if version == 1 {
+            BITRATES_V1[layer as usize - 1][bitrate_index]
+        } else {
+            BITRATES_V2[if layer == 1 { 0 } else { 1 }][bitrate_index]
+        };
+        let rates = match version {
+            1 => [44100, 48000, 32000],
+            2 => [22050, 24000, 16000],
+            _ => [11025, 12000, 8000],
+        };
+        let sample_rate = *rates
+            .get(((data[2] >> 2) & 0x03) as usize)
+            .ok_or_else(|| invalid("Reserved mp3 sample rate"))?;
+        Ok(Self {
+            version,
+            layer,
+            bitrate,
+            sample_rate,
+            channels: if data[3] >> 6 == 3 { 1 } else { 2 },
+            padding: data[2] & 0x02 != 0,
+        })
+    }
+
+    ///1 for MPEG-1,2 for MPEG-2,25 for MPEG-2.5
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn layer(&self) -> u8 {
+        self.layer
+    }
+
+    ///Bitrate in kbps
+    pub fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    ///Count of pcm samples per channel in one frame
+    pub fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _) => 384,
+            (3, 2) | (3, 25) => 576,
+            _ => 1152,
+        }
+    }
+
+    ///Size of the whole frame in bytes,including the header
+    pub fn frame_size(&self) -> usize {
+        let padding = self.padding as u32;
+        let size = if self.layer == 1 {
+            (12 * self.bitrate * 1000 / self.sample_rate + padding) * 4
+        } else {
+            self.samples_per_frame() / 8 * self.bitrate * 1000 / self.sample_rate + padding
+        };
+        size as usize
+    }
+}
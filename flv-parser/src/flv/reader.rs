@@ -0,0 +1,144 @@
+use super::{ParseResult, Parser};
+use std::io::{self, Read};
+
+///Pull flv header,previous tag sizes and tags from something implements Read trait,
+///feeding a Parser under the hood.
+pub struct Reader<R: Read> {
+    inner: R,
+    parser: Parser,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            parser: Parser::new(),
+            buffer: vec![0x00; 100 * 1024],
+            eof: false,
+        }
+    }
+
+    ///Return the next parse result,None at the end of input.
+    ///It never returns ParseResult::MoreDataRequired.
+    pub fn read_next(&mut self) -> io::Result<Option<ParseResult>> {
+        loop {
+            match self.parser.parse()? {
+                ParseResult::MoreDataRequired(_) => {
+                    if self.eof {
+                        return Ok(None);
+                    }
+                    let count = match self.inner.read(&mut self.buffer) {
+                        Ok(count) => count,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    if count == 0 {
+                        self.eof = true;
+                    } else {
+                        self.parser.feed(&self.buffer[..count]);
+                    }
+                }
+                result => return Ok(Some(result)),
+            }
+        }
+    }
+
+    ///Count of bytes left unparsed at the end of input,eg. a truncated tag
+    pub fn pending(&self) -> usize {
+        self.parser.buffered()
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<ParseResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_next().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flv::{Header, Tag};
+
+    fn file(tags: &[Tag]) -> Vec<u8> {
+        let mut data = Header::new(true, false).into_bytes();
+        data.extend_from_slice(&0_u32.to_be_bytes());
+        for tag in tags {
+            data.extend_from_slice(&tag.into_bytes());
+            data.extend_from_slice(&tag.tag_size().to_be_bytes());
+        }
+        data
+    }
+
+    fn video(timestamp: u32) -> Tag {
+        Tag::video(
+            timestamp,
+            Tag::FRAME_TYPE_KEY,
+            Tag::VIDEO_CODEC_AVC,
+            Tag::PACKET_TYPE_NALU,
+            0,
+            &[0; 100],
+        )
+    }
+
+    #[test]
+    fn reads_header_sizes_and_tags() {
+        let data = file(&[video(0), video(40)]);
+        let mut reader = Reader::new(data.as_slice());
+        assert!(matches!(
+            reader.read_next().unwrap(),
+            Some(ParseResult::Header(_))
+        ));
+        let mut timestamps = vec![];
+        let mut sizes = vec![];
+        for result in reader.by_ref() {
+            match result.unwrap() {
+                ParseResult::Tag(tag) => timestamps.push(tag.timestamp()),
+                ParseResult::PreTagSize(size) => sizes.push(size),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(timestamps, [0, 40]);
+        assert_eq!(sizes, [0, 116, 116]);
+        assert_eq!(reader.pending(), 0);
+    }
+
+    #[test]
+    fn leaves_a_truncated_tag_pending() {
+        let mut data = file(&[video(0)]);
+        data.extend_from_slice(&video(40).into_bytes()[..50]);
+        let mut reader = Reader::new(data.as_slice());
+        let mut tags = 0;
+        while let Some(result) = reader.read_next().unwrap() {
+            if let ParseResult::Tag(_) = result {
+                tags += 1;
+            }
+        }
+        assert_eq!(tags, 1);
+        assert_eq!(reader.pending(), 50);
+        assert!(reader.read_next().unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_a_file_split_across_reads() {
+        //a reader returning one byte at a time
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let count = self.0.len().min(buf.len()).min(1);
+                buf[..count].copy_from_slice(&self.0[..count]);
+                self.0 = &self.0[count..];
+                Ok(count)
+            }
+        }
+        let data = file(&[video(0), video(40)]);
+        let tags = Reader::new(Trickle(&data))
+            .filter(|result| matches!(result, Ok(ParseResult::Tag(_))))
+            .count();
+        assert_eq!(tags, 2);
+    }
+}
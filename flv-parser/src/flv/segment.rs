@@ -23,7 +23,7 @@ impl Segment {
                 }
             },
         }
-        return Ok(Self { pre_tag_size, tag });
+        Ok(Self { pre_tag_size, tag })
     }
 
     pub fn tag(&self) -> &Option<Tag> {
@@ -35,45 +35,18 @@ impl Segment {
     }
 
     pub fn has_video_tag(&self) -> bool {
-        if self.tag.is_some() {
-            match self.tag.as_ref().unwrap().data() {
-                &TagData::Video(_) => {
-                    return true;
-                }
-                _ => {
-                    return false;
-                }
-            }
-        }
-        return false;
+        matches!(self.tag.as_ref().map(|t| t.data()), Some(TagData::Video(_)))
     }
 
     pub fn has_script_tag(&self) -> bool {
-        if self.tag.is_some() {
-            match self.tag.as_ref().unwrap().data() {
-                &TagData::Script(_) => {
-                    return true;
-                }
-                _ => {
-                    return false;
-                }
-            }
-        }
-        return false;
+        matches!(
+            self.tag.as_ref().map(|t| t.data()),
+            Some(TagData::Script(_))
+        )
     }
 
     pub fn has_audio_tag(&self) -> bool {
-        if self.tag.is_some() {
-            match self.tag.as_ref().unwrap().data() {
-                &TagData::Audio(_) => {
-                    return true;
-                }
-                _ => {
-                    return false;
-                }
-            }
-        }
-        return false;
+        matches!(self.tag.as_ref().map(|t| t.data()), Some(TagData::Audio(_)))
     }
 
     pub fn tag_mut(&mut self) -> &mut Option<Tag> {
@@ -89,14 +62,10 @@ impl Segment {
     }
 
     pub fn into_bytes(&self) -> Vec<u8> {
-        if self.has_tag() {
-            let mut result = self.tag.as_ref().unwrap().into_bytes();
-            let pre_tag_size_bytes = self.pre_tag_size.to_be_bytes();
-            for i in 0..pre_tag_size_bytes.len() {
-                result.insert(i, pre_tag_size_bytes[i]);
-            }
-            return result;
+        let mut result: Vec<u8> = self.pre_tag_size.to_be_bytes().into();
+        if let Some(tag) = &self.tag {
+            result.extend_from_slice(&tag.into_bytes());
         }
-        return self.pre_tag_size.to_be_bytes().into();
+        result
     }
 }
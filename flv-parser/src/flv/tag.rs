@@ -6,11 +6,9 @@ use std::{
 };
 
 pub(crate) fn be_bytes_to_u32(bytes: &[u8]) -> u32 {
-    let mut result = 0_u32;
-    for i in 0..bytes.len() {
-        result += (bytes[i] as u32) << ((bytes.len() - i - 1) * 8);
-    }
-    return result;
+    bytes
+        .iter()
+        .fold(0_u32, |result, byte| (result << 8) | *byte as u32)
 }
 
 fn decode_script_data(data: &[u8]) -> io::Result<Vec<amf::Amf0Value>> {
@@ -32,7 +30,15 @@ fn decode_script_data(data: &[u8]) -> io::Result<Vec<amf::Amf0Value>> {
             },
         }
     }
-    return Ok(metas);
+    Ok(metas)
+}
+
+fn encode_script_data(metas: &[amf::Amf0Value]) -> Vec<u8> {
+    let mut data = vec![];
+    for meta in metas {
+        meta.write_to(&mut data).unwrap();
+    }
+    data
 }
 
 type ScriptTagData = Vec<amf::Amf0Value>;
@@ -45,7 +51,12 @@ pub trait ScriptTagDataTrait {
     fn framerate(&self) -> f64;
     fn video_codec_id(&self) -> f64;
     fn audio_codec_id(&self) -> f64;
+    fn audio_sample_rate(&self) -> f64;
+    fn video_data_rate(&self) -> f64;
+    fn audio_data_rate(&self) -> f64;
+    fn stereo(&self) -> Option<bool>;
     fn read_f64(&self, key: &str) -> f64;
+    fn read_bool(&self, key: &str) -> Option<bool>;
 }
 
 impl ScriptTagDataTrait for ScriptTagData {
@@ -54,7 +65,7 @@ impl ScriptTagDataTrait for ScriptTagData {
         for val in self.iter_mut() {
             if let amf::Amf0Value::EcmaArray { entries } = val {
                 for kv in entries.iter_mut() {
-                    if &kv.key == "duration" {
+                    if kv.key == "duration" {
                         kv.value = amf::Amf0Value::Number(duration);
                         set = true;
                         break;
@@ -70,11 +81,10 @@ impl ScriptTagDataTrait for ScriptTagData {
             }
         }
         if !set {
-            let mut entries = vec![];
-            entries.push(amf::Pair {
+            let entries = vec![amf::Pair {
                 key: "duration".to_string(),
                 value: amf::Amf0Value::Number(duration),
-            });
+            }];
             self.push(amf::Amf0Value::EcmaArray { entries });
         }
     }
@@ -83,7 +93,7 @@ impl ScriptTagDataTrait for ScriptTagData {
         for val in self.iter() {
             if let amf::Amf0Value::EcmaArray { entries } = val {
                 for kv in entries.iter() {
-                    if &kv.key == key {
+                    if kv.key == key {
                         if let amf::Amf0Value::Number(result) = kv.value {
                             return result;
                         }
@@ -91,7 +101,22 @@ impl ScriptTagDataTrait for ScriptTagData {
                 }
             }
         }
-        return f64::INFINITY;
+        f64::INFINITY
+    }
+
+    fn read_bool(&self, key: &str) -> Option<bool> {
+        for val in self.iter() {
+            if let amf::Amf0Value::EcmaArray { entries } = val {
+                for kv in entries.iter() {
+                    if kv.key == key {
+                        if let amf::Amf0Value::Boolean(result) = kv.value {
+                            return Some(result);
+                        }
+                    }
+                }
+            }
+        }
+        None
     }
 
     fn duration(&self) -> f64 {
@@ -117,6 +142,22 @@ impl ScriptTagDataTrait for ScriptTagData {
     fn audio_codec_id(&self) -> f64 {
         self.read_f64("audiocodecid")
     }
+
+    fn audio_sample_rate(&self) -> f64 {
+        self.read_f64("audiosamplerate")
+    }
+
+    fn video_data_rate(&self) -> f64 {
+        self.read_f64("videodatarate")
+    }
+
+    fn audio_data_rate(&self) -> f64 {
+        self.read_f64("audiodatarate")
+    }
+
+    fn stereo(&self) -> Option<bool> {
+        self.read_bool("stereo")
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -137,11 +178,47 @@ pub struct Tag {
 }
 
 impl Tag {
-    const TYPE_AUDIO: u8 = 0x08;
-    const TYPE_VIDEO: u8 = 0x09;
-    const TYPE_SCRIPT: u8 = 0x12;
+    pub const TYPE_AUDIO: u8 = 0x08;
+    pub const TYPE_VIDEO: u8 = 0x09;
+    pub const TYPE_SCRIPT: u8 = 0x12;
     pub const TAG_HEADER_SIZE: u32 = 11;
 
+    pub const FRAME_TYPE_KEY: u8 = 1;
+    pub const FRAME_TYPE_INTER: u8 = 2;
+    pub const FRAME_TYPE_DISPOSABLE_INTER: u8 = 3;
+    pub const FRAME_TYPE_GENERATED_KEY: u8 = 4;
+    pub const FRAME_TYPE_COMMAND: u8 = 5;
+
+    pub const VIDEO_CODEC_JPEG: u8 = 1;
+    pub const VIDEO_CODEC_H263: u8 = 2;
+    pub const VIDEO_CODEC_SCREEN: u8 = 3;
+    pub const VIDEO_CODEC_VP6: u8 = 4;
+    pub const VIDEO_CODEC_VP6_ALPHA: u8 = 5;
+    pub const VIDEO_CODEC_SCREEN_V2: u8 = 6;
+    pub const VIDEO_CODEC_AVC: u8 = 7;
+    ///Not in the flv spec,but widely used for HEVC
+    pub const VIDEO_CODEC_HEVC: u8 = 12;
+
+    pub const SOUND_FORMAT_PCM_PLATFORM: u8 = 0;
+    pub const SOUND_FORMAT_ADPCM: u8 = 1;
+    pub const SOUND_FORMAT_MP3: u8 = 2;
+    pub const SOUND_FORMAT_PCM_LE: u8 = 3;
+    pub const SOUND_FORMAT_NELLYMOSER_16K: u8 = 4;
+    pub const SOUND_FORMAT_NELLYMOSER_8K: u8 = 5;
+    pub const SOUND_FORMAT_NELLYMOSER: u8 = 6;
+    pub const SOUND_FORMAT_G711_ALAW: u8 = 7;
+    pub const SOUND_FORMAT_G711_MULAW: u8 = 8;
+    pub const SOUND_FORMAT_AAC: u8 = 10;
+    pub const SOUND_FORMAT_SPEEX: u8 = 11;
+    pub const SOUND_FORMAT_MP3_8K: u8 = 14;
+
+    ///AVCPacketType/AACPacketType of a sequence header
+    pub const PACKET_TYPE_SEQUENCE_HEADER: u8 = 0;
+    ///AVCPacketType/AACPacketType of a coded frame
+    pub const PACKET_TYPE_NALU: u8 = 1;
+    ///AVCPacketType of the end of sequence
+    pub const PACKET_TYPE_END_OF_SEQUENCE: u8 = 2;
+
     pub fn from_reader(reader: &mut impl Read) -> io::Result<Self> {
         let tp: u8 = reader.read_u8()?;
         let mut data_size: [u8; 3] = [0; 3];
@@ -172,14 +249,66 @@ impl Tag {
             TagData::Script(decode_script_data(&data)?)
         };
 
-        return Ok(Self {
+        Ok(Self {
             tp,
             data_size,
             timestamp,
             timestamp_ex,
             stream_id,
             data: tag_data,
-        });
+        })
+    }
+
+    ///Build a tag of stream 0,the data size is computed from data
+    pub fn new(timestamp: u32, data: TagData) -> Self {
+        let (tp, size) = match &data {
+            TagData::Audio(bytes) => (Self::TYPE_AUDIO, bytes.len()),
+            TagData::Video(bytes) => (Self::TYPE_VIDEO, bytes.len()),
+            TagData::Script(metas) => (Self::TYPE_SCRIPT, encode_script_data(metas).len()),
+        };
+        let size = (size as u32).to_be_bytes();
+        let mut tag = Self {
+            tp,
+            data_size: [size[1], size[2], size[3]],
+            timestamp: [0; 3],
+            timestamp_ex: 0,
+            stream_id: [0; 3],
+            data,
+        };
+        tag.set_timestamp(timestamp);
+        tag
+    }
+
+    ///Build a video tag from the flv video tag header fields and the codec payload.
+    ///The packet type and composition time are written for AVC and HEVC only.
+    pub fn video(
+        timestamp: u32,
+        frame_type: u8,
+        codec_id: u8,
+        packet_type: u8,
+        composition_time: i32,
+        body: &[u8],
+    ) -> Self {
+        let mut data = Vec::with_capacity(body.len() + 5);
+        data.push((frame_type << 4) | (codec_id & 0x0F));
+        if codec_id == Self::VIDEO_CODEC_AVC || codec_id == Self::VIDEO_CODEC_HEVC {
+            data.push(packet_type);
+            data.extend_from_slice(&composition_time.to_be_bytes()[1..]);
+        }
+        data.extend_from_slice(body);
+        Self::new(timestamp, TagData::Video(data))
+    }
+
+    ///Build an audio tag from the flags byte and the codec payload.
+    ///The packet type is written for AAC only.
+    pub fn audio(timestamp: u32, flags: u8, packet_type: u8, body: &[u8]) -> Self {
+        let mut data = Vec::with_capacity(body.len() + 2);
+        data.push(flags);
+        if flags >> 4 == Self::SOUND_FORMAT_AAC {
+            data.push(packet_type);
+        }
+        data.extend_from_slice(body);
+        Self::new(timestamp, TagData::Audio(data))
     }
 
     pub fn into_bytes(&self) -> Vec<u8> {
@@ -191,15 +320,13 @@ impl Tag {
         result.extend_from_slice(&self.stream_id);
         match &self.data {
             TagData::Audio(data) | TagData::Video(data) => {
-                result.extend_from_slice(&data);
+                result.extend_from_slice(data);
             }
             TagData::Script(metas) => {
-                for meta in metas {
-                    meta.write_to(&mut result).unwrap();
-                }
+                result.extend_from_slice(&encode_script_data(metas));
             }
         }
-        return result;
+        result
     }
 
     pub fn is_video_tag(&self) -> bool {
@@ -233,4 +360,140 @@ impl Tag {
     pub fn data(&self) -> &TagData {
         &self.data
     }
+
+    ///The size of tag data,excluding the tag header
+    pub fn data_size(&self) -> u32 {
+        be_bytes_to_u32(&self.data_size)
+    }
+
+    ///The raw bytes of an audio or video tag
+    fn media_data(&self) -> Option<&[u8]> {
+        match &self.data {
+            TagData::Audio(data) | TagData::Video(data) => Some(data),
+            TagData::Script(_) => None,
+        }
+    }
+
+    ///FrameType of a video tag
+    pub fn frame_type(&self) -> Option<u8> {
+        if !self.is_video_tag() {
+            return None;
+        }
+        self.media_data()?.first().map(|b| b >> 4)
+    }
+
+    ///CodecID of a video tag
+    pub fn video_codec_id(&self) -> Option<u8> {
+        if !self.is_video_tag() {
+            return None;
+        }
+        self.media_data()?.first().map(|b| b & 0x0F)
+    }
+
+    ///Indicates that this is a video key frame
+    pub fn is_keyframe(&self) -> bool {
+        self.frame_type() == Some(Self::FRAME_TYPE_KEY)
+    }
+
+    ///SoundFormat of an audio tag
+    pub fn sound_format(&self) -> Option<u8> {
+        if !self.is_audio_tag() {
+            return None;
+        }
+        self.media_data()?.first().map(|b| b >> 4)
+    }
+
+    ///SoundRate of an audio tag in Hz
+    pub fn sound_rate(&self) -> Option<u32> {
+        if !self.is_audio_tag() {
+            return None;
+        }
+        let flags = *self.media_data()?.first()?;
+        Some(match flags >> 4 {
+            Self::SOUND_FORMAT_NELLYMOSER_16K | Self::SOUND_FORMAT_SPEEX => 16000,
+            Self::SOUND_FORMAT_NELLYMOSER_8K
+            | Self::SOUND_FORMAT_G711_ALAW
+            | Self::SOUND_FORMAT_G711_MULAW
+            | Self::SOUND_FORMAT_MP3_8K => 8000,
+            _ => [5512, 11025, 22050, 44100][((flags >> 2) & 0x03) as usize],
+        })
+    }
+
+    ///SoundSize of an audio tag in bits
+    pub fn sound_size(&self) -> Option<u8> {
+        if !self.is_audio_tag() {
+            return None;
+        }
+        self.media_data()?
+            .first()
+            .map(|b| if b & 0x02 == 0 { 8 } else { 16 })
+    }
+
+    ///Count of channels declared by SoundType of an audio tag
+    pub fn sound_channels(&self) -> Option<u8> {
+        if !self.is_audio_tag() {
+            return None;
+        }
+        self.media_data()?.first().map(|b| (b & 0x01) + 1)
+    }
+
+    ///Return true if the codec of this tag has a packet type byte(AVC,HEVC and AAC)
+    fn has_packet_type(&self) -> bool {
+        matches!(
+            self.video_codec_id(),
+            Some(Self::VIDEO_CODEC_AVC) | Some(Self::VIDEO_CODEC_HEVC)
+        ) || self.sound_format() == Some(Self::SOUND_FORMAT_AAC)
+    }
+
+    ///AVCPacketType of an AVC/HEVC tag or AACPacketType of an AAC tag
+    pub fn packet_type(&self) -> Option<u8> {
+        if !self.has_packet_type() {
+            return None;
+        }
+        self.media_data()?.get(1).copied()
+    }
+
+    ///Indicates that this is an AVC/HEVC/AAC sequence header
+    pub fn is_sequence_header(&self) -> bool {
+        self.packet_type() == Some(Self::PACKET_TYPE_SEQUENCE_HEADER)
+    }
+
+    ///CompositionTime of an AVC/HEVC tag in milliseconds,0 for other tags
+    pub fn composition_time(&self) -> i32 {
+        if self.is_video_tag() && self.has_packet_type() {
+            if let Some(bytes) = self.media_data().and_then(|d| d.get(2..5)) {
+                return ((be_bytes_to_u32(bytes) << 8) as i32) >> 8;
+            }
+        }
+        0
+    }
+
+    ///Set the CompositionTime of an AVC/HEVC tag,other tags don't have one
+    pub fn set_composition_time(&mut self, composition_time: i32) {
+        if !self.is_video_tag() || !self.has_packet_type() {
+            return;
+        }
+        if let TagData::Video(data) = &mut self.data {
+            if let Some(bytes) = data.get_mut(2..5) {
+                bytes.copy_from_slice(&composition_time.to_be_bytes()[1..]);
+            }
+        }
+    }
+
+    ///The codec payload of an audio or video tag,without the flv audio/video tag header
+    pub fn body(&self) -> &[u8] {
+        let data = self.media_data().unwrap_or(&[]);
+        let header_size = if self.is_video_tag() {
+            if self.has_packet_type() {
+                5
+            } else {
+                1
+            }
+        } else if self.has_packet_type() {
+            2
+        } else {
+            1
+        };
+        data.get(header_size..).unwrap_or(&[])
+    }
 }
@@ -0,0 +1,285 @@
+use super::Tag;
+
+///Thresholds of a TimestampFilter in milliseconds
+#[derive(Debug, Clone, Copy)]
+pub struct TimestampFilterOptions {
+    ///Steps back of a stream up to this are clamped,longer ones are discontinuities
+    pub max_backward: u32,
+    ///Steps forward of a stream longer than this are discontinuities
+    pub max_gap: u32,
+}
+
+impl Default for TimestampFilterOptions {
+    fn default() -> Self {
+        Self {
+            max_backward: 1000,
+            max_gap: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscontinuityKind {
+    ///Timestamps went back,eg. an encoder restarted at 0
+    Backward,
+    ///Timestamps jumped forward
+    Gap,
+}
+
+///A discontinuity found and bridged by a TimestampFilter
+#[derive(Debug, Clone, Copy)]
+pub struct Discontinuity {
+    pub kind: DiscontinuityKind,
+    ///Tag::TYPE_VIDEO or Tag::TYPE_AUDIO
+    pub tag_type: u8,
+    ///Timestamp of the tag,negative if the extended byte has the sign bit
+    pub found: i64,
+    ///Previous timestamp of the stream in the input,None for its first tag
+    pub previous: Option<i64>,
+    ///Timestamp written instead
+    pub timestamp: u32,
+}
+
+#[derive(Debug, Default, Clone)]
+struct StreamClock {
+    ///Added to the timestamps found
+    offset: i64,
+    ///Last timestamp written
+    last: Option<i64>,
+    duration_sum: i64,
+    duration_count: i64,
+}
+
+impl StreamClock {
+    ///Average frame or sample duration,at least 1ms
+    fn duration(&self) -> i64 {
+        if self.duration_count > 0 {
+            (self.duration_sum / self.duration_count).max(1)
+        } else {
+            1
+        }
+    }
+}
+
+///Make the timestamps of every stream continuous.
+///Each stream keeps its own offset.At a discontinuity a stream takes the offset of the other
+///stream if that makes it continuous,so both stay in sync across an encoder restart,
+///otherwise it continues one average frame duration after the latest timestamp of all streams.
+#[derive(Debug, Clone)]
+pub struct TimestampFilter {
+    options: TimestampFilterOptions,
+    ///Video and audio
+    streams: [StreamClock; 2],
+    ///Stream whose offset applies to script tags
+    current: usize,
+    clamped: u64,
+}
+
+impl TimestampFilter {
+    pub fn new(options: TimestampFilterOptions) -> Self {
+        Self {
+            options,
+            streams: [StreamClock::default(), StreamClock::default()],
+            current: 0,
+            clamped: 0,
+        }
+    }
+
+    ///Count of timestamps stepping back less than max_backward,which were clamped
+    pub fn clamped(&self) -> u64 {
+        self.clamped
+    }
+
+    ///Rewrite the timestamp of tag,returns the discontinuity it starts
+    pub fn push(&mut self, tag: &mut Tag) -> Option<Discontinuity> {
+        let found = tag.timestamp() as i32 as i64;
+        let index = if tag.is_video_tag() {
+            0
+        } else if tag.is_audio_tag() {
+            1
+        } else {
+            let timestamp = found + self.streams[self.current].offset;
+            tag.set_timestamp(timestamp.max(0) as u32);
+            return None;
+        };
+        self.current = index;
+        let latest = self.streams.iter().filter_map(|s| s.last).max();
+        let other_offset = self.streams[1 - index].offset;
+        let max_backward = self.options.max_backward as i64;
+        let max_gap = self.options.max_gap as i64;
+        let stream = &mut self.streams[index];
+        let mut timestamp = found + stream.offset;
+        let previous = stream.last.map(|last| last - stream.offset);
+
+        //sequence headers are often sent again with old timestamps,they don't start anything
+        if tag.is_sequence_header() || tag.packet_type() == Some(Tag::PACKET_TYPE_END_OF_SEQUENCE) {
+            if let Some(last) = stream.last.or(latest) {
+                if timestamp < last || timestamp > last + max_gap {
+                    timestamp = last;
+                }
+            }
+            tag.set_timestamp(timestamp.max(0) as u32);
+            return None;
+        }
+
+        let kind = match (stream.last, latest) {
+            (Some(last), _) if timestamp < last - max_backward => Some(DiscontinuityKind::Backward),
+            (Some(last), _) if timestamp > last + max_gap => Some(DiscontinuityKind::Gap),
+            (None, Some(latest)) if timestamp < latest - max_backward => {
+                Some(DiscontinuityKind::Backward)
+            }
+            (None, None) if timestamp < 0 => Some(DiscontinuityKind::Backward),
+            _ => None,
+        };
+        let discontinuity = match kind {
+            Some(kind) => {
+                let shared = found + other_offset;
+                let target = match stream.last {
+                    Some(last) if shared >= last && shared <= last + max_gap => shared,
+                    _ => latest.map_or(0, |latest| latest + stream.duration()),
+                };
+                stream.offset += target - timestamp;
+                timestamp = target;
+                Some(Discontinuity {
+                    kind,
+                    tag_type: if index == 0 {
+                        Tag::TYPE_VIDEO
+                    } else {
+                        Tag::TYPE_AUDIO
+                    },
+                    found,
+                    previous,
+                    timestamp: timestamp as u32,
+                })
+            }
+            None => {
+                match stream.last {
+                    Some(last) if timestamp < last => {
+                        self.clamped += 1;
+                        timestamp = last;
+                    }
+                    Some(last) if timestamp > last => {
+                        stream.duration_sum += timestamp - last;
+                        stream.duration_count += 1;
+                    }
+                    _ => {}
+                }
+                None
+            }
+        };
+        let timestamp = timestamp.max(0);
+        stream.last = Some(timestamp);
+        tag.set_timestamp(timestamp as u32);
+        discontinuity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(timestamp: u32) -> Tag {
+        Tag::video(
+            timestamp,
+            Tag::FRAME_TYPE_INTER,
+            Tag::VIDEO_CODEC_AVC,
+            Tag::PACKET_TYPE_NALU,
+            0,
+            &[0],
+        )
+    }
+
+    fn audio(timestamp: u32) -> Tag {
+        Tag::audio(timestamp, Tag::SOUND_FORMAT_AAC << 4 | 0x0F, 1, &[0])
+    }
+
+    ///Push a tag that continues its stream,returning its new timestamp
+    fn push(filter: &mut TimestampFilter, mut tag: Tag) -> u32 {
+        assert!(filter.push(&mut tag).is_none());
+        tag.timestamp()
+    }
+
+    ///Push a tag that starts a discontinuity
+    fn push_discontinuity(filter: &mut TimestampFilter, mut tag: Tag) -> (u32, Discontinuity) {
+        let discontinuity = filter.push(&mut tag).expect("a discontinuity");
+        (tag.timestamp(), discontinuity)
+    }
+
+    #[test]
+    fn continues_after_a_restart_at_0() {
+        let mut filter = TimestampFilter::new(TimestampFilterOptions::default());
+        for timestamp in (0..=2000).step_by(40) {
+            assert_eq!(push(&mut filter, video(timestamp)), timestamp);
+        }
+        let (timestamp, discontinuity) = push_discontinuity(&mut filter, video(0));
+        assert_eq!(discontinuity.kind, DiscontinuityKind::Backward);
+        assert_eq!(discontinuity.tag_type, Tag::TYPE_VIDEO);
+        assert_eq!(discontinuity.found, 0);
+        assert_eq!(discontinuity.previous, Some(2000));
+        //one average frame duration after the last frame
+        assert_eq!(timestamp, 2040);
+        assert_eq!(push(&mut filter, video(40)), 2080);
+    }
+
+    #[test]
+    fn bridges_a_forward_gap() {
+        let mut filter = TimestampFilter::new(TimestampFilterOptions::default());
+        for timestamp in [0, 40, 80] {
+            push(&mut filter, video(timestamp));
+        }
+        let (timestamp, discontinuity) = push_discontinuity(&mut filter, video(3_600_000));
+        assert_eq!(discontinuity.kind, DiscontinuityKind::Gap);
+        assert_eq!(timestamp, 120);
+        assert_eq!(push(&mut filter, video(3_600_040)), 160);
+    }
+
+    #[test]
+    fn clamps_a_small_step_back() {
+        let mut filter = TimestampFilter::new(TimestampFilterOptions::default());
+        for timestamp in [0, 40, 80] {
+            push(&mut filter, video(timestamp));
+        }
+        assert_eq!(push(&mut filter, video(60)), 80);
+        assert_eq!(filter.clamped(), 1);
+        assert_eq!(push(&mut filter, video(120)), 120);
+    }
+
+    #[test]
+    fn audio_follows_the_offset_of_video() {
+        let mut filter = TimestampFilter::new(TimestampFilterOptions::default());
+        for timestamp in (0..=2000).step_by(20) {
+            if timestamp % 40 == 0 {
+                push(&mut filter, video(timestamp));
+            }
+            push(&mut filter, audio(timestamp));
+        }
+        //the encoder restarts,video first
+        let (video_timestamp, _) = push_discontinuity(&mut filter, video(0));
+        assert_eq!(video_timestamp, 2040);
+        let (audio_timestamp, discontinuity) = push_discontinuity(&mut filter, audio(0));
+        assert_eq!(discontinuity.tag_type, Tag::TYPE_AUDIO);
+        //the shared offset keeps both streams in sync
+        assert_eq!(audio_timestamp, video_timestamp);
+        assert_eq!(push(&mut filter, audio(20)), 2060);
+        assert_eq!(push(&mut filter, video(40)), 2080);
+    }
+
+    #[test]
+    fn sequence_headers_never_start_a_discontinuity() {
+        let mut filter = TimestampFilter::new(TimestampFilterOptions::default());
+        for timestamp in (0..=2000).step_by(40) {
+            push(&mut filter, video(timestamp));
+        }
+        //far enough back to be a discontinuity for a frame
+        let header = Tag::video(
+            0,
+            Tag::FRAME_TYPE_KEY,
+            Tag::VIDEO_CODEC_AVC,
+            Tag::PACKET_TYPE_SEQUENCE_HEADER,
+            0,
+            &[0],
+        );
+        assert_eq!(push(&mut filter, header), 2000);
+        assert_eq!(push(&mut filter, video(2040)), 2040);
+    }
+}
@@ -0,0 +1,42 @@
+use super::{Header, Tag};
+use std::io::{self, Write};
+
+///Write flv header,tags and previous tag sizes to something implements Write trait
+pub struct Writer<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    ///Write the header followed by the first previous tag size,which is always 0
+    pub fn write_header(&mut self, header: &Header) -> io::Result<()> {
+        self.inner.write_all(&header.into_bytes())?;
+        self.inner.write_all(&0_u32.to_be_bytes())
+    }
+
+    ///Write a tag followed by its size
+    pub fn write_tag(&mut self, tag: &Tag) -> io::Result<()> {
+        let bytes = tag.into_bytes();
+        self.inner.write_all(&bytes)?;
+        self.inner.write_all(&(bytes.len() as u32).to_be_bytes())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}